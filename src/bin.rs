@@ -8,6 +8,14 @@ use std::env;
 use std::io::Write;
 use time::get_time;
 
+use sparrow::calibrate_model;
+use sparrow::export_model;
+use sparrow::inspect_model;
+use sparrow::merge_models;
+use sparrow::rescale_model;
+use sparrow::serve_model;
+use sparrow::slice_model;
+use sparrow::truncate_model;
 use sparrow::testing;
 use sparrow::training;
 
@@ -26,9 +34,41 @@ fn main() {
         .init();
 
     let args: Vec<String> = env::args().collect();
-    let usage_info = "Usage: ./sparrow [train|test] <config_file_path>";
-    if args.len() != 3 {
+    let usage_info = "Usage: ./sparrow [train|test] <config_file_path>\n       \
+                      ./sparrow inspect <model_file_path> [--json]\n       \
+                      ./sparrow export <model_file_path> <output_file_path> <format>\n       \
+                      ./sparrow truncate <model_file_path> <num_trees> <output_file_path>\n       \
+                      ./sparrow slice <model_file_path> <start> <end> <output_file_path>\n       \
+                      ./sparrow merge <model_file_path> <model_file_path> <output_file_path> \
+                      [--force]\n       \
+                      ./sparrow rescale <model_file_path> <factor> <output_file_path>\n       \
+                      ./sparrow calibrate <config_file_path> <model_file_path> [platt|isotonic] \
+                      <output_file_path>\n       \
+                      ./sparrow serve <model_file_path|model_dir> <port>";
+    if args.len() == 4 && args[1] == "inspect" && args[3] == "--json" {
+        inspect_model(&args[2], true);
+    } else if args.len() == 4 && args[1] == "serve" {
+        serve_model(&args[2], args[3].parse().expect("Cannot parse the port"));
+    } else if args.len() == 5 && args[1] == "export" {
+        export_model(&args[2], &args[3], &args[4]);
+    } else if args.len() == 5 && args[1] == "truncate" {
+        truncate_model(&args[2], args[3].parse().expect("Cannot parse the number of trees"),
+                       &args[4]);
+    } else if args.len() == 6 && args[1] == "slice" {
+        slice_model(&args[2], args[3].parse().expect("Cannot parse the start of the range"),
+                    args[4].parse().expect("Cannot parse the end of the range"), &args[5]);
+    } else if args.len() == 5 && args[1] == "merge" {
+        merge_models(&args[2], &args[3], &args[4], false);
+    } else if args.len() == 6 && args[1] == "merge" && args[5] == "--force" {
+        merge_models(&args[2], &args[3], &args[4], true);
+    } else if args.len() == 5 && args[1] == "rescale" {
+        rescale_model(&args[2], args[3].parse().expect("Cannot parse the factor"), &args[4]);
+    } else if args.len() == 6 && args[1] == "calibrate" {
+        calibrate_model(&args[2], &args[3], &args[4], &args[5]);
+    } else if args.len() != 3 {
         println!("{}", usage_info);
+    } else if args[1] == "inspect" {
+        inspect_model(&args[2], false);
     } else if args[1] == "train" {
         training(&args[2]);
     } else if args[1] == "test" {
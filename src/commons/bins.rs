@@ -77,6 +77,24 @@ impl Bins {
         }
         left as TFeature
     }
+
+    /// Return the split index `j` such that the split `x < vals[j + 1]` on the bins is the closest
+    /// to the split `x < threshold` on the raw feature values, i.e. `vals[j + 1]` is the bin
+    /// boundary that is the closest to `threshold`. The last index is returned if `threshold` is
+    /// larger than all bin boundaries, in which case all examples go to the left child.
+    pub fn get_threshold_index(&self, threshold: f32) -> TFeature {
+        match (1..self.size).find(|k| self.vals[*k] >= threshold) {
+            None    => (self.size.max(1) - 1) as TFeature,
+            Some(1) => 0,
+            Some(k) => {
+                if threshold - self.vals[k - 1] < self.vals[k] - threshold {
+                    (k - 2) as TFeature
+                } else {
+                    (k - 1) as TFeature
+                }
+            },
+        }
+    }
 }
 
 impl DistinctValues {
@@ -0,0 +1,328 @@
+use commons::model_format::BinaryReader;
+use commons::model_format::BinaryWriter;
+
+// The maximum number of the Newton iterations for fitting the Platt scaling
+const PLATT_MAX_ITERATIONS: usize = 100;
+const PLATT_MIN_STEP_SIZE: f64 = 1e-10;
+const PLATT_SIGMA: f64 = 1e-12;
+const PLATT_EPSILON: f64 = 1e-5;
+
+// The tags of the calibrators in the binary format
+const TAG_PLATT: u8 = 1;
+const TAG_ISOTONIC: u8 = 2;
+
+
+/// The methods for calibrating the scores of a model into probabilities
+pub enum CalibrationMethod {
+    Platt,
+    Isotonic,
+}
+
+impl CalibrationMethod {
+    pub fn new(method: &String) -> CalibrationMethod {
+        match method.to_lowercase().as_str() {
+            "platt"    => CalibrationMethod::Platt,
+            "isotonic" => CalibrationMethod::Isotonic,
+            _          => {
+                error!("Unrecognized calibration method. Use platt by default.");
+                CalibrationMethod::Platt
+            }
+        }
+    }
+}
+
+
+/// A mapping from the scores of a model to the probabilities of the examples being positive,
+/// fitted on the scores of a held-out data set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Calibrator {
+    /// `P(y = 1) = 1 / (1 + exp(a * score + b))`
+    Platt { a: f32, b: f32 },
+    /// a non-decreasing piecewise linear function through the points `(scores[i], probs[i])`,
+    /// which is constant outside of the range of `scores`
+    Isotonic { scores: Vec<f32>, probs: Vec<f32> },
+}
+
+
+impl Calibrator {
+    /// Fit a calibrator on the scores of the examples, where the labels are positive for the
+    /// positive examples
+    pub fn fit(method: &CalibrationMethod, scores: &[f32], labels: &[f32]) -> Calibrator {
+        assert!(!scores.is_empty(), "Cannot calibrate the model without any examples");
+        match method {
+            CalibrationMethod::Platt    => fit_platt(scores, labels),
+            CalibrationMethod::Isotonic => fit_isotonic(scores, labels),
+        }
+    }
+
+    pub fn get_probability(&self, score: f32) -> f32 {
+        match self {
+            Calibrator::Platt { a, b } => 1.0 / (1.0 + (a * score + b).exp()),
+            Calibrator::Isotonic { ref scores, ref probs } => {
+                let index = scores.iter().position(|t| score <= *t);
+                match index {
+                    None    => *probs.last().unwrap(),
+                    Some(0) => probs[0],
+                    Some(k) => {
+                        let ratio = (score - scores[k - 1]) / (scores[k] - scores[k - 1]);
+                        probs[k - 1] + ratio * (probs[k] - probs[k - 1])
+                    },
+                }
+            },
+        }
+    }
+
+    pub fn write_binary(&self, writer: &mut BinaryWriter) {
+        match self {
+            Calibrator::Platt { a, b } => {
+                writer.u8(TAG_PLATT);
+                writer.f32(*a);
+                writer.f32(*b);
+            },
+            Calibrator::Isotonic { ref scores, ref probs } => {
+                writer.u8(TAG_ISOTONIC);
+                writer.u64(scores.len() as u64);
+                scores.iter().zip(probs.iter()).for_each(|(score, prob)| {
+                    writer.f32(*score);
+                    writer.f32(*prob);
+                });
+            },
+        }
+    }
+
+    /// Read the calibrator written by `write_binary` after its tag, returns `None` if the data
+    /// is corrupted
+    pub fn read_binary(tag: u8, reader: &mut BinaryReader) -> Option<Calibrator> {
+        match tag {
+            TAG_PLATT    => Some(Calibrator::Platt { a: reader.f32()?, b: reader.f32()? }),
+            TAG_ISOTONIC => {
+                // each point takes 8 bytes, which bounds the allocations by the size of the data
+                let len = reader.u64()? as usize;
+                if len == 0 || len > reader.remaining() / 8 {
+                    return None;
+                }
+                let mut scores = Vec::with_capacity(len);
+                let mut probs = Vec::with_capacity(len);
+                for _ in 0..len {
+                    scores.push(reader.f32()?);
+                    probs.push(reader.f32()?);
+                }
+                // the probabilities are interpolated between the sorted scores
+                if scores.windows(2).any(|t| !(t[0] <= t[1])) {
+                    return None;
+                }
+                Some(Calibrator::Isotonic { scores: scores, probs: probs })
+            },
+            _            => None,
+        }
+    }
+}
+
+
+// Platt scaling with the Newton's method and the smoothed targets, following
+// Lin, Lin, and Weng, "A note on Platt's probabilistic outputs for support vector machines"
+fn fit_platt(scores: &[f32], labels: &[f32]) -> Calibrator {
+    let num_positive = labels.iter().filter(|t| **t > 0.0).count() as f64;
+    let num_negative = labels.len() as f64 - num_positive;
+    let high_target = (num_positive + 1.0) / (num_positive + 2.0);
+    let low_target = 1.0 / (num_negative + 2.0);
+    let data: Vec<(f64, f64)> = scores.iter().zip(labels.iter()).map(|(score, label)| {
+        (*score as f64, if *label > 0.0 { high_target } else { low_target })
+    }).collect();
+    // the negative log-likelihood
+    let get_loss = |a: f64, b: f64| -> f64 {
+        data.iter().map(|(score, target)| {
+            let t = a * score + b;
+            if t >= 0.0 {
+                target * t + (-t).exp().ln_1p()
+            } else {
+                (target - 1.0) * t + t.exp().ln_1p()
+            }
+        }).sum()
+    };
+
+    let mut a = 0.0;
+    let mut b = ((num_negative + 1.0) / (num_positive + 1.0)).ln();
+    let mut loss = get_loss(a, b);
+    for _ in 0..PLATT_MAX_ITERATIONS {
+        // the gradient and the Hessian
+        let (mut h11, mut h22, mut h21, mut g1, mut g2) = (PLATT_SIGMA, PLATT_SIGMA, 0.0, 0.0, 0.0);
+        data.iter().for_each(|(score, target)| {
+            let p = 1.0 / (1.0 + (a * score + b).exp());
+            let d2 = p * (1.0 - p);
+            h11 += score * score * d2;
+            h22 += d2;
+            h21 += score * d2;
+            let d1 = target - p;
+            g1 += score * d1;
+            g2 += d1;
+        });
+        if g1.abs() < PLATT_EPSILON && g2.abs() < PLATT_EPSILON {
+            break;
+        }
+        let det = h11 * h22 - h21 * h21;
+        let da = -(h22 * g1 - h21 * g2) / det;
+        let db = -(-h21 * g1 + h11 * g2) / det;
+        let gd = g1 * da + g2 * db;
+        // line search
+        let mut step_size = 1.0;
+        while step_size >= PLATT_MIN_STEP_SIZE {
+            let (new_a, new_b) = (a + step_size * da, b + step_size * db);
+            let new_loss = get_loss(new_a, new_b);
+            if new_loss < loss + 0.0001 * step_size * gd {
+                a = new_a;
+                b = new_b;
+                loss = new_loss;
+                break;
+            }
+            step_size /= 2.0;
+        }
+        if step_size < PLATT_MIN_STEP_SIZE {
+            warn!("The line search of the Platt scaling fails");
+            break;
+        }
+    }
+    Calibrator::Platt { a: a as f32, b: b as f32 }
+}
+
+
+// Isotonic regression with the pool adjacent violators algorithm
+fn fit_isotonic(scores: &[f32], labels: &[f32]) -> Calibrator {
+    let mut sorted: Vec<(f32, f32)> = scores.iter().zip(labels.iter()).map(|(score, label)| {
+        (*score, if *label > 0.0 { 1.0 } else { 0.0 })
+    }).collect();
+    sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    // (sum of the scores, sum of the targets, count) of the examples with the same score
+    let mut points: Vec<(f64, f64, usize)> = vec![];
+    let mut last_score = None;
+    sorted.iter().for_each(|(score, target)| {
+        if last_score == Some(*score) {
+            let point = points.last_mut().unwrap();
+            point.0 += *score as f64;
+            point.1 += *target as f64;
+            point.2 += 1;
+        } else {
+            points.push((*score as f64, *target as f64, 1));
+        }
+        last_score = Some(*score);
+    });
+    // pool the adjacent blocks that violate the monotonicity
+    let mut blocks: Vec<(f64, f64, usize)> = vec![];
+    points.into_iter().for_each(|point| {
+        blocks.push(point);
+        while blocks.len() >= 2 {
+            let (s1, t1, c1) = blocks[blocks.len() - 2];
+            let (s2, t2, c2) = blocks[blocks.len() - 1];
+            if t1 / c1 as f64 >= t2 / c2 as f64 {
+                blocks.pop();
+                *blocks.last_mut().unwrap() = (s1 + s2, t1 + t2, c1 + c2);
+            } else {
+                break;
+            }
+        }
+    });
+    Calibrator::Isotonic {
+        scores: blocks.iter().map(|(s, _, c)| (s / *c as f64) as f32).collect(),
+        probs:  blocks.iter().map(|(_, t, c)| (t / *c as f64) as f32).collect(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand::StdRng;
+
+    use commons::model_format::BinaryReader;
+    use commons::model_format::BinaryWriter;
+    use super::CalibrationMethod;
+    use super::Calibrator;
+    use super::TAG_ISOTONIC;
+
+    // the examples whose probabilities of being positive are `1 / (1 + exp(-4 * score + 1))`
+    fn get_examples() -> (Vec<f32>, Vec<f32>) {
+        let mut rng = StdRng::from_seed(&[0]);
+        (0..20000).map(|_| {
+            let score: f32 = rng.gen::<f32>() * 2.0 - 1.0;
+            let prob = 1.0 / (1.0 + (-4.0 * score + 1.0).exp());
+            (score, if rng.gen::<f32>() < prob { 1.0 } else { -1.0 })
+        }).unzip()
+    }
+
+    #[test]
+    fn test_platt() {
+        let (scores, labels) = get_examples();
+        let calibrator = Calibrator::fit(&CalibrationMethod::Platt, &scores, &labels);
+        match calibrator {
+            Calibrator::Platt { a, b } => {
+                assert!((a - (-4.0)).abs() < 0.3);
+                assert!((b - 1.0).abs() < 0.1);
+            },
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_isotonic() {
+        let (scores, labels) = get_examples();
+        let calibrator = Calibrator::fit(&CalibrationMethod::Isotonic, &scores, &labels);
+        let mut last_prob = 0.0;
+        for k in 0..=20 {
+            let score = -1.0 + 0.1 * k as f32;
+            let prob = calibrator.get_probability(score);
+            assert!(prob >= last_prob);
+            last_prob = prob;
+            let expected = 1.0 / (1.0 + (-4.0 * score + 1.0).exp());
+            assert!((prob - expected).abs() < 0.1);
+        }
+        assert_eq!(calibrator.get_probability(-10.0), calibrator.get_probability(-1.0));
+        assert_eq!(calibrator.get_probability(10.0), calibrator.get_probability(1.0));
+
+        // ties are grouped before pooling the violators
+        let calibrator = Calibrator::fit(
+            &CalibrationMethod::Isotonic, &[-1.0, -1.0, -1.0, -1.0, 0.0, 0.0],
+            &[-1.0, -1.0, -1.0, 1.0, -1.0, 1.0]);
+        assert_eq!(calibrator, Calibrator::Isotonic {
+            scores: vec![-1.0, 0.0],
+            probs:  vec![0.25, 0.5],
+        });
+        let calibrator = Calibrator::fit(
+            &CalibrationMethod::Isotonic, &[0.0, 0.0, 1.0, 2.0, 3.0], &[1.0, -1.0, 1.0, -1.0, 1.0]);
+        assert_eq!(calibrator, Calibrator::Isotonic {
+            scores: vec![0.75, 3.0],
+            probs:  vec![0.5, 1.0],
+        });
+        assert_eq!(calibrator.get_probability(0.0), 0.5);
+        assert_eq!(calibrator.get_probability(1.875), 0.75);
+    }
+
+    #[test]
+    fn test_binary_calibrator() {
+        let calibrators = vec![
+            Calibrator::Platt { a: -2.0, b: 0.5 },
+            Calibrator::Isotonic { scores: vec![-1.0, 0.0, 2.0], probs: vec![0.1, 0.5, 0.75] },
+        ];
+        calibrators.into_iter().for_each(|calibrator| {
+            let mut writer = BinaryWriter::new();
+            calibrator.write_binary(&mut writer);
+            let mut reader = BinaryReader::new(&writer.buf[1..]);
+            assert_eq!(Calibrator::read_binary(writer.buf[0], &mut reader), Some(calibrator));
+        });
+
+        let read_isotonic = |scores: Vec<f32>| {
+            let mut writer = BinaryWriter::new();
+            Calibrator::Isotonic { probs: vec![0.5; scores.len()], scores: scores }
+                .write_binary(&mut writer);
+            Calibrator::read_binary(writer.buf[0], &mut BinaryReader::new(&writer.buf[1..]))
+        };
+        assert!(read_isotonic(vec![-1.0, 0.0, 0.0, 2.0]).is_some());
+        // the scores are not sorted
+        assert!(read_isotonic(vec![-1.0, 2.0, 0.0]).is_none());
+        assert!(read_isotonic(vec![-1.0, ::std::f32::NAN]).is_none());
+        // the number of points is larger than the data
+        let mut data = vec![0xff; 8];
+        data.extend_from_slice(&[0; 16]);
+        assert!(Calibrator::read_binary(TAG_ISOTONIC, &mut BinaryReader::new(&data)).is_none());
+    }
+}
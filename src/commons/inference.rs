@@ -0,0 +1,252 @@
+use rayon::prelude::*;
+
+use TFeature;
+use Example;
+use commons::ScoredExample;
+use commons::model::Model;
+
+
+// The number of examples scored by one task
+const EXAMPLES_BLOCK_SIZE: usize = 256;
+// The number of trees evaluated on the whole block of examples before moving on to
+// the next trees, so that the nodes of the trees stay in the cache
+const TREES_BLOCK_SIZE: usize = 64;
+const LEAF: u32 = ::std::u32::MAX;
+
+
+#[derive(Clone, Copy)]
+struct FlatNode {
+    // `LEAF` if the node is a leaf
+    feature:   u32,
+    threshold: TFeature,
+    // the index of the left child in the node array, and the right child is next to it
+    left:      u32,
+    predict:   f32,
+}
+
+
+/// An inference engine that lays out the nodes of all trees in one contiguous array, and
+/// scores the examples in blocks in parallel.
+///
+/// It only contains the trees of the model starting from `version`, i.e. the scores are
+/// the sums of the predictions of the trees appended since `version`.
+///
+/// The children of each node must be consecutive and come after the node, which holds for
+/// the trees grown by `Tree::split`. It is checked when the engine is created.
+pub struct FlatModel {
+    nodes: Vec<FlatNode>,
+    roots: Vec<usize>,
+    version: usize,
+}
+
+impl FlatModel {
+    /// Create the engine for the trees appended since `version`, returns an error if the layout
+    /// of a tree is not supported
+    pub fn new(model: &Model, version: usize) -> Result<FlatModel, String> {
+        let trees = &model.get_trees()[version..];
+        let mut nodes = Vec::with_capacity(trees.iter().map(|tree| tree.num_nodes).sum());
+        let mut roots = Vec::with_capacity(trees.len());
+        for (tree_index, tree) in trees.iter().enumerate() {
+            let root = nodes.len();
+            roots.push(root);
+            for index in 0..tree.num_nodes {
+                nodes.push(match tree.get_split(index) {
+                    Some((feature, threshold, left, right)) => {
+                        if left <= index || left + 1 != right || right >= tree.num_nodes {
+                            return Err(format!(
+                                "The children of the node {} of the tree {} are not supported \
+                                 by the flat inference engine", index, version + tree_index));
+                        }
+                        FlatNode {
+                            feature:   feature as u32,
+                            threshold: threshold,
+                            left:      (root + left) as u32,
+                            predict:   tree.get_predict(index),
+                        }
+                    },
+                    None => FlatNode {
+                        feature:   LEAF,
+                        threshold: 0,
+                        left:      0,
+                        predict:   tree.get_predict(index),
+                    },
+                });
+            }
+        }
+        Ok(FlatModel {
+            nodes: nodes,
+            roots: roots,
+            version: version,
+        })
+    }
+
+    /// The size of the model, i.e. the version of the scores after the update
+    pub fn size(&self) -> usize {
+        self.version + self.roots.len()
+    }
+
+    /// Return the sums of the predictions of the trees on the examples
+    pub fn predict(&self, data: &[Example]) -> Vec<f32> {
+        let mut scores = vec![0.0; data.len()];
+        data.par_chunks(EXAMPLES_BLOCK_SIZE)
+            .zip(scores.par_chunks_mut(EXAMPLES_BLOCK_SIZE))
+            .for_each(|(examples, scores)| {
+                let examples: Vec<&Example> = examples.iter().collect();
+                let versions = vec![self.version; examples.len()];
+                self.add_predictions(&examples, &versions, scores);
+            });
+        scores
+    }
+
+    /// Update the scores of the examples, whose versions must not be smaller than the version
+    /// of the engine
+    pub fn update_scores<T: ScoredExample + Send>(&self, data: &mut [T]) {
+        data.par_chunks_mut(EXAMPLES_BLOCK_SIZE).for_each(|block| {
+            let (mut scores, versions): (Vec<f32>, Vec<usize>) = block.iter().map(|example| {
+                example.get_score()
+            }).unzip();
+            {
+                let examples: Vec<&Example> =
+                    block.iter().map(|example| example.get_example()).collect();
+                self.add_predictions(&examples, &versions, &mut scores);
+            }
+            block.iter_mut().zip(scores.into_iter()).for_each(|(example, score)| {
+                example.set_score(score, self.size());
+            });
+        });
+    }
+
+    // Add the predictions of the trees since `versions` to `scores`
+    fn add_predictions(&self, examples: &[&Example], versions: &[usize], scores: &mut [f32]) {
+        let mut start = 0;
+        while start < self.roots.len() {
+            let end = (start + TREES_BLOCK_SIZE).min(self.roots.len());
+            examples.iter().zip(versions.iter()).zip(scores.iter_mut())
+                    .for_each(|((example, version), score)| {
+                        debug_assert!(*version >= self.version);
+                        let first = start.max(*version - self.version);
+                        let feature = &example.feature;
+                        for root in &self.roots[first.min(end)..end] {
+                            let mut node = &self.nodes[*root];
+                            while node.feature != LEAF {
+                                // branchless selection of the child
+                                let is_right = feature[node.feature as usize] > node.threshold;
+                                node = &self.nodes[node.left as usize + is_right as usize];
+                            }
+                            *score += node.predict;
+                        }
+                    });
+            start = end;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand::StdRng;
+    use serde_json;
+
+    use std::time::Instant;
+
+    use Example;
+    use commons::ExampleWithScore;
+    use commons::ScoredExample;
+    use commons::model::Model;
+    use commons::test_helper::get_n_random_examples;
+    use commons::tree::Tree;
+    use super::FlatModel;
+
+    fn get_random_model(num_trees: usize, num_splits: usize, num_features: usize) -> Model {
+        let mut rng = StdRng::from_seed(&[0]);
+        let mut model = Model::new();
+        (0..num_trees).for_each(|_| {
+            let mut tree = Tree::new(num_splits);
+            (0..num_splits).for_each(|_| {
+                let leaves: Vec<usize> = (0..tree.num_nodes).filter(|k| tree.is_leaf(*k)).collect();
+                tree.split(leaves[rng.gen_range(0, leaves.len())],
+                           rng.gen_range(0, num_features), rng.gen(),
+                           rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5);
+            });
+            model.append(tree);
+        });
+        model
+    }
+
+    #[test]
+    fn test_predict_batch() {
+        let model = get_random_model(150, 5, 10);
+        let examples: Vec<Example> =
+            get_n_random_examples(1000, 10).into_iter().map(|t| t.0).collect();
+        let scores = model.predict_batch(&examples);
+        examples.iter().zip(scores.iter()).for_each(|(example, score)| {
+            assert!((model.get_prediction(example, 0).0 - score).abs() < 1e-4);
+        });
+        let scores = FlatModel::new(&model, 100).unwrap().predict(&examples);
+        examples.iter().zip(scores.iter()).for_each(|(example, score)| {
+            assert!((model.get_prediction(example, 100).0 - score).abs() < 1e-4);
+        });
+    }
+
+    #[test]
+    fn test_unsupported_tree() {
+        let mut tree = Tree::new(1);
+        tree.split(0, 1, 1, -0.5, 0.5);
+        // swap the children of the root, which still gives a valid tree
+        let mut json: serde_json::Value = serde_json::to_value(&tree).unwrap();
+        json["left_child"][0] = serde_json::Value::from(2);
+        json["right_child"][0] = serde_json::Value::from(1);
+        json["predicts"][1] = serde_json::Value::from(0.5);
+        json["predicts"][2] = serde_json::Value::from(-0.5);
+        let mut model = Model::new();
+        model.append(serde_json::from_value(json).unwrap());
+        assert!(FlatModel::new(&model, 0).is_err());
+        // falls back to evaluating the trees one by one
+        let examples: Vec<Example> =
+            get_n_random_examples(100, 10).into_iter().map(|t| t.0).collect();
+        let scores = model.predict_batch(&examples);
+        examples.iter().zip(scores.iter()).for_each(|(example, score)| {
+            assert_eq!(model.get_prediction(example, 0).0, *score);
+        });
+    }
+
+    #[test]
+    fn test_update_scores() {
+        let model = get_random_model(150, 5, 10);
+        let mut examples = get_n_random_examples(1000, 10);
+        let mut rng = StdRng::from_seed(&[1]);
+        examples.iter_mut().for_each(|example| {
+            let version = rng.gen_range(0, model.size() + 1);
+            example.1 = (model.get_prediction(&example.0, 0).0 -
+                         model.get_prediction(&example.0, version).0, version);
+        });
+        let mut expected: Vec<ExampleWithScore> = examples.clone();
+        expected.iter_mut().for_each(|example| model.update_score(example));
+        model.update_scores(&mut examples);
+        examples.iter().zip(expected.iter()).for_each(|(a, b)| {
+            assert_eq!(a.get_score().1, model.size());
+            assert!((a.get_score().0 - b.get_score().0).abs() < 1e-4);
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_predict_batch() {
+        let model = get_random_model(1000, 10, 100);
+        let examples: Vec<Example> =
+            get_n_random_examples(100000, 100).into_iter().map(|t| t.0).collect();
+        let timer = Instant::now();
+        let _: Vec<f32> = {
+            use rayon::prelude::*;
+            examples.par_iter().map(|example| model.get_prediction(example, 0).0).collect()
+        };
+        let get_prediction_duration = timer.elapsed();
+        let timer = Instant::now();
+        let _ = model.predict_batch(&examples);
+        let predict_batch_duration = timer.elapsed();
+        println!("get_prediction: {:?}, predict_batch: {:?}",
+                 get_prediction_duration, predict_batch_duration);
+    }
+}
@@ -108,35 +108,53 @@ where
     TFeature::Err: Debug,
     TLabel: FromStr + Send + Sync,
     TLabel::Err: Debug
+{
+    try_parse_libsvm_one_line(raw_string, missing_val, size, positive).unwrap_or_else(|err| {
+        panic!("Cannot parse the example `{}`, {}", raw_string.trim(), err)
+    })
+}
+
+/// Parse an example in the libsvm format, returns `Ok(None)` if the line is empty, or an error
+/// if the line is malformed. The label is optional (the example is negative if it is missing),
+/// and the value `nan` is parsed as 0.
+pub fn try_parse_libsvm_one_line<TFeature, TLabel>(
+    raw_string: &str,
+    missing_val: TFeature,
+    size: usize,
+    positive: &String,
+) -> ::std::result::Result<Option<LabeledData<TFeature, TLabel>>, String>
+where
+    TFeature: FromStr + Clone,
+    TLabel: FromStr,
 {
     if raw_string.trim().is_empty() {
-        return None;
+        return Ok(None);
     }
-    let mut numbers = raw_string.split_whitespace();
-    let label: TLabel = {
-        if numbers.next().unwrap() == *positive {
-            "1".parse().unwrap()
-        } else {
-            "-1".parse().unwrap()
-        }
+    let mut tokens = raw_string.split_whitespace().peekable();
+    let is_positive = match tokens.peek() {
+        Some(token) if !token.contains(':') => tokens.next() == Some(positive.as_str()),
+        _                                   => false,
     };
+    let label: TLabel = (if is_positive { "1" } else { "-1" }).parse()
+        .map_err(|_| "Cannot parse the label".to_string())?;
     let mut feature: Vec<TFeature> = vec![missing_val; size];
-    numbers.map(|index_value| {
-        let sep = index_value.find(':').unwrap();
-        (
-            index_value[..sep].parse().unwrap(),
-            {
-                if index_value[sep+1..].to_lowercase() == "nan" {
-                    "0".parse().unwrap()  // TODO: comes up with a better placeholder for NAN
-                } else {
-                    index_value[sep+1..].parse().unwrap()
-                }
+    for index_value in tokens {
+        let malformed = || format!("Cannot parse the feature `{}`", index_value);
+        let sep = index_value.find(':').ok_or_else(malformed)?;
+        let index: usize = index_value[..sep].parse().map_err(|_| malformed())?;
+        let value = {
+            if index_value[sep+1..].to_lowercase() == "nan" {
+                "0"  // TODO: comes up with a better placeholder for NAN
+            } else {
+                &index_value[sep+1..]
             }
-        )
-    }).for_each(|(index, value): (usize, TFeature)| {
-        feature[index] = value;
-    });
-    Some(LabeledData::new(feature, label))
+        };
+        if index >= size {
+            return Err(format!("The feature index {} is out of range", index));
+        }
+        feature[index] = value.parse().map_err(|_| malformed())?;
+    }
+    Ok(Some(LabeledData::new(feature, label)))
 }
 
 
@@ -247,6 +265,22 @@ mod tests {
         assert_eq!(parse_libsvm_one_line(&raw_string, 0, 6, &"1".to_string()), Some(labeled_data));
     }
 
+    #[test]
+    fn test_try_parse_libsvm_one_line() {
+        let positive = "1".to_string();
+        let parse = |raw_string: &str| {
+            try_parse_libsvm_one_line::<f32, f32>(raw_string, 0.0, 4, &positive)
+        };
+        assert_eq!(parse("1 1:2.5 3:NaN"),
+                   Ok(Some(LabeledData::new(vec![0.0, 2.5, 0.0, 0.0], 1.0))));
+        // the label is optional
+        assert_eq!(parse("2:1e3"), Ok(Some(LabeledData::new(vec![0.0, 0.0, 1000.0, 0.0], -1.0))));
+        assert_eq!(parse("  "), Ok(None));
+        assert!(parse("1 4:1.0").is_err());
+        assert!(parse("1 1:x").is_err());
+        assert!(parse("1 1:1.0 2").is_err());
+    }
+
     #[test]
     fn test_parse_libsvm() {
         let raw_strings = vec![
@@ -1,20 +1,28 @@
 pub mod bins;
+pub mod calibration;
 pub mod channel;
+// The inference engine for scoring the examples in batches
+pub mod inference;
 pub mod io;
 pub mod model;
+// Compact binary format of the models
+pub mod model_format;
 // The class of the training examples.
 pub mod labeled_data;
 pub mod packet;
 pub mod performance_monitor;
 // Functions to read/write samples, models, and worker assignments
 pub mod persistent_io;
+// The bounds for deciding if a weak rule should be accepted.
+pub mod stopping_rule;
 // The class of the weak learner, namely a decision stump.
 pub mod tree;
 // helper function for testing
 #[cfg(test)] pub mod test_helper;
 
+use rand::SeedableRng;
+use rand::StdRng;
 use rayon::prelude::*;
-use std::f32::INFINITY;
 
 use super::Example;
 
@@ -22,9 +30,53 @@ use super::Example;
 pub type ExampleInSampleSet = (Example, (f32, f32, usize, usize));  // weight, score, new_ver, base_ver
 pub type ExampleWithScore = (Example, (f32, usize));
 
-const DELTA: f32  = 0.000001;
-const SHRINK: f32 = 1.0;
-const THRESHOLD_FACTOR: f32 = 1.0;
+/// An example with a score, which sums the predictions of the first `version` trees of a model.
+/// The score is updated by `Model::update_score`.
+pub trait ScoredExample {
+    fn get_example(&self) -> &Example;
+    /// Return the score and the number of trees that the score accounts for
+    fn get_score(&self) -> (f32, usize);
+    fn set_score(&mut self, score: f32, version: usize);
+}
+
+impl ScoredExample for ExampleWithScore {
+    fn get_example(&self) -> &Example {
+        &self.0
+    }
+
+    fn get_score(&self) -> (f32, usize) {
+        self.1
+    }
+
+    fn set_score(&mut self, score: f32, version: usize) {
+        self.1 = (score, version);
+    }
+}
+
+// The weight of the example is updated along with its score
+impl ScoredExample for ExampleInSampleSet {
+    fn get_example(&self) -> &Example {
+        &self.0
+    }
+
+    fn get_score(&self) -> (f32, usize) {
+        ((self.1).1, (self.1).2)
+    }
+
+    fn set_score(&mut self, score: f32, version: usize) {
+        let base_version = (self.1).3;
+        self.1 = (get_weight(&self.0, score), score, version, base_version);
+    }
+}
+
+// Identifiers of the components that draw random numbers, used as the first key for deriving
+// their random number generators from the seed
+pub const RNG_FEATURE_SAMPLER: usize = 0;
+pub const RNG_ROOT_NODE:       usize = 1;
+pub const RNG_SAMPLER:         usize = 2;
+pub const RNG_GATHERER:        usize = 3;
+pub const RNG_KDTREE:          usize = 4;
+
 const ALMOST_ZERO: f32 = 1e-8;
 
 // Boosting related
@@ -44,24 +96,11 @@ pub fn get_weights(data: &Vec<Example>, scores: &[f32]) -> Vec<f32> {
         .collect()
 }
 
-#[inline]
-pub fn get_bound(sum_c: f32, sum_c_squared: f32) -> f32 {
-    let threshold: f32 = THRESHOLD_FACTOR * 173.0 * (4.0 / DELTA).ln();
-    if sum_c_squared >= threshold {
-        let log_log_term = 3.0 * sum_c_squared / 2.0 / sum_c.abs();
-        let log_log = {
-            if log_log_term > 2.7183 {
-                log_log_term.ln().ln()
-            } else {
-                0.0
-            }
-        };
-        SHRINK * (
-            3.0 * sum_c_squared * (2.0 * log_log + (2.0 / DELTA).ln())
-        ).sqrt()
-    } else {
-        INFINITY
-    }
+/// Create a random number generator that is fully determined by `seed` and `keys`
+pub fn get_rng(seed: u64, keys: &[usize]) -> StdRng {
+    let mut full_seed = vec![seed as usize];
+    full_seed.extend_from_slice(keys);
+    StdRng::from_seed(&full_seed[..])
 }
 
 /// Set initial weights to the samples
@@ -107,3 +146,32 @@ pub fn get_sign(a: f64) -> i8 {
         0
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use super::get_rng;
+
+    fn get_stream(seed: u64, keys: &[usize]) -> Vec<u32> {
+        let mut rng = get_rng(seed, keys);
+        (0..4).map(|_| rng.gen::<u32>()).collect()
+    }
+
+    #[test]
+    fn test_rng_streams() {
+        // the streams do not change across runs
+        assert_eq!(get_stream(7, &[1, 2]), vec![3925903224, 3539403419, 1588643451, 2234566954]);
+        assert_eq!(get_stream(7, &[1, 2]), get_stream(7, &[1, 2]));
+        // each seed and each key has its own stream
+        let streams = vec![
+            get_stream(7, &[1, 2]), get_stream(8, &[1, 2]), get_stream(7, &[2, 2]),
+            get_stream(7, &[1, 3]), get_stream(7, &[1]), get_stream(7, &[2, 1]),
+        ];
+        for i in 0..streams.len() {
+            for j in (i + 1)..streams.len() {
+                assert!(streams[i] != streams[j]);
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
 use rayon::prelude::*;
+use commons::ScoredExample;
+use commons::calibration::Calibrator;
+use commons::inference::FlatModel;
 use commons::tree::Tree;
 use Example;
 
@@ -7,6 +10,15 @@ use Example;
 pub struct Model {
     models: Vec<Tree>,
     pub base_size: usize,
+    /// The policy used for growing the trees
+    #[serde(default)]
+    pub growth_policy: String,
+    /// The number of trees of the best model on the validation set, if early stopping is used
+    #[serde(default)]
+    pub best_iteration: Option<usize>,
+    /// The mapping from the scores to the probabilities, if the model is calibrated
+    #[serde(default)]
+    pub calibrator: Option<Calibrator>,
 }
 
 
@@ -15,6 +27,9 @@ impl Model {
         Model {
             models: vec![],
             base_size: 0,
+            growth_policy: String::new(),
+            best_iteration: None,
+            calibrator: None,
         }
     }
 
@@ -30,10 +45,138 @@ impl Model {
         (pred, (self.size(), version))
     }
 
+    /// Return the score of `data` after adding the predictions of the trees appended since
+    /// `version` to `score`, along with the new version (i.e. the size of the model)
+    pub fn get_updated_score(&self, data: &Example, score: f32, version: usize) -> (f32, usize) {
+        let (inc_score, (model_size, _)) = self.get_prediction(data, version);
+        (score + inc_score, model_size)
+    }
+
+    /// Update the score of `example` by only applying the trees appended since the version of
+    /// its current score
+    pub fn update_score<T: ScoredExample>(&self, example: &mut T) {
+        let (score, version) = example.get_score();
+        let (new_score, new_version) = self.get_updated_score(example.get_example(), score, version);
+        example.set_score(new_score, new_version);
+    }
+
+    /// Return the scores of the examples, which are evaluated in batches by the flat inference
+    /// engine. It is much faster than calling `get_prediction` on each example.
+    pub fn predict_batch(&self, data: &[Example]) -> Vec<f32> {
+        self.predict_batch_since(data, 0)
+    }
+
+    /// Return the sums of the predictions of the trees appended since `version` on
+    /// the examples, see `predict_batch`
+    pub fn predict_batch_since(&self, data: &[Example], version: usize) -> Vec<f32> {
+        match FlatModel::new(self, version) {
+            Ok(flat_model) => flat_model.predict(data),
+            Err(err)       => {
+                warn!("{}, the trees are evaluated one by one", err);
+                self.get_predictions(data, version)
+            },
+        }
+    }
+
+    /// Return the sums of the predictions of the trees appended since `version` on
+    /// the examples, by calling `get_prediction` on each example
+    pub fn get_predictions(&self, data: &[Example], version: usize) -> Vec<f32> {
+        data.par_iter().map(|example| self.get_prediction(example, version).0).collect()
+    }
+
+    /// Update the scores of the examples in batches, see `update_score`
+    pub fn update_scores<T: ScoredExample + Send>(&self, data: &mut [T]) {
+        let version = data.iter().map(|example| example.get_score().1).min();
+        if let Some(version) = version {
+            match FlatModel::new(self, version.min(self.size())) {
+                Ok(flat_model) => flat_model.update_scores(data),
+                Err(err)       => {
+                    warn!("{}, the trees are evaluated one by one", err);
+                    data.par_iter_mut().for_each(|example| self.update_score(example));
+                },
+            }
+        }
+    }
+
+    /// Return the calibrated probability of `score` being positive, or `None` if the model
+    /// is not calibrated
+    pub fn get_probability(&self, score: f32) -> Option<f32> {
+        self.calibrator.as_ref().map(|calibrator| calibrator.get_probability(score))
+    }
+
+    /// Return the contribution of each feature to the score of `data`, with the bias of
+    /// the model as the last element. The contributions and the bias sum to the score.
+    pub fn explain(&self, data: &Example) -> Vec<f32> {
+        let num_features = data.feature.len();
+        let mut contributions = vec![0.0; num_features + 1];
+        self.models.iter().for_each(|tree| {
+            let bias = tree.add_contributions(data, &mut contributions[..num_features]);
+            contributions[num_features] += bias;
+        });
+        contributions
+    }
+
+    pub fn get_trees(&self) -> &[Tree] {
+        &self.models
+    }
+
     pub fn append(&mut self, update_tree: Tree) {
+        self.clear_calibrator();
         self.models.push(update_tree);
     }
 
+    /// Keep only the first `size` trees
+    pub fn truncate(&mut self, size: usize) {
+        if size < self.size() {
+            self.clear_calibrator();
+        }
+        self.models.truncate(size);
+        self.base_size = self.base_size.min(size);
+        if self.best_iteration.map(|t| t > size).unwrap_or(false) {
+            self.best_iteration = None;
+        }
+    }
+
+    /// Return a new model that consists of the trees in the range `[start, end)`, in which
+    /// the trees of the base model are the ones that were in the base model of this model
+    pub fn slice(&self, start: usize, end: usize) -> Model {
+        assert!(start <= end && end <= self.size(),
+                "The range [{}, {}) is out of the range of the model with {} trees",
+                start, end, self.size());
+        Model {
+            models: self.models[start..end].to_vec(),
+            base_size: self.base_size.max(start).min(end) - start,
+            growth_policy: self.growth_policy.clone(),
+            best_iteration: None,
+            calibrator: None,
+        }
+    }
+
+    /// Append the trees of `other` to this model. Both models must be trained on the same bins.
+    pub fn merge(&mut self, other: &Model) {
+        if self.growth_policy != other.growth_policy {
+            warn!("Merging the models grown by different policies, {} and {}",
+                  self.growth_policy, other.growth_policy);
+        }
+        self.models.extend(other.models.iter().cloned());
+        self.best_iteration = None;
+        self.clear_calibrator();
+    }
+
+    /// Multiply the predictions of all trees by `factor`
+    pub fn rescale(&mut self, factor: f32) {
+        self.models.iter_mut().for_each(|tree| tree.rescale(factor));
+        self.clear_calibrator();
+    }
+
+    // The calibrator does not apply to the scores of the edited model
+    fn clear_calibrator(&mut self) {
+        if self.calibrator.is_some() {
+            warn!("The calibration of the model is removed since its scores are changed");
+            self.calibrator = None;
+        }
+    }
+
     pub fn get_last_new_tree(&self) -> Option<Tree> {
         if self.size() <= self.base_size {
             None
@@ -42,7 +185,171 @@ impl Model {
         }
     }
 
+    pub fn set_growth_policy(&mut self, growth_policy: &String) {
+        if !self.growth_policy.is_empty() && self.growth_policy != *growth_policy {
+            warn!("The growth policy of the model is changed from {} to {}",
+                  self.growth_policy, growth_policy);
+        }
+        self.growth_policy = growth_policy.clone();
+    }
+
     pub fn set_base_size(&mut self) {
         self.base_size = self.size();
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand::StdRng;
+
+    use commons::ExampleInSampleSet;
+    use commons::ExampleWithScore;
+    use commons::ScoredExample;
+    use commons::calibration::Calibrator;
+    use commons::get_weight;
+    use commons::test_helper::get_n_random_examples;
+    use commons::tree::Tree;
+    use super::Model;
+
+    fn get_random_tree(rng: &mut StdRng, num_features: usize) -> Tree {
+        let mut tree = Tree::new(3);
+        tree.split(0, rng.gen_range(0, num_features), rng.gen(), rng.gen(), rng.gen());
+        tree.split(1, rng.gen_range(0, num_features), rng.gen(), rng.gen(), rng.gen());
+        tree.split(2, rng.gen_range(0, num_features), rng.gen(), rng.gen(), rng.gen());
+        tree
+    }
+
+    #[test]
+    fn test_update_score() {
+        let num_features = 10;
+        let mut rng = StdRng::from_seed(&[0]);
+        let mut model = Model::new();
+        let mut examples: Vec<ExampleWithScore> = get_n_random_examples(100, num_features);
+        let mut examples_in_sample: Vec<ExampleInSampleSet> =
+            examples.iter()
+                    .map(|(example, _)| (example.clone(), (1.0, 0.0, 0, 0)))
+                    .collect();
+        for num_trees in 1..10 {
+            for _ in 0..num_trees {
+                model.append(get_random_tree(&mut rng, num_features));
+            }
+            examples.iter_mut().for_each(|example| model.update_score(example));
+            examples_in_sample.iter_mut().for_each(|example| model.update_score(example));
+            for (a, b) in examples.iter().zip(examples_in_sample.iter()) {
+                let (expected, _) = model.get_prediction(&a.0, 0);
+                assert!((a.get_score().0 - expected).abs() < 1e-4);
+                assert!((b.get_score().0 - expected).abs() < 1e-4);
+                assert_eq!(a.get_score().1, model.size());
+                assert_eq!(b.get_score().1, model.size());
+                assert_eq!((b.1).0, get_weight(&b.0, b.get_score().0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncate_slice_merge_rescale() {
+        let num_features = 10;
+        let mut rng = StdRng::from_seed(&[2]);
+        let mut model = Model::new();
+        for _ in 0..10 {
+            model.append(get_random_tree(&mut rng, num_features));
+        }
+        model.base_size = 4;
+        let examples: Vec<ExampleWithScore> = get_n_random_examples(100, num_features);
+        let predict = |model: &Model, example| model.get_prediction(example, 0).0;
+
+        let head = model.slice(0, 6);
+        let tail = model.slice(6, 10);
+        assert_eq!((head.size(), head.base_size), (6, 4));
+        assert_eq!((tail.size(), tail.base_size), (4, 0));
+        assert_eq!(model.slice(2, 8).base_size, 2);
+
+        let mut truncated = model.clone();
+        truncated.truncate(6);
+        assert_eq!(truncated, head);
+        truncated.best_iteration = Some(4);
+        truncated.truncate(4);
+        assert_eq!(truncated.best_iteration, Some(4));
+        truncated.truncate(2);
+        assert_eq!(truncated.base_size, 2);
+        assert_eq!(truncated.best_iteration, None);
+
+        let mut merged = head.clone();
+        merged.merge(&tail);
+        assert_eq!(merged, model);
+
+        let mut rescaled = model.clone();
+        rescaled.rescale(0.5);
+        examples.iter().for_each(|(example, _)| {
+            assert!((predict(&head, example) + predict(&tail, example) -
+                     predict(&model, example)).abs() < 1e-4);
+            assert!((predict(&rescaled, example) * 2.0 - predict(&model, example)).abs() < 1e-4);
+        });
+    }
+
+    #[test]
+    fn test_edits_clear_calibrator() {
+        let num_features = 10;
+        let mut rng = StdRng::from_seed(&[3]);
+        let mut model = Model::new();
+        for _ in 0..4 {
+            model.append(get_random_tree(&mut rng, num_features));
+        }
+        model.calibrator = Some(Calibrator::Isotonic {
+            scores: vec![-1.0, 0.5],
+            probs:  vec![0.2, 0.9],
+        });
+        let other = model.slice(0, 2);
+
+        let mut truncated = model.clone();
+        truncated.truncate(4);
+        assert!(truncated.calibrator.is_some());
+        truncated.truncate(2);
+        assert!(truncated.calibrator.is_none());
+
+        let mut appended = model.clone();
+        appended.append(get_random_tree(&mut rng, num_features));
+        assert!(appended.calibrator.is_none());
+
+        let mut merged = model.clone();
+        merged.merge(&other);
+        assert!(merged.calibrator.is_none());
+
+        let mut rescaled = model.clone();
+        rescaled.rescale(0.5);
+        assert!(rescaled.calibrator.is_none());
+    }
+
+    #[test]
+    fn test_explain() {
+        let num_features = 5;
+        let mut rng = StdRng::from_seed(&[1]);
+        let mut model = Model::new();
+        for _ in 0..20 {
+            model.append(get_random_tree(&mut rng, num_features));
+        }
+        // the root of a tree might have non-zero prediction
+        let mut tree = Tree::new(1);
+        tree.split(0, 2, 128, 0.5, -0.5);
+        model.append(tree);
+        let examples: Vec<ExampleWithScore> = get_n_random_examples(100, num_features);
+        examples.iter().for_each(|(example, _)| {
+            let contributions = model.explain(example);
+            assert_eq!(contributions.len(), num_features + 1);
+            let sum: f32 = contributions.iter().sum();
+            assert!((sum - model.get_prediction(example, 0).0).abs() < 1e-4);
+        });
+        // the contribution of a feature that is never used for splitting is zero
+        let mut model = Model::new();
+        let mut tree = Tree::new(1);
+        tree.split(0, 1, 100, 0.25, -0.25);
+        model.append(tree);
+        let contributions = model.explain(&examples[0].0);
+        assert_eq!(contributions[0], 0.0);
+        assert_eq!(contributions[1].abs(), 0.25);
+        assert_eq!(contributions[num_features], 0.0);
+    }
+}
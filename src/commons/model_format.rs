@@ -0,0 +1,337 @@
+use commons::bins::Bins;
+use commons::calibration::Calibrator;
+use commons::model::Model;
+use commons::tree::Tree;
+
+
+/*
+Binary model format (all numbers are little-endian):
+
+    magic bytes         b"SPRW"
+    format version      u32
+    number of features  u64
+    hash of the bins    u64
+    timestamp           f32
+    base size           u64
+    growth policy       u64 length + UTF-8 bytes
+    best iteration      u64, `u64::MAX` if not set
+    calibrator          u8 tag, 0 if not set, see `Calibrator::write_binary`
+    number of trees     u64
+    trees               see `Tree::write_binary`
+
+The number of features and the hash of the bins are checked against the bins used for
+validation, since the thresholds of the trees are the indices of the bins.
+Files that do not start with the magic bytes are parsed as the legacy JSON format,
+i.e. the `(timestamp, size, model)` tuple.
+*/
+const MAGIC: &[u8; 4] = b"SPRW";
+pub const FORMAT_VERSION: u32 = 1;
+const NO_CALIBRATOR: u8 = 0;
+const NO_BEST_ITERATION: u64 = ::std::u64::MAX;
+
+
+/// The format of the model files
+pub enum ModelFileFormat {
+    Json,
+    Binary,
+}
+
+impl ModelFileFormat {
+    pub fn new(format: &String) -> ModelFileFormat {
+        match format.to_lowercase().as_str() {
+            "json"   => ModelFileFormat::Json,
+            "binary" => ModelFileFormat::Binary,
+            _        => {
+                error!("Unrecognized model format. Use json by default.");
+                ModelFileFormat::Json
+            }
+        }
+    }
+
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ModelFileFormat::Json   => "json",
+            ModelFileFormat::Binary => "bin",
+        }
+    }
+}
+
+
+/// The header of a model in the binary format
+#[derive(Debug, PartialEq)]
+pub struct ModelHeader {
+    pub format_version: u32,
+    pub num_features:   usize,
+    pub bins_hash:      u64,
+}
+
+impl ModelHeader {
+    pub fn new(bins: &Vec<Bins>) -> ModelHeader {
+        ModelHeader {
+            format_version: FORMAT_VERSION,
+            num_features:   bins.len(),
+            bins_hash:      get_bins_hash(bins),
+        }
+    }
+
+    /// Check if the model was trained on `bins`
+    pub fn check(&self, bins: &Vec<Bins>) -> bool {
+        let expected = ModelHeader::new(bins);
+        if self.num_features != expected.num_features || self.bins_hash != expected.bins_hash {
+            error!("The model was trained with different bins ({} features, hash {:x}) from \
+                    the current bins ({} features, hash {:x})", self.num_features, self.bins_hash,
+                   expected.num_features, expected.bins_hash);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+
+/// FNV-1a hash of the sizes and the thresholds of the bins
+pub fn get_bins_hash(bins: &Vec<Bins>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut update = |val: u64| {
+        for k in 0..8 {
+            hash ^= (val >> (8 * k)) & 0xff;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    bins.iter().for_each(|bin| {
+        update(bin.len() as u64);
+        bin.get_vals().iter().for_each(|val| update(val.to_bits() as u64));
+    });
+    hash
+}
+
+
+pub fn is_binary_model(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+
+pub fn encode_model(model: &Model, timestamp: f32, bins: &Vec<Bins>) -> Vec<u8> {
+    encode_model_with_header(model, timestamp, &ModelHeader::new(bins))
+}
+
+
+/// Encode a model with an existing header, e.g. the header of the model it was derived from.
+/// The model is always encoded in the current format version.
+pub fn encode_model_with_header(model: &Model, timestamp: f32, header: &ModelHeader) -> Vec<u8> {
+    let mut writer = BinaryWriter::new();
+    writer.buf.extend_from_slice(MAGIC);
+    writer.u32(FORMAT_VERSION);
+    writer.u64(header.num_features as u64);
+    writer.u64(header.bins_hash);
+    writer.f32(timestamp);
+    writer.u64(model.base_size as u64);
+    writer.string(&model.growth_policy);
+    writer.u64(model.best_iteration.map(|t| t as u64).unwrap_or(NO_BEST_ITERATION));
+    match model.calibrator {
+        Some(ref calibrator) => calibrator.write_binary(&mut writer),
+        None                 => writer.u8(NO_CALIBRATOR),
+    }
+    writer.u64(model.size() as u64);
+    model.get_trees().iter().for_each(|tree| tree.write_binary(&mut writer));
+    writer.buf
+}
+
+
+/// Decode a model in the binary format, returns `None` if the data is corrupted
+pub fn decode_model(data: &[u8]) -> Option<(ModelHeader, f32, Model)> {
+    if !is_binary_model(data) {
+        return None;
+    }
+    let mut reader = BinaryReader::new(&data[MAGIC.len()..]);
+    let format_version = reader.u32()?;
+    if format_version != FORMAT_VERSION {
+        error!("The model format version {} is not supported (the supported version is {})",
+               format_version, FORMAT_VERSION);
+        return None;
+    }
+    let header = ModelHeader {
+        format_version: format_version,
+        num_features:   reader.u64()? as usize,
+        bins_hash:      reader.u64()?,
+    };
+    let timestamp = reader.f32()?;
+    let base_size = reader.u64()? as usize;
+    let growth_policy = reader.string()?;
+    let best_iteration =
+        Some(reader.u64()?).filter(|t| *t != NO_BEST_ITERATION).map(|t| t as usize);
+    let calibrator = match reader.u8()? {
+        NO_CALIBRATOR => None,
+        tag           => Some(Calibrator::read_binary(tag, &mut reader)?),
+    };
+    let num_trees = reader.u64()?;
+    let mut model = Model::new();
+    for _ in 0..num_trees {
+        model.append(Tree::read_binary(&mut reader, header.num_features)?);
+    }
+    model.base_size = base_size;
+    model.growth_policy = growth_policy;
+    model.best_iteration = best_iteration;
+    model.calibrator = calibrator;
+    Some((header, timestamp, model))
+}
+
+
+pub struct BinaryWriter {
+    pub buf: Vec<u8>,
+}
+
+impl BinaryWriter {
+    pub fn new() -> BinaryWriter {
+        BinaryWriter {
+            buf: vec![],
+        }
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        (0..4).for_each(|k| self.buf.push((val >> (8 * k)) as u8));
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        (0..8).for_each(|k| self.buf.push((val >> (8 * k)) as u8));
+    }
+
+    pub fn f32(&mut self, val: f32) {
+        self.u32(val.to_bits());
+    }
+
+    pub fn string(&mut self, val: &String) {
+        self.u64(val.len() as u64);
+        self.buf.extend_from_slice(val.as_bytes());
+    }
+}
+
+
+pub struct BinaryReader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(data: &'a [u8]) -> BinaryReader<'a> {
+        BinaryReader {
+            data: data,
+            index: 0,
+        }
+    }
+
+    /// The number of bytes that are not read yet
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.index
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.remaining() {
+            return None;
+        }
+        self.index += len;
+        Some(&self.data[self.index - len..self.index])
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|t| t[0])
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|t| t.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u32))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|t| t.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        let len = self.u64()? as usize;
+        self.take(len).and_then(|t| String::from_utf8(t.to_vec()).ok())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use commons::calibration::Calibrator;
+    use commons::model::Model;
+    use commons::test_helper::get_synthetic_bins;
+    use commons::tree::SplitStats;
+    use commons::tree::Tree;
+    use super::ModelHeader;
+    use super::decode_model;
+    use super::encode_model;
+
+    fn get_model() -> Model {
+        let mut model = Model::new();
+        for k in 0..5 {
+            let mut tree = Tree::new(2);
+            let (left, _) = tree.split(0, k, k as u8, 0.0, 0.0);
+            tree.split(left, k + 1, 3, -0.5 + k as f32, 0.25);
+            if k % 2 == 0 {
+                tree.set_split_stats(left, SplitStats {
+                    gamma:           0.1,
+                    raw_martingale:  1.5,
+                    sum_c:           1.0,
+                    sum_c_squared:   2.0,
+                    bound:           0.5,
+                    num_scanned:     1000 + k,
+                    positive:        400,
+                    negative:        600,
+                    positive_weight: 4.0,
+                    negative_weight: 6.0,
+                    fallback:        k == 0,
+                });
+            }
+            model.append(tree);
+        }
+        model.base_size = 3;
+        model.growth_policy = "best-first".to_string();
+        model.best_iteration = Some(4);
+        model.calibrator = Some(Calibrator::Isotonic {
+            scores: vec![-1.0, 0.5],
+            probs:  vec![0.2, 0.9],
+        });
+        model
+    }
+
+    #[test]
+    fn test_binary_model_round_trip() {
+        let model = get_model();
+        let bins = get_synthetic_bins(10, 8);
+        let data = encode_model(&model, 12.5, &bins);
+        let (header, timestamp, decoded) = decode_model(&data).unwrap();
+        assert_eq!(header, ModelHeader::new(&bins));
+        assert!(header.check(&bins));
+        assert!(!header.check(&get_synthetic_bins(10, 9)));
+        assert_eq!(timestamp, 12.5);
+        assert_eq!(decoded, model);
+        // all fields, including the split statistics, are kept
+        assert_eq!(serde_json::to_string(&decoded).unwrap(),
+                   serde_json::to_string(&model).unwrap());
+        // smaller than the JSON format
+        assert!(data.len() < serde_json::to_string(&model).unwrap().len());
+    }
+
+    #[test]
+    fn test_corrupted_binary_model() {
+        let data = encode_model(&get_model(), 0.0, &get_synthetic_bins(10, 8));
+        assert!(decode_model(&data[..data.len() - 1]).is_none());
+        assert!(decode_model(b"[0.0, 0, {}]").is_none());
+        // the format version follows the magic bytes
+        let mut data = data.clone();
+        data[4] = 2;
+        assert!(decode_model(&data).is_none());
+    }
+}
@@ -1,4 +1,6 @@
+use std::fs::read;
 use std::fs::rename;
+use std::path::Path;
 use std::fs::remove_file;
 use std::io::Write;
 use std::sync::Arc;
@@ -18,6 +20,12 @@ use commons::io::load_s3 as io_load_s3;
 use commons::io::write_s3 as io_write_s3;
 use commons::performance_monitor::PerformanceMonitor;
 use commons::model::Model;
+use commons::model_format::ModelFileFormat;
+use commons::model_format::ModelHeader;
+use commons::model_format::decode_model;
+use commons::model_format::encode_model;
+use commons::model_format::encode_model_with_header;
+use commons::model_format::is_binary_model;
 
 
 // (sample_version, new_sample, model);
@@ -140,27 +148,45 @@ pub fn load_sample_s3(_exp_name: &str) -> Option<VersionedSampleModel> {
 
 // read/write model
 
+fn serialize_model(
+    model: &Model, timestamp: f32, format: &ModelFileFormat, bins: &Vec<Bins>,
+) -> Vec<u8> {
+    match format {
+        ModelFileFormat::Json   => {
+            serde_json::to_string(&(timestamp, model.size(), model)).expect(
+                "Local model cannot be serialized."
+            ).into_bytes()
+        },
+        ModelFileFormat::Binary => encode_model(model, timestamp, bins),
+    }
+}
+
+/// Write the model to `<model_dir>/model_<size>-v<size>.<ext>` if `save_process` is true,
+/// or to `<model_dir>/model.<ext>` otherwise
 #[cfg(not(test))]
-pub fn write_model(model: &Model, timestamp: f32, save_process: bool) -> String {
-    let json = serde_json::to_string(&(timestamp, model.size(), model)).expect(
-        "Local model cannot be serialized."
-    );
+pub fn write_model(
+    model: &Model, timestamp: f32, save_process: bool, format: &ModelFileFormat,
+    bins: &Vec<Bins>, model_dir: &String,
+) -> Vec<u8> {
+    let data = serialize_model(model, timestamp, format, bins);
     let filename = {
         if save_process {
-            format!("models/model_{}-v{}.json", model.size(), model.size())
+            format!("{}/model_{}-v{}.{}", model_dir, model.size(), model.size(),
+                    format.get_extension())
         } else {
-            "model.json".to_string()
+            format!("{}/model.{}", model_dir, format.get_extension())
         }
     };
-    create_bufwriter(&filename).write(json.as_ref()).unwrap();
-    json
+    create_bufwriter(&filename).write(data.as_ref()).unwrap();
+    data
 }
 
 #[cfg(test)]
-pub fn write_model(model: &Model, timestamp: f32, _save_process: bool) -> String {
-    serde_json::to_string(&(timestamp, model.size(), model)).expect(
-        "Local model cannot be serialized."
-    )
+pub fn write_model(
+    model: &Model, timestamp: f32, _save_process: bool, format: &ModelFileFormat,
+    bins: &Vec<Bins>, _model_dir: &String,
+) -> Vec<u8> {
+    serialize_model(model, timestamp, format, bins)
 }
 
 
@@ -173,9 +199,61 @@ pub fn write_model(model: &Model, timestamp: f32, _save_process: bool) -> String
 // }
 
 
-pub fn read_model() -> (f32, usize, Model) {
-    serde_json::from_str(&raw_read_all(&"model.json".to_string()).expect("Read model.json failed"))
-            .expect(&format!("Cannot parse the model in `model.json`"))
+/// Read the model written by `write_model` to `<model_dir>/model.<ext>`
+pub fn read_model(format: &ModelFileFormat, model_dir: &String) -> (f32, usize, Model) {
+    read_model_from_file(&format!("{}/model.{}", model_dir, format.get_extension()))
+}
+
+
+/// Read the model persisted by `write_model` from `filename`, in either the binary or
+/// the JSON format
+pub fn read_model_from_file(filename: &String) -> (f32, usize, Model) {
+    let (timestamp, size, model, _) = read_model_with_header(filename);
+    (timestamp, size, model)
+}
+
+
+/// Read the model persisted by `write_model` from `filename`, along with the header if
+/// the model is in the binary format
+pub fn read_model_with_header(filename: &String) -> (f32, usize, Model, Option<ModelHeader>) {
+    if !Path::new(filename).exists() {
+        panic!("Cannot read `{}`", filename);
+    }
+    try_read_model_with_header(filename)
+        .expect(&format!("Cannot parse the model in `{}`", filename))
+}
+
+
+/// Same as `read_model_with_header`, but returns `None` if the file cannot be read or cannot be
+/// parsed, e.g. if it is being written
+pub fn try_read_model_with_header(
+    filename: &String,
+) -> Option<(f32, usize, Model, Option<ModelHeader>)> {
+    let data = read(filename).ok()?;
+    if is_binary_model(&data) {
+        let (header, timestamp, model) = decode_model(&data)?;
+        Some((timestamp, model.size(), model, Some(header)))
+    } else {
+        let (timestamp, size, model) = serde_json::from_slice(&data).ok()?;
+        Some((timestamp, size, model, None))
+    }
+}
+
+
+/// Write the model to `filename`, in the binary format with `header` if it is provided,
+/// or in the JSON format otherwise
+pub fn write_model_to_file(
+    filename: &String, model: &Model, timestamp: f32, header: Option<&ModelHeader>,
+) {
+    let data = match header {
+        Some(header) => encode_model_with_header(model, timestamp, header),
+        None         => {
+            serde_json::to_string(&(timestamp, model.size(), model)).expect(
+                "Local model cannot be serialized."
+            ).into_bytes()
+        },
+    };
+    write_all(filename, &data).expect(&format!("Cannot write the model to `{}`", filename));
 }
 
 
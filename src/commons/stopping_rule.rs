@@ -0,0 +1,194 @@
+use std::f32::INFINITY;
+
+use config::Config;
+
+
+/// Statistics of the martingale of a candidate weak rule
+pub struct MartingaleStats {
+    /// Sum of the increments
+    pub sum_c: f32,
+    /// Sum of the squared increments
+    pub sum_c_squared: f32,
+    /// Sum of the squared (half-)ranges of the increments
+    pub sum_range_squared: f32,
+    /// Largest (half-)range of a single increment
+    pub max_range: f32,
+    /// Number of increments
+    pub count: usize,
+}
+
+
+/// A stopping rule decides when the empirical edge of a weak rule is large enough
+/// for it to be accepted.
+pub trait StoppingRule: Send + Sync {
+    /// Name of the stopping rule, reported in the logs of the generated tree nodes
+    fn name(&self) -> &'static str;
+
+    /// The value that `stats.sum_c` must exceed for the weak rule to be accepted.
+    /// The bound is non-negative, so the weak rules with a non-positive `sum_c` are rejected
+    /// without computing the bound.
+    fn get_bound(&self, stats: &MartingaleStats) -> f32;
+}
+
+
+/// The parameters of the stopping rule, from which the stopping rule is created
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoppingRuleParams {
+    pub name: String,
+    pub delta: f32,
+    pub shrink: f32,
+    pub threshold_factor: f32,
+}
+
+impl StoppingRuleParams {
+    pub fn new(config: &Config) -> StoppingRuleParams {
+        StoppingRuleParams {
+            name:             config.stopping_rule.clone(),
+            delta:            config.stopping_rule_delta,
+            shrink:           config.stopping_rule_shrink,
+            threshold_factor: config.stopping_rule_threshold_factor,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn StoppingRule> {
+        match self.name.to_lowercase().as_str() {
+            "lil"                 => Box::new(LilBound {
+                delta:            self.delta,
+                shrink:           self.shrink,
+                threshold_factor: self.threshold_factor,
+            }),
+            "hoeffding"           => Box::new(HoeffdingBound {
+                delta: self.delta,
+            }),
+            "empirical-bernstein" => Box::new(EmpiricalBernsteinBound {
+                delta: self.delta,
+            }),
+            _                     => {
+                error!("Unrecognized stopping rule. Use lil by default.");
+                Box::new(LilBound {
+                    delta:            self.delta,
+                    shrink:           self.shrink,
+                    threshold_factor: self.threshold_factor,
+                })
+            }
+        }
+    }
+}
+
+
+/// The bound based on the law of the iterated logarithm
+pub struct LilBound {
+    delta: f32,
+    shrink: f32,
+    threshold_factor: f32,
+}
+
+impl StoppingRule for LilBound {
+    fn name(&self) -> &'static str {
+        "lil"
+    }
+
+    fn get_bound(&self, stats: &MartingaleStats) -> f32 {
+        let (sum_c, sum_c_squared) = (stats.sum_c, stats.sum_c_squared);
+        let threshold: f32 = self.threshold_factor * 173.0 * (4.0 / self.delta).ln();
+        if sum_c_squared >= threshold {
+            let log_log_term = 3.0 * sum_c_squared / 2.0 / sum_c.abs();
+            let log_log = {
+                if log_log_term > 2.7183 {
+                    log_log_term.ln().ln()
+                } else {
+                    0.0
+                }
+            };
+            self.shrink * (
+                3.0 * sum_c_squared * (2.0 * log_log + (2.0 / self.delta).ln())
+            ).sqrt()
+        } else {
+            INFINITY
+        }
+    }
+}
+
+
+/// Hoeffding's bound on the bounded increments, made valid at any time by a union bound
+/// over the number of increments
+pub struct HoeffdingBound {
+    delta: f32,
+}
+
+impl StoppingRule for HoeffdingBound {
+    fn name(&self) -> &'static str {
+        "hoeffding"
+    }
+
+    fn get_bound(&self, stats: &MartingaleStats) -> f32 {
+        if stats.count == 0 {
+            return INFINITY;
+        }
+        let log_term = get_union_log_term(stats.count, self.delta);
+        (2.0 * stats.sum_range_squared * log_term).sqrt()
+    }
+}
+
+
+/// Empirical Bernstein bound, which replaces the ranges in Hoeffding's bound by the observed
+/// squared increments, plus a correction term on the largest range
+pub struct EmpiricalBernsteinBound {
+    delta: f32,
+}
+
+impl StoppingRule for EmpiricalBernsteinBound {
+    fn name(&self) -> &'static str {
+        "empirical-bernstein"
+    }
+
+    fn get_bound(&self, stats: &MartingaleStats) -> f32 {
+        if stats.count == 0 {
+            return INFINITY;
+        }
+        let log_term = get_union_log_term(stats.count, self.delta / 3.0);
+        (2.0 * stats.sum_c_squared * log_term).sqrt() + 3.0 * stats.max_range * log_term
+    }
+}
+
+
+// ln(1 / delta_n), where delta_n = delta / (n * (n + 1)) sums up to delta over all n
+fn get_union_log_term(count: usize, delta: f32) -> f32 {
+    let n = count as f32;
+    (n * (n + 1.0) / delta).ln()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::EmpiricalBernsteinBound;
+    use super::HoeffdingBound;
+    use super::LilBound;
+    use super::MartingaleStats;
+    use super::StoppingRule;
+
+    fn get_stats(count: usize) -> MartingaleStats {
+        MartingaleStats {
+            sum_c: 0.2 * count as f32,
+            sum_c_squared: count as f32,
+            sum_range_squared: count as f32,
+            max_range: 1.0,
+            count: count,
+        }
+    }
+
+    #[test]
+    fn test_bounds_fire_eventually() {
+        let rules: Vec<Box<dyn StoppingRule>> = vec![
+            Box::new(LilBound { delta: 0.000001, shrink: 1.0, threshold_factor: 1.0 }),
+            Box::new(HoeffdingBound { delta: 0.000001 }),
+            Box::new(EmpiricalBernsteinBound { delta: 0.000001 }),
+        ];
+        for rule in rules {
+            let early = get_stats(10);
+            assert!(early.sum_c <= rule.get_bound(&early), "{}", rule.name());
+            let late = get_stats(1000000);
+            assert!(late.sum_c > rule.get_bound(&late), "{}", rule.name());
+        }
+    }
+}
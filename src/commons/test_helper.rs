@@ -3,7 +3,9 @@ use rand;
 use rand::Rng;
 
 use TFeature;
+use config::Config;
 use commons::ExampleWithScore;
+use commons::bins::Bins;
 use commons::labeled_data::LabeledData;
 use commons::packet::TaskPacket;
 use commons::packet::UpdatePacket;
@@ -27,13 +29,68 @@ pub fn get_n_random_examples(n: usize, num_features: usize) -> Vec<ExampleWithSc
 }
 
 
+pub fn get_test_config(num_features: usize) -> Config {
+    let config = format!(r#"
+        training_filename: "training.libsvm"
+        num_examples: 1000
+        num_features: {}
+        positive: "1"
+        testing_filename: "testing.libsvm"
+        num_testing_examples: 1000
+        max_sample_size: 1000
+        max_bin_size: 255
+        min_gamma: 0.001
+        default_gamma: 0.25
+        max_trials_before_shrink: 1000000
+        min_ess: 0.0
+        min_grid_size: 1000
+        num_trees: 10
+        num_splits: 10
+        channel_size: 10000
+        buffer_size: 1000
+        batch_size: 100
+        serial_sampling: true
+        sampling_mode: "memory"
+        sampler_scanner: "both"
+        sleep_duration: 10
+        num_examples_per_block: 1000
+        disk_buffer_filename: "disk_buffer.bin"
+        num_assigners: 1
+        num_samplers: 1
+        network: []
+        port: 8000
+        local_name: "local"
+        exp_name: "test"
+        save_process: false
+        save_interval: 1
+        debug_mode: true
+        models_table_filename: "models_table.txt"
+        incremental_testing: false
+        testing_scores_only: false
+        resume_training: false
+    "#, num_features);
+    serde_yaml::from_str(&config).unwrap()
+}
+
+
+/// Bins with `num_bins` thresholds for each feature
+pub fn get_synthetic_bins(num_features: usize, num_bins: usize) -> Vec<Bins> {
+    let bins = format!(
+        r#"{{"size":{},"vals":[{}]}}"#,
+        num_bins,
+        (0..num_bins).map(|t| t.to_string()).collect::<Vec<String>>().join(","),
+    );
+    (0..num_features).map(|_| serde_json::from_str(&bins).unwrap()).collect()
+}
+
+
 pub fn get_mock_packet(
     _machine_id: usize, _node_id: usize, _gamma: f32, packet_size: usize,
 ) -> UpdatePacket {
     let ess = 0.5;
-    let mut update_tree = Tree::new(1);
+    let update_tree = Tree::new(1);
     let task_packet = TaskPacket::new();
-    UpdatePacket::new(update_tree, 0, task_packet, 0, ess)
+    UpdatePacket::new(Some(update_tree), 0, task_packet, 0, ess)
 }
 
 
@@ -1,9 +1,34 @@
 use TFeature;
 use commons::Example;
+use commons::model_format::BinaryReader;
+use commons::model_format::BinaryWriter;
 
 use commons::is_zero;
 
 type DimScaleType = usize;
+// `split_feature` of the leaves in the binary format
+const NO_SPLIT: u32 = ::std::u32::MAX;
+// the number of bytes of the attributes of a node in the binary format
+const BINARY_NODE_SIZE: usize = 21;
+
+
+/// The statistics of the weak rule that split a node, recorded by the learner when the
+/// split is found. They are only kept for auditing the model and computing the importance
+/// of the features, and are not used for prediction.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SplitStats {
+    pub gamma:           f32,
+    pub raw_martingale:  f32,
+    pub sum_c:           f32,
+    pub sum_c_squared:   f32,
+    pub bound:           f32,
+    pub num_scanned:     usize,
+    pub positive:        usize,
+    pub negative:        usize,
+    pub positive_weight: f32,
+    pub negative_weight: f32,
+    pub fallback:        bool,
+}
 
 
 /*
@@ -21,7 +46,10 @@ pub struct Tree {
     split_feature:  Vec<Option<DimScaleType>>,
     threshold:      Vec<TFeature>,
     predicts:       Vec<f32>,
-    leaf_depth:     Vec<DimScaleType>
+    leaf_depth:     Vec<DimScaleType>,
+    // Statistics of the splits, which is empty for the models trained by the earlier versions
+    #[serde(default)]
+    split_stats:    Vec<Option<SplitStats>>,
 }
 
 impl Clone for Tree {
@@ -34,7 +62,8 @@ impl Clone for Tree {
             split_feature:  self.split_feature.clone(),
             threshold:      self.threshold.clone(),
             predicts:       self.predicts.clone(),
-            leaf_depth:     self.leaf_depth.clone()
+            leaf_depth:     self.leaf_depth.clone(),
+            split_stats:    self.split_stats.clone(),
         }
     }
 }
@@ -50,7 +79,8 @@ impl Tree {
             split_feature:  Vec::with_capacity(max_nodes as usize),
             threshold:      Vec::with_capacity(max_nodes as usize),
             predicts:       Vec::with_capacity(max_nodes as usize),
-            leaf_depth:     Vec::with_capacity(max_nodes as usize)
+            leaf_depth:     Vec::with_capacity(max_nodes as usize),
+            split_stats:    Vec::with_capacity(max_nodes as usize),
             // leaf_parent:    Vec::with_capacity(max_leaves),
             // leaf_count:     Vec::with_capacity(max_leaves),
             // internal_value: Vec::with_capacity(max_leaves as usize),
@@ -67,6 +97,7 @@ impl Tree {
         self.threshold.shrink_to_fit();
         self.predicts.shrink_to_fit();
         self.leaf_depth.shrink_to_fit();
+        self.split_stats.shrink_to_fit();
     }
 
     pub fn split(
@@ -102,11 +133,182 @@ impl Tree {
         self.get_leaf_index_prediction(data).1
     }
 
+    /// Add the contribution of each feature to the prediction of `data` to `contributions`,
+    /// and return the prediction of the root (i.e. the bias).
+    ///
+    /// The prediction of a node is the prediction of its parent plus the prediction of
+    /// the weak rule that split the parent, so the change of the predictions along the path
+    /// of `data` is attributed to the features of the splits on the path (Saabas), and
+    /// the bias plus the contributions sum to the prediction of the leaf.
+    pub fn add_contributions(&self, data: &Example, contributions: &mut [f32]) -> f32 {
+        let mut node: usize = 0;
+        let feature = &(data.feature);
+        while let Some(split_feature) = self.split_feature[node] {
+            let child = if feature[split_feature as usize] <= self.threshold[node] {
+                self.left_child[node]
+            } else {
+                self.right_child[node]
+            } as usize;
+            contributions[split_feature as usize] += self.predicts[child] - self.predicts[node];
+            node = child;
+        }
+        self.predicts[0]
+    }
+
+    /// Multiply the predictions of all nodes by `factor`
+    pub fn rescale(&mut self, factor: f32) {
+        self.predicts.iter_mut().for_each(|predict| *predict *= factor);
+    }
+
+    pub fn is_leaf(&self, index: usize) -> bool {
+        self.split_feature[index].is_none()
+    }
+
+    pub fn get_depth(&self, index: usize) -> usize {
+        self.leaf_depth[index]
+    }
+
+    /// Return the (feature, threshold, left child, right child) of the node `index`,
+    /// or `None` if it is a leaf
+    pub fn get_split(&self, index: usize) -> Option<(usize, TFeature, usize, usize)> {
+        self.split_feature[index].map(|feature| {
+            (feature, self.threshold[index], self.left_child[index], self.right_child[index])
+        })
+    }
+
+    pub fn get_predict(&self, index: usize) -> f32 {
+        self.predicts[index]
+    }
+
+    /// Record the statistics of the weak rule that split the node `index`
+    pub fn set_split_stats(&mut self, index: usize, stats: SplitStats) {
+        if self.split_stats.len() < self.num_nodes {
+            self.split_stats.resize(self.num_nodes, None);
+        }
+        self.split_stats[index] = Some(stats);
+    }
+
+    /// Return the statistics of the split on the node `index`, or `None` if the node is
+    /// a leaf or the statistics were not recorded
+    pub fn get_split_stats(&self, index: usize) -> Option<&SplitStats> {
+        self.split_stats.get(index).and_then(|t| t.as_ref())
+    }
+
     pub fn is_full_tree(&self) -> bool {
         trace!("is-full-tree, {}, {}", self.num_nodes, self.max_nodes);
         self.num_nodes >= self.max_nodes
     }
 
+    /// Write the tree in the binary model format, in which each attribute of the nodes is
+    /// stored as a flat array, followed by the split statistics if any
+    pub fn write_binary(&self, writer: &mut BinaryWriter) {
+        let k = self.num_nodes;
+        writer.u64(self.max_nodes as u64);
+        writer.u64(k as u64);
+        self.left_child[0..k].iter().for_each(|t| writer.u32(*t as u32));
+        self.right_child[0..k].iter().for_each(|t| writer.u32(*t as u32));
+        self.split_feature[0..k].iter().for_each(|t| {
+            writer.u32(t.map(|feature| feature as u32).unwrap_or(NO_SPLIT))
+        });
+        self.threshold[0..k].iter().for_each(|t| writer.u8(*t as u8));
+        self.predicts[0..k].iter().for_each(|t| writer.f32(*t));
+        self.leaf_depth[0..k].iter().for_each(|t| writer.u32(*t as u32));
+        writer.u8(if self.split_stats.is_empty() { 0 } else { 1 });
+        if !self.split_stats.is_empty() {
+            (0..k).for_each(|index| {
+                match self.get_split_stats(index) {
+                    None => writer.u8(0),
+                    Some(stats) => {
+                        writer.u8(1);
+                        writer.f32(stats.gamma);
+                        writer.f32(stats.raw_martingale);
+                        writer.f32(stats.sum_c);
+                        writer.f32(stats.sum_c_squared);
+                        writer.f32(stats.bound);
+                        writer.u64(stats.num_scanned as u64);
+                        writer.u64(stats.positive as u64);
+                        writer.u64(stats.negative as u64);
+                        writer.f32(stats.positive_weight);
+                        writer.f32(stats.negative_weight);
+                        writer.u8(stats.fallback as u8);
+                    },
+                }
+            });
+        }
+    }
+
+    /// Read a tree written by `write_binary`, returns `None` if the data is corrupted, or if
+    /// the splits are not on the first `num_features` features
+    pub fn read_binary(reader: &mut BinaryReader, num_features: usize) -> Option<Tree> {
+        let max_nodes = reader.u64()? as usize;
+        let k = reader.u64()? as usize;
+        // the sizes are not trusted before the nodes are read, so the allocations are bounded
+        // by the size of the remaining data
+        if k > max_nodes || k > reader.remaining() / BINARY_NODE_SIZE {
+            return None;
+        }
+        let mut tree = Tree {
+            max_nodes:      max_nodes,
+            num_nodes:      k,
+            left_child:     Vec::with_capacity(k),
+            right_child:    Vec::with_capacity(k),
+            split_feature:  Vec::with_capacity(k),
+            threshold:      Vec::with_capacity(k),
+            predicts:       Vec::with_capacity(k),
+            leaf_depth:     Vec::with_capacity(k),
+            split_stats:    vec![],
+        };
+        for _ in 0..k { tree.left_child.push(reader.u32()? as DimScaleType); }
+        for _ in 0..k { tree.right_child.push(reader.u32()? as DimScaleType); }
+        for _ in 0..k {
+            let feature = reader.u32()?;
+            tree.split_feature.push(
+                if feature == NO_SPLIT { None } else { Some(feature as DimScaleType) });
+        }
+        for _ in 0..k { tree.threshold.push(reader.u8()? as TFeature); }
+        for _ in 0..k { tree.predicts.push(reader.f32()?); }
+        for _ in 0..k { tree.leaf_depth.push(reader.u32()? as DimScaleType); }
+        if reader.u8()? == 1 {
+            tree.split_stats.reserve(k);
+            for _ in 0..k {
+                let stats = if reader.u8()? == 0 {
+                    None
+                } else {
+                    Some(SplitStats {
+                        gamma:           reader.f32()?,
+                        raw_martingale:  reader.f32()?,
+                        sum_c:           reader.f32()?,
+                        sum_c_squared:   reader.f32()?,
+                        bound:           reader.f32()?,
+                        num_scanned:     reader.u64()? as usize,
+                        positive:        reader.u64()? as usize,
+                        negative:        reader.u64()? as usize,
+                        positive_weight: reader.f32()?,
+                        negative_weight: reader.f32()?,
+                        fallback:        reader.u8()? != 0,
+                    })
+                };
+                tree.split_stats.push(stats);
+            }
+        }
+        // the children are created after their parents by `split`, and are consecutive, so
+        // the traversal of the tree always terminates
+        let is_valid = (0..k).all(|index| {
+            match tree.split_feature[index] {
+                None          => true,
+                Some(feature) => {
+                    let (left, right) = (tree.left_child[index], tree.right_child[index]);
+                    feature < num_features && left > index && right == left + 1 && right < k
+                },
+            }
+        });
+        if is_valid {
+            Some(tree)
+        } else {
+            None
+        }
+    }
+
     fn add_new_node(&mut self, predict: f32, depth: DimScaleType) {
         self.num_nodes += 1;
         self.left_child.push(0);
@@ -138,4 +340,103 @@ impl PartialEq for Tree {
 }
 
 
-impl Eq for Tree {}
\ No newline at end of file
+impl Eq for Tree {}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use commons::model_format::BinaryReader;
+    use commons::model_format::BinaryWriter;
+    use super::SplitStats;
+    use super::Tree;
+
+    fn get_split_stats() -> SplitStats {
+        SplitStats {
+            gamma:           0.1,
+            raw_martingale:  2.0,
+            sum_c:           1.0,
+            sum_c_squared:   3.0,
+            bound:           0.5,
+            num_scanned:     100,
+            positive:        40,
+            negative:        60,
+            positive_weight: 4.0,
+            negative_weight: 6.0,
+            fallback:        false,
+        }
+    }
+
+    #[test]
+    fn test_split_stats() {
+        let mut tree = Tree::new(2);
+        let (left, _) = tree.split(0, 1, 2, 0.0, 0.0);
+        tree.split(left, 2, 3, -0.1, 0.1);
+        tree.set_split_stats(left, get_split_stats());
+        assert!(tree.get_split_stats(0).is_none());
+        assert_eq!(tree.get_split_stats(left), Some(&get_split_stats()));
+        assert!(tree.get_split_stats(tree.num_nodes - 1).is_none());
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let restored: Tree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_split_stats(left), Some(&get_split_stats()));
+    }
+
+    #[test]
+    fn test_read_tree_without_split_stats() {
+        let mut tree = Tree::new(2);
+        tree.split(0, 1, 2, -0.1, 0.1);
+        let json = serde_json::to_string(&tree).unwrap()
+                              .replace(",\"split_stats\":[]", "");
+        assert!(!json.contains("split_stats"));
+        let mut restored: Tree = serde_json::from_str(&json).unwrap();
+        assert!(restored == tree);
+        assert!(restored.get_split_stats(0).is_none());
+        // the statistics can be recorded on the restored tree
+        let (left, _) = restored.split(1, 0, 3, -0.1, 0.1);
+        restored.set_split_stats(1, get_split_stats());
+        assert!(restored.get_split_stats(left).is_none());
+        assert_eq!(restored.get_split_stats(1), Some(&get_split_stats()));
+    }
+    fn read_binary(data: &[u8], num_features: usize) -> Option<Tree> {
+        Tree::read_binary(&mut BinaryReader::new(data), num_features)
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut tree = Tree::new(3);
+        let (left, _) = tree.split(0, 2, 1, 0.5, -0.5);
+        tree.split(left, 4, 3, 0.25, 0.0);
+        tree.set_split_stats(left, get_split_stats());
+        let mut writer = BinaryWriter::new();
+        tree.write_binary(&mut writer);
+        assert_eq!(read_binary(&writer.buf, 5), Some(tree));
+        // the split on the feature 4 is out of range
+        assert_eq!(read_binary(&writer.buf, 4), None);
+    }
+
+    #[test]
+    fn test_invalid_binary_tree() {
+        let mut tree = Tree::new(2);
+        tree.split(0, 0, 1, 0.5, -0.5);
+        let mut writer = BinaryWriter::new();
+        tree.write_binary(&mut writer);
+        let data = writer.buf;
+        // the left child of the root is at the offset 16, after `max_nodes` and `num_nodes`
+        let with_left_child = |left: u8| {
+            let mut data = data.clone();
+            data[16] = left;
+            data
+        };
+        assert!(read_binary(&with_left_child(1), 1).is_some());
+        // a loop to the root itself
+        assert!(read_binary(&with_left_child(0), 1).is_none());
+        // the children are not consecutive
+        assert!(read_binary(&with_left_child(2), 1).is_none());
+        // the number of nodes is larger than the data
+        let mut large = data.clone();
+        large[8..16].copy_from_slice(&[0xff; 8]);
+        large[0..8].copy_from_slice(&[0xff; 8]);
+        assert!(read_binary(&large, 1).is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use commons::io::create_bufreader;
+use metrics::Metric;
 
 /// Configuration for training and testing with Sparrow
 #[derive(Clone, Serialize, Deserialize)]
@@ -24,6 +25,20 @@ pub struct Config {
     pub min_gamma: f32,
     /// Default maximum value of the \gamma for generating tree nodes
     pub default_gamma: f32,
+    /// Stopping rule for accepting the weak rules, could be "lil" (the bound based on the law of
+    /// the iterated logarithm), "hoeffding", or "empirical-bernstein"
+    #[serde(default = "default_stopping_rule")]
+    pub stopping_rule: String,
+    /// Probability that the stopping rule accepts a weak rule whose edge is smaller than \gamma
+    #[serde(default = "default_stopping_rule_delta")]
+    pub stopping_rule_delta: f32,
+    /// (for the "lil" stopping rule only) the scaling factor of the bound
+    #[serde(default = "default_one")]
+    pub stopping_rule_shrink: f32,
+    /// (for the "lil" stopping rule only) the scaling factor of the minimum variance of
+    /// the martingale before the stopping rule can fire
+    #[serde(default = "default_one")]
+    pub stopping_rule_threshold_factor: f32,
     /// Maximum number of examples to scan before shrinking the value of \gamma
     pub max_trials_before_shrink: u32,
     /// Minimum effective sample size for triggering resample
@@ -38,6 +53,55 @@ pub struct Config {
     pub num_trees: usize,
     /// Maximum number of splits per tree
     pub num_splits: usize,
+    /// Maximum depth of the leaves of a tree (set to 0 for no limit)
+    #[serde(default)]
+    pub max_depth: usize,
+    /// Minimum number of scanned examples that fall into each child of a split
+    #[serde(default)]
+    pub min_leaf_count: usize,
+    /// Minimum sum of the weights of the scanned examples that fall into each child of a split
+    #[serde(default)]
+    pub min_leaf_weight: f32,
+    /// L1 penalty on the predictions of the tree leaves, relative to the sum of the weights of
+    /// the examples in each leaf
+    #[serde(default)]
+    pub l1_reg: f32,
+    /// L2 penalty on the predictions of the tree leaves, relative to the sum of the weights of
+    /// the examples in each leaf
+    #[serde(default)]
+    pub l2_reg: f32,
+    /// Fraction of the features sampled for each tree
+    #[serde(default = "default_feature_fraction")]
+    pub feature_fraction_per_tree: f32,
+    /// Fraction of the features of a tree sampled for each of its candidate nodes
+    #[serde(default = "default_feature_fraction")]
+    pub feature_fraction_per_node: f32,
+    /// Strategy for splitting the root of each tree, could be "random-median" (split on the
+    /// median of a randomly selected feature), "learned" (find the root split with the stopping
+    /// rule), or "forced" (split on the median of `root_feature`)
+    #[serde(default = "default_root_split")]
+    pub root_split: String,
+    /// (for the "forced" root split only) the feature to split the root on
+    #[serde(default)]
+    pub root_feature: Option<usize>,
+    /// Policy for growing the trees, could be "first-to-fire" (split the first candidate node
+    /// whose stopping rule fires), "best-first" (split the valid rule with the largest empirical
+    /// edge among all candidate nodes), or "level-wise" (only split the shallowest leaves)
+    #[serde(default = "default_growth_policy")]
+    pub growth_policy: String,
+    /// Keep the statistics of the scanned examples after a split, and derive the histograms
+    /// of a child as the histograms of its parent minus the ones of its sibling, instead of
+    /// scanning new examples for the children. The statistics are reset instead if scanning
+    /// the examples again would cost more than twice the new examples scanned since the last
+    /// split.
+    #[serde(default)]
+    pub histogram_subtraction: bool,
+    /// Seed for the random number generators. Each component derives its own generator
+    /// from this seed, so runs with the same seed and the same numbers of threads draw
+    /// the same random numbers. The trained models may still differ slightly, because
+    /// the statistics of the examples are summed in parallel in a non-deterministic order.
+    #[serde(default)]
+    pub seed: u64,
 
     /// Maximum number of elements in the channel connecting scanner and sampler
     pub channel_size: usize,
@@ -76,25 +140,173 @@ pub struct Config {
     pub save_process: bool,
     /// Number of iterations between persisting models on disk
     pub save_interval: usize,
+    /// Format of the persisted models, `json` or `binary`
+    #[serde(default = "default_model_format")]
+    pub model_format: String,
+    /// Directory for persisting the models
+    #[serde(default = "default_model_dir")]
+    pub model_dir: String,
     /// Flag for activating debug mode
     pub debug_mode: bool,
 
     /// (for validation only) the file names of the models to run the validation
+    #[serde(default)]
     pub models_table_filename: String,
+    /// (for validation only) the directory of the models persisted by a training run, which are
+    /// validated incrementally in the order of their sizes instead of `models_table_filename`
+    #[serde(default)]
+    pub testing_model_dir: Option<String>,
     /// Flag indicating if models are trained incrementally
     pub incremental_testing: bool,
     /// Flag for validation mode, set to true to output raw scores of testing examples,
     /// and set to false for printing the validation scores but not raw scores
     pub testing_scores_only: bool,
+    /// Flag for writing the contribution of each feature to the scores of the testing examples
+    /// (only effective if `testing_scores_only` is true)
+    #[serde(default)]
+    pub testing_explain: bool,
+    /// Metrics for validating the models, e.g. `loss`, `auroc`, `precision@100`, `confusion@0.5`
+    #[serde(default = "default_testing_metrics")]
+    pub testing_metrics: Vec<String>,
+    /// File path for writing the performance of the models
+    #[serde(default = "default_testing_performance_filename")]
+    pub testing_performance_filename: String,
+    /// Format of the performance of the models, `csv` or `json` (JSON lines)
+    #[serde(default = "default_testing_performance_format")]
+    pub testing_performance_format: String,
+    /// Flag for writing the reliability diagram of the probabilities of each model
+    #[serde(default)]
+    pub testing_reliability_diagram: bool,
 
     /// Continous training from an interupted training process
     pub resume_training: bool,
+    /// File name for saving the statistics of the learner when the booster is interrupted,
+    /// which are reloaded when the scanner restarts (not saved if not set)
+    #[serde(default)]
+    pub learner_checkpoint_filename: Option<String>,
+    /// File name of a model trained by another library as the initial model (not used if
+    /// `resume_training` is true)
+    #[serde(default)]
+    pub init_model_filename: Option<String>,
+    /// Format of the initial model, `xgboost` (JSON dump) or `lightgbm` (text model)
+    #[serde(default = "default_init_model_format")]
+    pub init_model_format: String,
+    /// Margin added to all predictions of the initial model, which is not in the model file,
+    /// e.g. `log(base_score / (1 - base_score))` for the `base_score` of XGBoost
+    #[serde(default)]
+    pub init_model_base_margin: f32,
+
+    /// File path to the validation data for early stopping (early stopping is disabled if not set)
+    #[serde(default)]
+    pub validation_filename: Option<String>,
+    /// Number of validation examples
+    #[serde(default)]
+    pub num_validation_examples: usize,
+    /// Metric on the validation data for early stopping, see `testing_metrics`
+    #[serde(default = "default_early_stopping_metric")]
+    pub early_stopping_metric: String,
+    /// Number of trees without improvement on the validation data before stopping the training
+    #[serde(default = "default_early_stopping_rounds")]
+    pub early_stopping_rounds: usize,
+}
+
+
+fn default_feature_fraction() -> f32 {
+    1.0
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_stopping_rule() -> String {
+    "lil".to_string()
+}
+
+fn default_stopping_rule_delta() -> f32 {
+    0.000001
+}
+
+fn default_growth_policy() -> String {
+    "first-to-fire".to_string()
+}
+
+fn default_model_format() -> String {
+    "json".to_string()
+}
+
+fn default_model_dir() -> String {
+    "models".to_string()
+}
+
+fn default_init_model_format() -> String {
+    "xgboost".to_string()
+}
+
+fn default_testing_metrics() -> Vec<String> {
+    vec!["loss", "auprc", "auroc", "error-rate"].iter().map(|t| t.to_string()).collect()
+}
+
+fn default_testing_performance_filename() -> String {
+    "models/performance.csv".to_string()
+}
+
+fn default_testing_performance_format() -> String {
+    "csv".to_string()
+}
+
+fn default_early_stopping_metric() -> String {
+    "loss".to_string()
+}
+
+fn default_early_stopping_rounds() -> usize {
+    20
+}
+
+fn default_root_split() -> String {
+    "random-median".to_string()
 }
 
 
 impl Config {
     pub fn new(config_filepath: &String) -> Config {
-        serde_yaml::from_reader(create_bufreader(config_filepath)).unwrap()
+        let config: Config = serde_yaml::from_reader(create_bufreader(config_filepath)).unwrap();
+        // the metrics are checked before the training or the testing starts
+        config.get_testing_metrics();
+        config.get_early_stopping_metric();
+        config.get_root_split();
+        config
+    }
+
+    /// Parse `testing_metrics`, panics if a metric is not recognized
+    pub fn get_testing_metrics(&self) -> Vec<Metric> {
+        self.testing_metrics.iter().map(|metric| {
+            Metric::new(metric).expect(&format!("Unrecognized metric `{}` in `testing_metrics`",
+                                                metric))
+        }).collect()
+    }
+
+    /// Parse `early_stopping_metric`, panics if the metric is not recognized or if it has
+    /// more than one value
+    pub fn get_early_stopping_metric(&self) -> Metric {
+        let metric = Metric::new(&self.early_stopping_metric).expect(&format!(
+            "Unrecognized metric `{}` in `early_stopping_metric`", self.early_stopping_metric));
+        assert!(metric.get_names().len() == 1,
+                "Early stopping requires a metric with a single value, but `{}` is given",
+                self.early_stopping_metric);
+        metric
+    }
+
+    /// Parse `root_split`, panics if `root_feature` is missing or out of range for
+    /// the forced root split
+    pub fn get_root_split(&self) -> RootSplit {
+        let root_split = RootSplit::new(&self.root_split, self.root_feature);
+        if let RootSplit::Forced(feature) = root_split {
+            assert!(feature < self.num_features,
+                    "`root_feature` {} is out of range, there are {} features",
+                    feature, self.num_features);
+        }
+        root_split
     }
 }
 
@@ -119,3 +331,88 @@ impl SampleMode {
         }
     }
 }
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RootSplit {
+    RandomMedian,
+    Learned,
+    Forced(usize),
+}
+
+impl RootSplit {
+    pub fn new(root_split: &String, root_feature: Option<usize>) -> RootSplit {
+        match root_split.to_lowercase().as_str() {
+            "random-median" => RootSplit::RandomMedian,
+            "learned"       => RootSplit::Learned,
+            "forced"        => {
+                RootSplit::Forced(
+                    root_feature.expect("`root_feature` must be set for the forced root split"))
+            },
+            _               => {
+                error!("Unrecognized root split strategy. Use random-median by default.");
+                RootSplit::RandomMedian
+            }
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GrowthPolicy {
+    FirstToFire,
+    BestFirst,
+    LevelWise,
+}
+
+impl GrowthPolicy {
+    pub fn new(growth_policy: &String) -> GrowthPolicy {
+        match growth_policy.to_lowercase().as_str() {
+            "first-to-fire" => GrowthPolicy::FirstToFire,
+            "best-first"    => GrowthPolicy::BestFirst,
+            "level-wise"    => GrowthPolicy::LevelWise,
+            _               => {
+                error!("Unrecognized growth policy. Use first-to-fire by default.");
+                GrowthPolicy::FirstToFire
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use commons::test_helper::get_test_config;
+    use super::RootSplit;
+
+    #[test]
+    fn test_root_split() {
+        let mut config = get_test_config(5);
+        assert_eq!(config.get_root_split(), RootSplit::RandomMedian);
+        config.root_split = "learned".to_string();
+        assert_eq!(config.get_root_split(), RootSplit::Learned);
+        config.root_split = "Forced".to_string();
+        config.root_feature = Some(4);
+        assert_eq!(config.get_root_split(), RootSplit::Forced(4));
+        // `root_feature` is ignored by the other strategies
+        config.root_split = "random-median".to_string();
+        assert_eq!(config.get_root_split(), RootSplit::RandomMedian);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_forced_root_split_without_feature() {
+        let mut config = get_test_config(5);
+        config.root_split = "forced".to_string();
+        config.get_root_split();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_forced_root_split_out_of_range() {
+        let mut config = get_test_config(5);
+        config.root_split = "forced".to_string();
+        config.root_feature = Some(5);
+        config.get_root_split();
+    }
+}
@@ -0,0 +1,98 @@
+use commons::model_format::ModelHeader;
+use commons::persistent_io::read_model_with_header;
+use commons::persistent_io::write_model_to_file;
+
+
+/// The operations on the persisted models
+pub enum EditOperation {
+    /// keep the first `n` trees
+    Truncate(usize),
+    /// keep the trees in the range `[start, end)`
+    Slice(usize, usize),
+    /// append the trees of the model persisted in the file, and skip the check of the bins
+    /// if the flag is set
+    Merge(String, bool),
+    /// multiply the predictions of all trees by the factor
+    Rescale(f32),
+}
+
+
+/// Apply `operation` to the model persisted in `model_filename`, and write the result to
+/// `output_filename` in the same format. The header of a binary model is kept, so the result
+/// is still checked against the bins that the model was trained on.
+pub fn edit(model_filename: &String, output_filename: &String, operation: &EditOperation) {
+    let (timestamp, _, mut model, header) = read_model_with_header(model_filename);
+    match operation {
+        EditOperation::Truncate(size) => model.truncate(*size),
+        EditOperation::Slice(start, end) => model = model.slice(*start, *end),
+        EditOperation::Merge(other_filename, force) => {
+            let (_, _, other, other_header) = read_model_with_header(other_filename);
+            check_same_bins(&header, &other_header, other_filename, *force);
+            model.merge(&other);
+        },
+        EditOperation::Rescale(factor) => model.rescale(*factor),
+    }
+    info!("Write the model with {} trees to `{}`", model.size(), output_filename);
+    write_model_to_file(output_filename, &model, timestamp, header.as_ref());
+}
+
+
+// The bins can only be compared if both models are in the binary format, so the models are
+// merged only if both headers match, unless `force` is set
+fn check_same_bins(
+    header: &Option<ModelHeader>, other_header: &Option<ModelHeader>, other_filename: &String,
+    force: bool,
+) {
+    let error = match (header, other_header) {
+        (Some(header), Some(other_header)) => {
+            if header.num_features == other_header.num_features &&
+                    header.bins_hash == other_header.bins_hash {
+                return;
+            }
+            format!("The model in `{}` was trained on different bins", other_filename)
+        },
+        _ => {
+            "Cannot verify that the models were trained on the same bins, since the bins are \
+             only recorded in the binary format".to_string()
+        },
+    };
+    if force {
+        warn!("{}, merging anyway since the check is skipped", error);
+    } else {
+        panic!("{}. Use `--force` to merge anyway.", error);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use commons::model_format::ModelHeader;
+    use commons::test_helper::get_synthetic_bins;
+    use super::check_same_bins;
+
+    #[test]
+    fn test_check_same_bins() {
+        let header = Some(ModelHeader::new(&get_synthetic_bins(10, 8)));
+        let filename = "other.bin".to_string();
+        check_same_bins(&header, &Some(ModelHeader::new(&get_synthetic_bins(10, 8))), &filename,
+                        false);
+        check_same_bins(&header, &None, &filename, true);
+        check_same_bins(&header, &Some(ModelHeader::new(&get_synthetic_bins(10, 9))), &filename,
+                        true);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_different_bins() {
+        check_same_bins(&Some(ModelHeader::new(&get_synthetic_bins(10, 8))),
+                        &Some(ModelHeader::new(&get_synthetic_bins(10, 9))),
+                        &"other.bin".to_string(), false);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_without_header() {
+        check_same_bins(&None, &Some(ModelHeader::new(&get_synthetic_bins(10, 8))),
+                        &"other.bin".to_string(), false);
+    }
+}
@@ -0,0 +1,225 @@
+use commons::bins::Bins;
+use commons::model::Model;
+use commons::tree::Tree;
+
+
+/*
+The trees are compiled into nested if/else statements on the raw feature values, one function
+for each tree, and `sparrow_predict` sums the predictions of all trees.
+
+An example goes to the left child of a node if its bin index is not larger than the threshold
+`j` of the node, i.e. if its value is smaller than `vals[j + 1]` of the bins of the feature.
+If `j` is the last bin, all examples go to the left child, and the comparison is omitted.
+The float literals are printed with the shortest representation that parses back to the same
+`f32` value, so the generated code gives exactly the same predictions. The non-finite values
+are printed as the constants `f32::INFINITY` and `f32::NAN` in Rust, and `INFINITY` and `NAN`
+from `math.h` in C.
+*/
+#[derive(Clone, Copy)]
+pub enum Language {
+    Rust,
+    C,
+}
+
+const INDENT: &str = "    ";
+
+
+/// Generate the source code of a function that computes the score of the model on
+/// the raw feature values, i.e. `pub fn sparrow_predict(x: &[f32]) -> f32` in Rust, or
+/// `float sparrow_predict(const float* x)` in C.
+pub fn to_source(model: &Model, bins: &Vec<Bins>, language: Language) -> String {
+    let mut lines = vec![match language {
+        Language::Rust => "// Generated by sparrow. Do not edit.".to_string(),
+        Language::C    => "/* Generated by sparrow. Do not edit. */".to_string(),
+    }];
+    if let Language::C = language {
+        lines.push("".to_string());
+        lines.push("#include <math.h>".to_string());
+    }
+    model.get_trees().iter().enumerate().for_each(|(index, tree)| {
+        lines.push("".to_string());
+        lines.push(match language {
+            Language::Rust => format!("fn sparrow_tree_{}(x: &[f32]) -> f32 {{", index),
+            Language::C    => format!("static float sparrow_tree_{}(const float* x) {{", index),
+        });
+        write_node(tree, bins, 0, 1, language, &mut lines);
+        lines.push("}".to_string());
+    });
+    lines.push("".to_string());
+    match language {
+        Language::Rust => {
+            lines.push("pub fn sparrow_predict(x: &[f32]) -> f32 {".to_string());
+            lines.push(format!("{}let mut score = 0.0;", INDENT));
+        },
+        Language::C    => {
+            lines.push("float sparrow_predict(const float* x) {".to_string());
+            lines.push(format!("{}float score = 0.0f;", INDENT));
+        },
+    }
+    (0..model.size()).for_each(|index| {
+        lines.push(format!("{}score += sparrow_tree_{}(x);", INDENT, index));
+    });
+    lines.push(match language {
+        Language::Rust => format!("{}score", INDENT),
+        Language::C    => format!("{}return score;", INDENT),
+    });
+    lines.push("}".to_string());
+    lines.push("".to_string());
+    lines.join("\n")
+}
+
+
+fn write_node(
+    tree: &Tree, bins: &Vec<Bins>, index: usize, depth: usize, language: Language,
+    lines: &mut Vec<String>,
+) {
+    let indent = INDENT.repeat(depth);
+    match tree.get_split(index) {
+        None => {
+            let predict = format_float(tree.get_predict(index), language);
+            lines.push(match language {
+                Language::Rust => format!("{}{}", indent, predict),
+                Language::C    => format!("{}return {};", indent, predict),
+            });
+        },
+        Some((feature, threshold, left, right)) => {
+            match bins[feature].get_vals().get(threshold as usize + 1) {
+                None => write_node(tree, bins, left, depth, language, lines),
+                Some(threshold) => {
+                    let threshold = format_float(*threshold, language);
+                    lines.push(match language {
+                        Language::Rust => format!("{}if x[{}] < {} {{", indent, feature, threshold),
+                        Language::C    => format!("{}if (x[{}] < {}) {{", indent, feature, threshold),
+                    });
+                    write_node(tree, bins, left, depth + 1, language, lines);
+                    lines.push(format!("{}}} else {{", indent));
+                    write_node(tree, bins, right, depth + 1, language, lines);
+                    lines.push(format!("{}}}", indent));
+                },
+            }
+        },
+    }
+}
+
+
+fn format_float(val: f32, language: Language) -> String {
+    // `Debug` prints the shortest representation that parses back to `val`, e.g. `1.0`, `1e-7`,
+    // but `inf` and `NaN` are not valid literals
+    if val.is_nan() {
+        return match language {
+            Language::Rust => "f32::NAN".to_string(),
+            Language::C    => "NAN".to_string(),
+        };
+    }
+    if val.is_infinite() {
+        let sign = if val < 0.0 { "-" } else { "" };
+        return match language {
+            Language::Rust => format!("{}f32::INFINITY", sign),
+            Language::C    => format!("{}INFINITY", sign),
+        };
+    }
+    let repr = format!("{:?}", val);
+    match language {
+        Language::Rust => repr,
+        Language::C    => repr + "f",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use std::env;
+    use std::f32;
+
+    use commons::bins::Bins;
+    use commons::io::create_bufreader;
+    use commons::io::read_k_labeled_data;
+    use commons::io::write_all;
+    use commons::labeled_data::LabeledData;
+    use commons::model::Model;
+    use commons::tree::Tree;
+    use super::Language;
+    use super::format_float;
+    use super::to_source;
+
+    // The golden outputs are compiled into the tests, and are regenerated by running the tests
+    // with the environment variable `SPARROW_UPDATE_GOLDEN` set
+    mod golden {
+        include!("../../tests/data/codegen/model.rs");
+    }
+    const GOLDEN_RUST: &str = include_str!("../../tests/data/codegen/model.rs");
+    const GOLDEN_C: &str = include_str!("../../tests/data/codegen/model.c");
+
+    fn get_bins(num_features: usize) -> Vec<Bins> {
+        let bins = "{\"size\": 4, \"vals\": [0.0, 2.5, 5.0, 10.0]}";
+        (0..num_features).map(|_| serde_json::from_str(bins).unwrap()).collect()
+    }
+
+    fn get_model() -> Model {
+        let mut model = Model::new();
+        let mut tree = Tree::new(3);
+        let (left, right) = tree.split(0, 4, 2, -0.3, 0.3);
+        tree.split(left, 1, 0, 0.1, -0.2);
+        // all examples go to the left child
+        tree.split(right, 5, 3, 0.05, 0.0);
+        model.append(tree);
+        let mut tree = Tree::new(1);
+        tree.split(0, 2, 1, 0.25, -0.125);
+        model.append(tree);
+        model
+    }
+
+    #[test]
+    fn test_codegen_golden() {
+        let num_features = 6;
+        let (model, bins) = (get_model(), get_bins(num_features));
+        let rust = to_source(&model, &bins, Language::Rust);
+        let c = to_source(&model, &bins, Language::C);
+        if env::var("SPARROW_UPDATE_GOLDEN").is_ok() {
+            write_all(&"tests/data/codegen/model.rs".to_string(), rust.as_bytes()).unwrap();
+            write_all(&"tests/data/codegen/model.c".to_string(), c.as_bytes()).unwrap();
+            return;
+        }
+        assert_eq!(rust, GOLDEN_RUST);
+        assert_eq!(c, GOLDEN_C);
+
+        // the predictions of the golden output match the model on the sample data, and on
+        // the boundaries of the bins
+        let mut reader = create_bufreader(&String::from("tests/data/sample_libsvm.txt"));
+        let mut examples: Vec<LabeledData<f32, f32>> =
+            read_k_labeled_data(&mut reader, 2, 0.0, num_features, &"1.2".to_string());
+        vec![-1.0, 0.0, 2.5, 5.0, 10.0, 20.0].into_iter().for_each(|val| {
+            examples.push(LabeledData::new(vec![val; num_features], 1.0));
+        });
+        examples.iter().for_each(|example| {
+            let binned = LabeledData::new(
+                example.feature.iter().enumerate()
+                                      .map(|(k, val)| bins[k].get_split_index(*val))
+                                      .collect(),
+                1);
+            assert_eq!(golden::sparrow_predict(&example.feature),
+                       model.get_prediction(&binned, 0).0);
+        });
+    }
+    #[test]
+    fn test_format_non_finite() {
+        assert_eq!(format_float(f32::INFINITY, Language::Rust), "f32::INFINITY");
+        assert_eq!(format_float(f32::NEG_INFINITY, Language::Rust), "-f32::INFINITY");
+        assert_eq!(format_float(f32::NAN, Language::Rust), "f32::NAN");
+        assert_eq!(format_float(f32::INFINITY, Language::C), "INFINITY");
+        assert_eq!(format_float(f32::NEG_INFINITY, Language::C), "-INFINITY");
+        assert_eq!(format_float(f32::NAN, Language::C), "NAN");
+        assert_eq!(format_float(-0.5, Language::C), "-0.5f");
+
+        let mut model = Model::new();
+        let mut tree = Tree::new(1);
+        tree.split(0, 0, 0, f32::NEG_INFINITY, f32::NAN);
+        model.append(tree);
+        let rust = to_source(&model, &get_bins(1), Language::Rust);
+        assert!(rust.contains("        -f32::INFINITY\n") && rust.contains("        f32::NAN\n"));
+        let c = to_source(&model, &get_bins(1), Language::C);
+        assert!(c.contains("return -INFINITY;") && c.contains("return NAN;"));
+    }
+}
@@ -0,0 +1,52 @@
+/// Exporting models to the ONNX format
+pub mod onnx;
+/// Compiling models into the Rust or C source code
+pub mod codegen;
+
+use commons::io::write_all;
+use commons::persistent_io::read_bins_disk;
+use commons::persistent_io::read_model_from_file;
+use self::codegen::Language;
+use self::codegen::to_source;
+use self::onnx::OnnxModelType;
+use self::onnx::to_onnx;
+
+
+/// The formats that the models can be exported to
+pub enum ExportFormat {
+    OnnxClassifier,
+    OnnxRegressor,
+    Rust,
+    C,
+}
+
+impl ExportFormat {
+    pub fn new(format: &String) -> Option<ExportFormat> {
+        match format.to_lowercase().as_str() {
+            "onnx-classifier" => Some(ExportFormat::OnnxClassifier),
+            "onnx-regressor"  => Some(ExportFormat::OnnxRegressor),
+            "rust"            => Some(ExportFormat::Rust),
+            "c"               => Some(ExportFormat::C),
+            _                 => {
+                error!("Unrecognized export format `{}`.", format);
+                None
+            },
+        }
+    }
+}
+
+
+/// Export the model persisted in `model_filename` to `output_filename`. The bins are read from
+/// `models/bins.json` for mapping the thresholds back to the raw feature values.
+pub fn export(model_filename: &String, output_filename: &String, format: &ExportFormat) {
+    let (_, _, model) = read_model_from_file(model_filename);
+    let bins = read_bins_disk();
+    let output = match format {
+        ExportFormat::OnnxClassifier => to_onnx(&model, &bins, &OnnxModelType::Classifier),
+        ExportFormat::OnnxRegressor  => to_onnx(&model, &bins, &OnnxModelType::Regressor),
+        ExportFormat::Rust           => to_source(&model, &bins, Language::Rust).into_bytes(),
+        ExportFormat::C              => to_source(&model, &bins, Language::C).into_bytes(),
+    };
+    write_all(output_filename, &output)
+        .expect(&format!("Cannot write the exported model to `{}`", output_filename));
+}
@@ -0,0 +1,565 @@
+use std::f32;
+
+use commons::bins::Bins;
+use commons::model::Model;
+
+
+/*
+The model is exported as an ONNX model with a single `TreeEnsembleRegressor` or
+`TreeEnsembleClassifier` node from the `ai.onnx.ml` domain, which is encoded in the protobuf
+format directly.
+
+The features of the input `X` are the raw feature values. An example goes to the left child of
+a node if its bin index is not larger than the threshold `j` of the node, i.e. if its value
+is smaller than `vals[j + 1]` of the bins of the feature, so the nodes use the mode `BRANCH_LT`
+with the threshold `vals[j + 1]` (or infinity if `j` is the last bin).
+
+The prediction of a leaf is the sum of the predictions on the path from the root, so
+the weight of a leaf in the ensemble is its prediction, and the output of the regressor is
+the score of the model. The classifier has the labels `[-1, 1]` with the scores
+`[-score, score]`.
+*/
+const IR_VERSION:   i64 = 7;
+const ONNX_OPSET:   i64 = 13;
+const ML_DOMAIN:    &str = "ai.onnx.ml";
+const ML_OPSET:     i64 = 1;
+const PRODUCER:     &str = "sparrow";
+
+// Data types of the tensors
+const TENSOR_FLOAT: i64 = 1;
+const TENSOR_INT64: i64 = 7;
+
+// Types of the attributes
+const ATTR_INT:     i64 = 2;
+const ATTR_STRING:  i64 = 3;
+const ATTR_FLOATS:  i64 = 6;
+const ATTR_INTS:    i64 = 7;
+const ATTR_STRINGS: i64 = 8;
+
+
+pub enum OnnxModelType {
+    Classifier,
+    Regressor,
+}
+
+
+/// Encode the model in the ONNX format. `bins` are used for mapping the thresholds of the splits
+/// back to the raw feature values.
+pub fn to_onnx(model: &Model, bins: &Vec<Bins>, model_type: &OnnxModelType) -> Vec<u8> {
+    let mut node = ProtoWriter::new();
+    node.string(1, "X");
+    let op_type = match model_type {
+        OnnxModelType::Classifier => {
+            node.string(2, "label");
+            node.string(2, "probabilities");
+            "TreeEnsembleClassifier"
+        },
+        OnnxModelType::Regressor => {
+            node.string(2, "variable");
+            "TreeEnsembleRegressor"
+        },
+    };
+    node.string(3, op_type);
+    node.string(4, op_type);
+    node.string(7, ML_DOMAIN);
+    get_attributes(model, bins, model_type).into_iter().for_each(|attr| node.message(5, attr));
+
+    let mut graph = ProtoWriter::new();
+    graph.message(1, node);
+    graph.string(2, PRODUCER);
+    graph.message(11, get_value_info("X", TENSOR_FLOAT, &[None, Some(bins.len() as i64)]));
+    match model_type {
+        OnnxModelType::Classifier => {
+            graph.message(12, get_value_info("label", TENSOR_INT64, &[None]));
+            graph.message(12, get_value_info("probabilities", TENSOR_FLOAT, &[None, Some(2)]));
+        },
+        OnnxModelType::Regressor => {
+            graph.message(12, get_value_info("variable", TENSOR_FLOAT, &[None, Some(1)]));
+        },
+    }
+
+    let mut onnx_model = ProtoWriter::new();
+    onnx_model.int(1, IR_VERSION);
+    onnx_model.string(2, PRODUCER);
+    onnx_model.string(3, env!("CARGO_PKG_VERSION"));
+    onnx_model.message(7, graph);
+    let mut opset = ProtoWriter::new();
+    opset.int(2, ONNX_OPSET);
+    onnx_model.message(8, opset);
+    let mut opset = ProtoWriter::new();
+    opset.string(1, ML_DOMAIN);
+    opset.int(2, ML_OPSET);
+    onnx_model.message(8, opset);
+    onnx_model.buf
+}
+
+
+fn get_attributes(model: &Model, bins: &Vec<Bins>, model_type: &OnnxModelType) -> Vec<ProtoWriter> {
+    let mut tree_ids = vec![];
+    let mut node_ids = vec![];
+    let mut features = vec![];
+    let mut thresholds = vec![];
+    let mut modes = vec![];
+    let mut true_node_ids = vec![];
+    let mut false_node_ids = vec![];
+    // (tree id, node id, prediction) of the leaves
+    let mut leaves = vec![];
+    model.get_trees().iter().enumerate().for_each(|(tree_index, tree)| {
+        (0..tree.num_nodes).for_each(|index| {
+            tree_ids.push(tree_index as i64);
+            node_ids.push(index as i64);
+            match tree.get_split(index) {
+                Some((feature, threshold, left, right)) => {
+                    let threshold = bins[feature].get_vals()
+                                                 .get(threshold as usize + 1)
+                                                 .map(|t| *t)
+                                                 .unwrap_or(f32::INFINITY);
+                    features.push(feature as i64);
+                    thresholds.push(threshold);
+                    modes.push("BRANCH_LT");
+                    true_node_ids.push(left as i64);
+                    false_node_ids.push(right as i64);
+                },
+                None => {
+                    features.push(0);
+                    thresholds.push(0.0);
+                    modes.push("LEAF");
+                    true_node_ids.push(0);
+                    false_node_ids.push(0);
+                    leaves.push((tree_index as i64, index as i64, tree.get_predict(index)));
+                },
+            }
+        });
+    });
+
+    let mut attrs = vec![
+        get_ints_attr("nodes_treeids", &tree_ids),
+        get_ints_attr("nodes_nodeids", &node_ids),
+        get_ints_attr("nodes_featureids", &features),
+        get_floats_attr("nodes_values", &thresholds),
+        get_strings_attr("nodes_modes", &modes),
+        get_ints_attr("nodes_truenodeids", &true_node_ids),
+        get_ints_attr("nodes_falsenodeids", &false_node_ids),
+        get_string_attr("post_transform", "NONE"),
+    ];
+    match model_type {
+        OnnxModelType::Classifier => {
+            // each leaf has the weights of both classes
+            let tree_ids: Vec<i64> = leaves.iter().flat_map(|t| vec![t.0, t.0]).collect();
+            let node_ids: Vec<i64> = leaves.iter().flat_map(|t| vec![t.1, t.1]).collect();
+            let class_ids: Vec<i64> = leaves.iter().flat_map(|_| vec![0, 1]).collect();
+            let weights: Vec<f32> = leaves.iter().flat_map(|t| vec![-t.2, t.2]).collect();
+            attrs.push(get_ints_attr("classlabels_int64s", &[-1, 1]));
+            attrs.push(get_ints_attr("class_treeids", &tree_ids));
+            attrs.push(get_ints_attr("class_nodeids", &node_ids));
+            attrs.push(get_ints_attr("class_ids", &class_ids));
+            attrs.push(get_floats_attr("class_weights", &weights));
+        },
+        OnnxModelType::Regressor => {
+            let tree_ids: Vec<i64> = leaves.iter().map(|t| t.0).collect();
+            let node_ids: Vec<i64> = leaves.iter().map(|t| t.1).collect();
+            let weights: Vec<f32> = leaves.iter().map(|t| t.2).collect();
+            attrs.push(get_int_attr("n_targets", 1));
+            attrs.push(get_string_attr("aggregate_function", "SUM"));
+            attrs.push(get_ints_attr("target_treeids", &tree_ids));
+            attrs.push(get_ints_attr("target_nodeids", &node_ids));
+            attrs.push(get_ints_attr("target_ids", &vec![0; leaves.len()]));
+            attrs.push(get_floats_attr("target_weights", &weights));
+        },
+    }
+    attrs
+}
+
+
+// `dims` are the sizes of the dimensions, or `None` if the size is not fixed
+fn get_value_info(name: &str, elem_type: i64, dims: &[Option<i64>]) -> ProtoWriter {
+    let mut shape = ProtoWriter::new();
+    dims.iter().for_each(|dim| {
+        let mut dimension = ProtoWriter::new();
+        match dim {
+            Some(size) => dimension.int(1, *size),
+            None       => dimension.string(2, "N"),
+        }
+        shape.message(1, dimension);
+    });
+    let mut tensor_type = ProtoWriter::new();
+    tensor_type.int(1, elem_type);
+    tensor_type.message(2, shape);
+    let mut type_proto = ProtoWriter::new();
+    type_proto.message(1, tensor_type);
+    let mut value_info = ProtoWriter::new();
+    value_info.string(1, name);
+    value_info.message(2, type_proto);
+    value_info
+}
+
+
+fn get_int_attr(name: &str, val: i64) -> ProtoWriter {
+    let mut attr = ProtoWriter::new();
+    attr.string(1, name);
+    attr.int(3, val);
+    attr.int(20, ATTR_INT);
+    attr
+}
+
+fn get_string_attr(name: &str, val: &str) -> ProtoWriter {
+    let mut attr = ProtoWriter::new();
+    attr.string(1, name);
+    attr.string(4, val);
+    attr.int(20, ATTR_STRING);
+    attr
+}
+
+fn get_ints_attr(name: &str, vals: &[i64]) -> ProtoWriter {
+    let mut attr = ProtoWriter::new();
+    attr.string(1, name);
+    attr.packed_ints(8, vals);
+    attr.int(20, ATTR_INTS);
+    attr
+}
+
+fn get_floats_attr(name: &str, vals: &[f32]) -> ProtoWriter {
+    let mut attr = ProtoWriter::new();
+    attr.string(1, name);
+    attr.packed_floats(7, vals);
+    attr.int(20, ATTR_FLOATS);
+    attr
+}
+
+fn get_strings_attr(name: &str, vals: &[&str]) -> ProtoWriter {
+    let mut attr = ProtoWriter::new();
+    attr.string(1, name);
+    vals.iter().for_each(|val| attr.string(9, val));
+    attr.int(20, ATTR_STRINGS);
+    attr
+}
+
+
+/// A minimal encoder of the protobuf messages
+struct ProtoWriter {
+    buf: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn new() -> ProtoWriter {
+        ProtoWriter {
+            buf: vec![],
+        }
+    }
+
+    fn varint(&mut self, mut val: u64) {
+        while val >= 0x80 {
+            self.buf.push((val as u8) | 0x80);
+            val >>= 7;
+        }
+        self.buf.push(val as u8);
+    }
+
+    fn tag(&mut self, field: u64, wire_type: u64) {
+        self.varint((field << 3) | wire_type);
+    }
+
+    fn int(&mut self, field: u64, val: i64) {
+        self.tag(field, 0);
+        self.varint(val as u64);
+    }
+
+    fn bytes(&mut self, field: u64, val: &[u8]) {
+        self.tag(field, 2);
+        self.varint(val.len() as u64);
+        self.buf.extend_from_slice(val);
+    }
+
+    fn string(&mut self, field: u64, val: &str) {
+        self.bytes(field, val.as_bytes());
+    }
+
+    fn message(&mut self, field: u64, val: ProtoWriter) {
+        self.bytes(field, &val.buf);
+    }
+
+    fn packed_ints(&mut self, field: u64, vals: &[i64]) {
+        if vals.is_empty() {
+            return;
+        }
+        let mut packed = ProtoWriter::new();
+        vals.iter().for_each(|val| packed.varint(*val as u64));
+        self.message(field, packed);
+    }
+
+    fn packed_floats(&mut self, field: u64, vals: &[f32]) {
+        if vals.is_empty() {
+            return;
+        }
+        let mut packed = ProtoWriter::new();
+        vals.iter().for_each(|val| {
+            let bits = val.to_bits();
+            (0..4).for_each(|k| packed.buf.push((bits >> (8 * k)) as u8));
+        });
+        self.message(field, packed);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand::StdRng;
+    use serde_json;
+
+    use std::collections::HashMap;
+    use std::env;
+    use std::path::Path;
+
+    use commons::bins::Bins;
+    use commons::io::create_bufreader;
+    use commons::io::raw_read_all;
+    use commons::io::read_all;
+    use commons::io::read_k_labeled_data;
+    use commons::io::write_all;
+    use commons::labeled_data::LabeledData;
+    use commons::model::Model;
+    use commons::tree::Tree;
+    use super::OnnxModelType;
+    use super::to_onnx;
+
+    enum Value<'a> {
+        Varint,
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(buf: &[u8], index: &mut usize) -> u64 {
+        let mut ret = 0;
+        let mut shift = 0;
+        loop {
+            let byte = buf[*index];
+            *index += 1;
+            ret |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte < 0x80 {
+                return ret;
+            }
+        }
+    }
+
+    // decode the fields of a message, which only has the wire types used by the exporter
+    fn decode(buf: &[u8]) -> Vec<(u64, Value<'_>)> {
+        let mut index = 0;
+        let mut ret = vec![];
+        while index < buf.len() {
+            let tag = read_varint(buf, &mut index);
+            let value = match tag & 7 {
+                0 => {
+                    read_varint(buf, &mut index);
+                    Value::Varint
+                },
+                2 => {
+                    let len = read_varint(buf, &mut index) as usize;
+                    index += len;
+                    Value::Bytes(&buf[index - len..index])
+                },
+                _ => panic!("unexpected wire type"),
+            };
+            ret.push((tag >> 3, value));
+        }
+        ret
+    }
+
+    fn get_bytes<'a>(buf: &'a [u8], field: u64) -> Vec<&'a [u8]> {
+        decode(buf).into_iter().filter_map(|(k, v)| match v {
+            Value::Bytes(t) if k == field => Some(t),
+            _                             => None,
+        }).collect()
+    }
+
+    struct Attributes<'a>(HashMap<String, &'a [u8]>);
+
+    impl<'a> Attributes<'a> {
+        fn new(node: &'a [u8]) -> Attributes<'a> {
+            Attributes(get_bytes(node, 5).into_iter().map(|attr| {
+                (String::from_utf8(get_bytes(attr, 1)[0].to_vec()).unwrap(), attr)
+            }).collect())
+        }
+
+        fn ints(&self, name: &str) -> Vec<i64> {
+            let packed = get_bytes(self.0[name], 8)[0];
+            let mut index = 0;
+            let mut ret = vec![];
+            while index < packed.len() {
+                ret.push(read_varint(packed, &mut index) as i64);
+            }
+            ret
+        }
+
+        fn floats(&self, name: &str) -> Vec<f32> {
+            get_bytes(self.0[name], 7)[0].chunks(4).map(|t| {
+                f32::from_bits(
+                    (t[0] as u32) | (t[1] as u32) << 8 | (t[2] as u32) << 16 | (t[3] as u32) << 24)
+            }).collect()
+        }
+
+        fn strings(&self, name: &str) -> Vec<String> {
+            get_bytes(self.0[name], 9).into_iter()
+                                      .map(|t| String::from_utf8(t.to_vec()).unwrap())
+                                      .collect()
+        }
+    }
+
+    // evaluate the tree ensemble following the semantics of the ONNX operators, and
+    // return the scores of each target (or class)
+    fn evaluate(onnx: &[u8], weights_prefix: &str, features: &[f32]) -> Vec<f32> {
+        let graph = get_bytes(onnx, 7)[0];
+        let node = get_bytes(graph, 1)[0];
+        let attrs = Attributes::new(node);
+        let (tree_ids, node_ids) = (attrs.ints("nodes_treeids"), attrs.ints("nodes_nodeids"));
+        let modes = attrs.strings("nodes_modes");
+        let (feature_ids, values) = (attrs.ints("nodes_featureids"), attrs.floats("nodes_values"));
+        let true_ids = attrs.ints("nodes_truenodeids");
+        let false_ids = attrs.ints("nodes_falsenodeids");
+        let find = |tree: i64, node: i64| {
+            (0..tree_ids.len()).find(|k| tree_ids[*k] == tree && node_ids[*k] == node).unwrap()
+        };
+        let weight_tree_ids = attrs.ints(&format!("{}_treeids", weights_prefix));
+        let weight_node_ids = attrs.ints(&format!("{}_nodeids", weights_prefix));
+        let weight_ids = attrs.ints(&format!("{}_ids", weights_prefix));
+        let weights = attrs.floats(&format!("{}_weights", weights_prefix));
+        let mut scores = vec![0.0; 2];
+        let mut num_trees = tree_ids.iter().max().map(|t| t + 1).unwrap_or(0);
+        while num_trees > 0 {
+            num_trees -= 1;
+            let mut k = find(num_trees, 0);
+            while modes[k] != "LEAF" {
+                assert_eq!(modes[k], "BRANCH_LT");
+                let next = if features[feature_ids[k] as usize] < values[k] {
+                    true_ids[k]
+                } else {
+                    false_ids[k]
+                };
+                k = find(num_trees, next);
+            }
+            (0..weights.len()).for_each(|j| {
+                if weight_tree_ids[j] == num_trees && weight_node_ids[j] == node_ids[k] {
+                    scores[weight_ids[j] as usize] += weights[j];
+                }
+            });
+        }
+        scores
+    }
+
+    // a random model, and the examples from the sample data and on the boundaries of the bins
+    fn get_model_and_examples() -> (Model, Vec<Bins>, Vec<LabeledData<f32, f32>>) {
+        let num_features = 6;
+        let mut reader = create_bufreader(&String::from("tests/data/sample_libsvm.txt"));
+        let mut examples: Vec<LabeledData<f32, f32>> =
+            read_k_labeled_data(&mut reader, 2, 0.0, num_features, &"1.2".to_string());
+        // the thresholds of the bins are the values in the data and their mid-points
+        let bins: Vec<Bins> = (0..num_features).map(|k| {
+            let mut vals: Vec<f32> = examples.iter().map(|t| t.feature[k]).collect();
+            let mid_points: Vec<f32> = vals.iter().map(|t| t + 0.5).collect();
+            vals.extend(mid_points);
+            vals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            vals.dedup();
+            let json = format!("{{\"size\": {}, \"vals\": {:?}}}", vals.len(), vals);
+            serde_json::from_str(&json).unwrap()
+        }).collect();
+        // examples on the boundaries of the bins
+        let mut rng = StdRng::from_seed(&[0]);
+        (0..20).for_each(|_| {
+            let features = bins.iter().map(|bin| {
+                bin.get_vals()[rng.gen_range(0, bin.len())]
+            }).collect();
+            examples.push(LabeledData::new(features, 1.0));
+        });
+
+        let mut model = Model::new();
+        (0..10).for_each(|_| {
+            let mut tree = Tree::new(4);
+            (0..4).for_each(|_| {
+                let leaves: Vec<usize> = (0..tree.num_nodes).filter(|k| tree.is_leaf(*k)).collect();
+                let feature = rng.gen_range(0, num_features);
+                tree.split(
+                    leaves[rng.gen_range(0, leaves.len())], feature,
+                    rng.gen_range(0, bins[feature].len()) as u8,
+                    rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5,
+                );
+            });
+            model.append(tree);
+        });
+        (model, bins, examples)
+    }
+
+    fn get_expected(model: &Model, bins: &Vec<Bins>, example: &LabeledData<f32, f32>) -> f32 {
+        let binned = LabeledData::new(
+            example.feature.iter().enumerate()
+                                  .map(|(k, val)| bins[k].get_split_index(*val))
+                                  .collect(),
+            1);
+        model.get_prediction(&binned, 0).0
+    }
+
+    #[test]
+    fn test_onnx_round_trip() {
+        let (model, bins, examples) = get_model_and_examples();
+        let regressor = to_onnx(&model, &bins, &OnnxModelType::Regressor);
+        let classifier = to_onnx(&model, &bins, &OnnxModelType::Classifier);
+        examples.iter().for_each(|example| {
+            let expected = get_expected(&model, &bins, example);
+            let scores = evaluate(&regressor, "target", &example.feature);
+            assert!((scores[0] - expected).abs() < 1e-5);
+            let scores = evaluate(&classifier, "class", &example.feature);
+            assert!((scores[0] + expected).abs() < 1e-5);
+            assert!((scores[1] - expected).abs() < 1e-5);
+        });
+    }
+    // The exported models and the inputs are written to `tests/data/onnx/` by running the tests
+    // with the environment variable `SPARROW_UPDATE_GOLDEN` set, and the outputs of onnxruntime
+    // on them are generated by `tests/data/onnx/make_golden.py`
+    const GOLDEN_DIR: &str = "tests/data/onnx";
+
+    #[test]
+    fn test_onnx_golden_models() {
+        let (model, bins, examples) = get_model_and_examples();
+        let regressor = to_onnx(&model, &bins, &OnnxModelType::Regressor);
+        let classifier = to_onnx(&model, &bins, &OnnxModelType::Classifier);
+        let inputs: Vec<&Vec<f32>> = examples.iter().map(|t| &t.feature).collect();
+        let inputs = serde_json::to_string(&inputs).unwrap();
+        if env::var("SPARROW_UPDATE_GOLDEN").is_ok() {
+            write_all(&format!("{}/regressor.onnx", GOLDEN_DIR), &regressor).unwrap();
+            write_all(&format!("{}/classifier.onnx", GOLDEN_DIR), &classifier).unwrap();
+            write_all(&format!("{}/inputs.json", GOLDEN_DIR), inputs.as_bytes()).unwrap();
+            return;
+        }
+        // the outputs of onnxruntime are only valid for these models and inputs
+        assert!(read_all(&format!("{}/regressor.onnx", GOLDEN_DIR)) == regressor);
+        assert!(read_all(&format!("{}/classifier.onnx", GOLDEN_DIR)) == classifier);
+        assert_eq!(raw_read_all(&format!("{}/inputs.json", GOLDEN_DIR)).unwrap(), inputs);
+    }
+
+    // Requires `tests/data/onnx/outputs.json`, which is not checked in yet. It has to be
+    // generated by running onnxruntime with `make_golden.py`, and must not be derived from
+    // the predictions of sparrow, which are the values under test.
+    #[test]
+    #[ignore = "requires tests/data/onnx/outputs.json generated by make_golden.py"]
+    fn test_onnx_runtime_golden() {
+        #[derive(Deserialize)]
+        struct Outputs {
+            regressor: Vec<f32>,
+            classifier: Vec<Vec<f32>>,
+        }
+
+        let (model, bins, examples) = get_model_and_examples();
+        let filename = format!("{}/outputs.json", GOLDEN_DIR);
+        assert!(Path::new(&filename).exists(),
+                "The outputs of onnxruntime are missing, run `make_golden.py` to generate them.");
+        let outputs: Outputs = serde_json::from_str(&raw_read_all(&filename).unwrap()).unwrap();
+        assert_eq!(outputs.regressor.len(), examples.len());
+        assert_eq!(outputs.classifier.len(), examples.len());
+        examples.iter().enumerate().for_each(|(k, example)| {
+            let expected = get_expected(&model, &bins, example);
+            assert!((outputs.regressor[k] - expected).abs() < 1e-5);
+            assert!((outputs.classifier[k][0] + expected).abs() < 1e-5);
+            assert!((outputs.classifier[k][1] - expected).abs() < 1e-5);
+        });
+    }
+}
@@ -0,0 +1,127 @@
+use Example;
+use TLabel;
+use commons::bins::Bins;
+use commons::model::Model;
+use config::Config;
+use metrics::Metric;
+use metrics::get_performance;
+use testing::load_examples;
+
+
+/// Track the performance of the model on a held-out validation set as the trees are accepted,
+/// and stop the training when the metric fails to improve for `rounds` trees.
+///
+/// The validation examples are kept in memory, and their scores are updated incrementally
+/// with the trees appended since the last evaluation.
+pub struct EarlyStopping {
+    examples: Vec<Example>,
+    labels: Vec<TLabel>,
+    scores: Vec<f32>,
+    version: usize,
+    metric: Metric,
+    rounds: usize,
+    // (the number of trees, the value of the metric) of the best model
+    best: Option<(usize, f32)>,
+}
+
+
+impl EarlyStopping {
+    /// Load the validation set, returns `None` if early stopping is not enabled
+    pub fn new(config: &Config, bins: &Vec<Bins>) -> Option<EarlyStopping> {
+        let filename = config.validation_filename.as_ref()?;
+        let examples = load_examples(
+            filename, config.num_validation_examples, config.num_features, &config.positive, bins);
+        info!("Loaded {} validation examples for early stopping from `{}`",
+              examples.len(), filename);
+        Some(EarlyStopping::from_examples(
+            examples, config.get_early_stopping_metric(), config.early_stopping_rounds))
+    }
+
+    fn from_examples(examples: Vec<Example>, metric: Metric, rounds: usize) -> EarlyStopping {
+        EarlyStopping {
+            labels: examples.iter().map(|example| example.label).collect(),
+            scores: vec![0.0; examples.len()],
+            examples: examples,
+            version: 0,
+            metric: metric,
+            rounds: rounds,
+            best: None,
+        }
+    }
+
+    /// Evaluate the model on the validation set, returns true if the training should stop
+    pub fn update(&mut self, model: &Model) -> bool {
+        if model.size() < self.version {
+            // the model is replaced, so the scores are recomputed
+            self.scores.iter_mut().for_each(|score| *score = 0.0);
+            self.version = 0;
+        }
+        let new_scores = model.predict_batch_since(&self.examples, self.version);
+        self.scores.iter_mut()
+                   .zip(new_scores.iter())
+                   .for_each(|(score, new_score)| *score += new_score);
+        self.version = model.size();
+        let value =
+            get_performance(&self.scores, None, &self.labels, &vec![self.metric.clone()])[0];
+        debug!("early stopping, validation, {}, {:?}, {}", model.size(), self.metric, value);
+        self.record(model.size(), value)
+    }
+
+    // Record the value of the metric of the model with `size` trees
+    fn record(&mut self, size: usize, value: f32) -> bool {
+        let is_better = match self.best {
+            None                  => true,
+            Some((_, best_value)) => {
+                if self.metric.is_larger_better() {
+                    value > best_value
+                } else {
+                    value < best_value
+                }
+            },
+        };
+        if is_better {
+            self.best = Some((size, value));
+        }
+        let (best_size, best_value) = self.best.unwrap();
+        if size >= best_size + self.rounds {
+            info!("Early stopping, the {:?} has not improved for {} trees, \
+                   the best iteration is {} ({})", self.metric, self.rounds, best_size, best_value);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The number of trees of the best model so far
+    pub fn get_best_iteration(&self) -> Option<usize> {
+        self.best.map(|(size, _)| size)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use metrics::Metric;
+    use super::EarlyStopping;
+
+    #[test]
+    fn test_early_stopping() {
+        let mut early_stopping = EarlyStopping::from_examples(vec![], Metric::Loss, 3);
+        assert!(!early_stopping.record(1, 0.9));
+        assert!(!early_stopping.record(2, 0.8));
+        assert!(!early_stopping.record(3, 0.85));
+        assert!(!early_stopping.record(4, 0.8));
+        assert_eq!(early_stopping.get_best_iteration(), Some(2));
+        assert!(early_stopping.record(5, 0.81));
+        assert_eq!(early_stopping.get_best_iteration(), Some(2));
+
+        let mut early_stopping = EarlyStopping::from_examples(vec![], Metric::AUROC, 2);
+        assert_eq!(early_stopping.get_best_iteration(), None);
+        assert!(!early_stopping.record(1, 0.6));
+        assert!(!early_stopping.record(2, 0.7));
+        assert!(!early_stopping.record(3, 0.65));
+        assert!(!early_stopping.record(4, 0.75));
+        assert!(early_stopping.record(6, 0.7));
+        assert_eq!(early_stopping.get_best_iteration(), Some(4));
+    }
+}
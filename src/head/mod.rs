@@ -6,6 +6,8 @@ pub mod model_manager;
 pub mod sampler;
 /// Assign tasks to the scanners
 pub mod scheduler;
+/// Stop the training when the model stops improving on the validation set
+mod early_stopping;
 mod model_with_version;
 
 use std::sync::mpsc;
@@ -56,7 +58,7 @@ pub fn start_head(
     );
 
     // 2. Create a model manager
-    let mut model_sync = ModelManager::new(&model);
+    let mut model_sync = ModelManager::new(&model, &config, &bins);
 
     // 3. Create a scheduler
     let mut scheduler = Scheduler::new(config.network.len(), config.min_grid_size, &config);
@@ -64,6 +66,7 @@ pub fn start_head(
 
     let mutex_task_packet_sender = Mutex::new(task_packet_sender.clone());
     let mutex_packet = Mutex::new(TaskPacket::new());
+    let mut is_stop_sent = false;
     // TODO: increase capacity
     let capacity = 1;
     let mut network = Network::new(config.port, &config.network,
@@ -72,6 +75,15 @@ pub fn start_head(
             debug!("received a packet, {}", packet.packet_id);
             packet.set_packet_type(model_sync.size());
             let mut model = model_sync.handle_packet(&from_addr, &mut packet);
+            if model_sync.is_early_stopped() {
+                // an empty task stops the scanners and the head node, which is sent only once
+                if !is_stop_sent {
+                    is_stop_sent = true;
+                    let task_packet_sender = mutex_task_packet_sender.lock().unwrap();
+                    send_task_packet(&task_packet_sender, None, TaskPacket::new());
+                }
+                return;
+            }
             let (gamma, _assigns) = scheduler.handle_packet(
                 &from_addr, &mut packet, &mut model, capacity);
 
@@ -87,11 +99,11 @@ pub fn start_head(
             task_packet.set_expand_node(None);
             let mut curr_packet = mutex_packet.lock().unwrap();
             if *curr_packet != task_packet {
-                task_packet_sender.send((None, task_packet.clone())).unwrap();
+                send_task_packet(&task_packet_sender, None, task_packet.clone());
                 *curr_packet = task_packet;
             } else if packet.packet_type == UpdatePacketType::Empty {
                 task_packet.set_dest(&from_addr);
-                task_packet_sender.send((Some(from_addr), task_packet.clone())).unwrap();
+                send_task_packet(&task_packet_sender, Some(from_addr), task_packet.clone());
             }
             drop(curr_packet);
             drop(task_packet_sender);
@@ -139,3 +151,14 @@ pub fn start_head(
     // let final_model = write_model(&self.model.model, self.model_ts, false);
     // debug!("model_manager, final model, {}", final_model);
 }
+
+
+// The packets received after the head node stops are still handled, but the tasks cannot be
+// sent since the receiver is dropped
+fn send_task_packet(
+    sender: &mpsc::Sender<(Option<String>, TaskPacket)>, dest: Option<String>, task: TaskPacket,
+) {
+    if sender.send((dest, task)).is_err() {
+        debug!("The head node has stopped, the task packet is dropped");
+    }
+}
@@ -2,13 +2,22 @@ use commons::packet::UpdatePacket;
 use commons::packet::UpdatePacketType;
 use commons::performance_monitor::PerformanceMonitor;
 
+use commons::bins::Bins;
+use commons::model_format::ModelFileFormat;
 use commons::persistent_io::write_model;
+use config::Config;
+use head::early_stopping::EarlyStopping;
 use head::model_with_version::ModelWithVersion;
 
 
 pub struct ModelManager {
     model: ModelWithVersion,
     model_ts: f32,
+    model_format: ModelFileFormat,
+    model_dir: String,
+    bins: Vec<Bins>,
+    early_stopping: Option<EarlyStopping>,
+    is_early_stopped: bool,
 
     perf_mon: PerformanceMonitor,
     _last_logging_ts: f32,
@@ -16,10 +25,15 @@ pub struct ModelManager {
 
 
 impl ModelManager {
-    pub fn new(init_tree: &ModelWithVersion) -> ModelManager {
+    pub fn new(init_tree: &ModelWithVersion, config: &Config, bins: &Vec<Bins>) -> ModelManager {
         ModelManager {
             model: init_tree.clone(),
             model_ts: 0.0,
+            model_format: ModelFileFormat::new(&config.model_format),
+            model_dir: config.model_dir.clone(),
+            bins: bins.clone(),
+            early_stopping: EarlyStopping::new(config, bins),
+            is_early_stopped: false,
 
             perf_mon: PerformanceMonitor::new(),
             _last_logging_ts: 0.0,
@@ -57,12 +71,18 @@ impl ModelManager {
         self.model.size()
     }
 
+    /// Return true if the model stops improving on the validation set
+    pub fn is_early_stopped(&self) -> bool {
+        self.is_early_stopped
+    }
+
     fn broadcast_model(&mut self, is_model_updated: bool) {
         // callback TODO: fix upload model
         // let is_upload_success = upload_model(
         //     &self.model.model, &self.model.model_sig, self.gamma.gamma, &self.exp_name);
         if is_model_updated {
-            write_model(&self.model.model, self.model_ts, true);
+            write_model(&self.model.model, self.model_ts, true, &self.model_format, &self.bins,
+                        &self.model_dir);
         }
         // debug!("model_manager, upload model, {}, {}",
         //         is_upload_success, self.model.model_sig);
@@ -71,6 +91,10 @@ impl ModelManager {
     fn update_model(&mut self, last_update_from: &String, packet: &UpdatePacket) {
         self.model.update(packet.update_tree.as_ref().unwrap().clone(), last_update_from);
         self.model.set_base_size();
+        if let Some(early_stopping) = self.early_stopping.as_mut() {
+            self.is_early_stopped = early_stopping.update(&self.model.model);
+            self.model.model.best_iteration = early_stopping.get_best_iteration();
+        }
         self.broadcast_model(true);
         debug!("model_manager, new updates, {}", self.model.size());
     }
@@ -45,6 +45,7 @@ pub fn start_sampler_async(
         config.resume_training,
         config.exp_name.clone(),
         packet_sender,
+        config.seed,
     );
 
     debug!("Initializing the stratified structure.");
@@ -4,12 +4,13 @@ use std::sync::Arc;
 use std::sync::RwLock;
 use std::sync::mpsc::Sender;
 use std::thread::spawn;
-use rand::thread_rng;
 
 use SampleMode;
 use commons::channel::Receiver;
 use commons::model::Model;
 use commons::ExampleWithScore;
+use commons::get_rng;
+use commons::RNG_GATHERER;
 use commons::packet::TaskPacket;
 use commons::performance_monitor::PerformanceMonitor;
 use commons::persistent_io::write_sample_local;
@@ -22,6 +23,7 @@ pub struct Gatherer {
     model:                  Arc<RwLock<Model>>,
     pub counter:            Arc<RwLock<Vec<u32>>>,
     exp_name:               String,
+    seed:                   u64,
 }
 
 
@@ -32,6 +34,7 @@ impl Gatherer {
         new_sample_capacity:    usize,
         model:                  Arc<RwLock<Model>>,
         exp_name:               String,
+        seed:                   u64,
     ) -> Gatherer {
         Gatherer {
             gather_new_sample:   gather_new_sample,
@@ -39,6 +42,7 @@ impl Gatherer {
             model:               model,
             counter:             Arc::new(RwLock::new(vec![])),
             exp_name:            exp_name,
+            seed:                seed,
         }
     }
 
@@ -50,6 +54,7 @@ impl Gatherer {
         let gather_new_sample = self.gather_new_sample.clone();
         let model = self.model.clone();
         let exp_name = self.exp_name.clone();
+        let seed = self.seed;
         info!("Starting non-blocking gatherer");
         spawn(move || {
             let mut version = 0;
@@ -70,6 +75,7 @@ impl Gatherer {
                     version,
                     model.clone(),
                     exp_name.as_str(),
+                    seed,
                 );
 
                 let mut packet = TaskPacket::new();
@@ -88,6 +94,7 @@ fn gather<F>(
     version: usize,
     model: Arc<RwLock<Model>>,
     exp_name: &str,
+    seed: u64,
 ) where F: Fn(Vec<ExampleWithScore>, Model, usize, &str) {
     debug!("sampler, start, generate sample");
     let mut pm = PerformanceMonitor::new();
@@ -118,7 +125,7 @@ fn gather<F>(
             last_log_count = new_sample.len();
         }
     }
-    thread_rng().shuffle(&mut new_sample);
+    get_rng(seed, &[RNG_GATHERER, version]).shuffle(&mut new_sample);
     // TODO: count number of examples fall, make sure the numbering is the same as the assignments
     let model = {
         let lock = model.read().unwrap();
@@ -11,7 +11,7 @@ use std::sync::mpsc;
 use std::thread::spawn;
 use std::thread::sleep;
 use std::time::Duration;
-use rand;
+use rand::Rng;
 
 use SampleMode;
 use TFeature;
@@ -125,6 +125,7 @@ impl StratifiedStorage {
         resume_training: bool,
         exp_name: String,
         packet_sender: mpsc::Sender<(Option<String>, TaskPacket)>,
+        seed: u64,
     ) -> StratifiedStorage {
         // let snapshot_filename = "stratified.serde".to_string();
         if resume_training {
@@ -166,6 +167,7 @@ impl StratifiedStorage {
             sample_capacity,
             model.clone(),
             exp_name,
+            seed,
         );
         let assigners = Assigners::new(
             strata.clone(),
@@ -182,6 +184,7 @@ impl StratifiedStorage {
             weights_table_r.clone(),
             num_samplers,
             sampler_state.clone(),
+            seed,
         );
         let size = Arc::new(RwLock::new(0));
         gatherer.run(sample_mode, packet_sender);
@@ -228,9 +231,10 @@ impl StratifiedStorage {
                             .map(|(idx, val)| {
                                 bins[idx].get_split_index(*val)
                             }).collect();
-                    let mapped_data = LabeledData::new(features, data.label);
-                    let (score, (model_size, _)) = model.get_prediction(&mapped_data, 0);
-                    updated_examples_s.send((mapped_data, (score, model_size)));
+                    let mut example: ExampleWithScore =
+                        (LabeledData::new(features, data.label), (0.0, 0));
+                    model.update_score(&mut example);
+                    updated_examples_s.send(example);
                 });
                 index += batch_size;
                 if index - last_report_length > size / 10 {
@@ -347,13 +351,15 @@ fn start_update_weights_table(
 // }
 
 
-fn sample_weights_table(weights_table_r: &WeightTableRead) -> Option<i8> {
-    let p: Vec<(i8, f64)> = weights_table_r.map_into(|a, b| (a.clone(), b[0].val));
+fn sample_weights_table<R: Rng>(weights_table_r: &WeightTableRead, rng: &mut R) -> Option<i8> {
+    let mut p: Vec<(i8, f64)> = weights_table_r.map_into(|a, b| (a.clone(), b[0].val));
+    // the order of the strata in the weight table is arbitrary
+    p.sort_by(|a, b| (a.0).cmp(&b.0));
     let sum_of_weights: f64 = p.iter().map(|t| t.1).sum();
     if get_sign(sum_of_weights) == 0 {
         None
     } else {
-        let mut frac = rand::random::<f64>() * sum_of_weights;
+        let mut frac = rng.gen::<f64>() * sum_of_weights;
         let mut iter = p.iter();
         let mut key_val = &(0, 0.0);
         while get_sign(frac) >= 0 {
@@ -365,6 +371,39 @@ fn sample_weights_table(weights_table_r: &WeightTableRead) -> Option<i8> {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use evmap;
+
+    use commons::get_rng;
+    use commons::RNG_SAMPLER;
+    use super::F64;
+    use super::WeightTableRead;
+    use super::WeightTableWrite;
+    use super::sample_weights_table;
+
+    #[test]
+    fn test_sample_weights_table_with_seed() {
+        let (weights_table_r, mut weights_table_w): (WeightTableRead, WeightTableWrite) =
+            evmap::new();
+        for index in 0..8 {
+            weights_table_w.update(index, Box::new(F64 { val: 1.0 + index as f64 }));
+        }
+        weights_table_w.refresh();
+        let get_strata = |seed: u64, thread_index: usize| -> Vec<i8> {
+            let mut rng = get_rng(seed, &[RNG_SAMPLER, thread_index]);
+            (0..100).map(|_| sample_weights_table(&weights_table_r, &mut rng).unwrap()).collect()
+        };
+        // the samplers with the same seed sample the same strata
+        let strata = get_strata(1, 0);
+        assert!(strata.iter().all(|index| *index >= 0 && *index < 8));
+        assert_eq!(strata, get_strata(1, 0));
+        assert!(strata != get_strata(2, 0));
+        assert!(strata != get_strata(1, 1));
+    }
+}
+
+
 /*
 #[cfg(test)]
 mod tests {
@@ -1,6 +1,6 @@
 use std::thread::sleep;
 use std::thread::spawn;
-use rand;
+use rand::Rng;
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -15,7 +15,9 @@ use super::Strata;
 use super::WeightTableRead;
 use super::SPEED_TEST;
 
+use commons::get_rng;
 use commons::get_weight;
+use commons::RNG_SAMPLER;
 
 
 /// Sample examples from the stratified structure
@@ -42,6 +44,7 @@ pub struct Samplers {
     weights_table: WeightTableRead,
     num_threads: usize,
     sampler_state: Arc<RwLock<bool>>,
+    seed: u64,
 }
 
 
@@ -57,6 +60,7 @@ impl Samplers {
         weights_table: WeightTableRead,
         num_threads: usize,
         sampler_state: Arc<RwLock<bool>>,
+        seed: u64,
     ) -> Samplers {
         Samplers {
             strata: strata,
@@ -67,6 +71,7 @@ impl Samplers {
             weights_table: weights_table,
             num_threads: num_threads,
             sampler_state: sampler_state,
+            seed: seed,
         }
     }
 
@@ -79,7 +84,8 @@ impl Samplers {
         let stats_update_s   = self.stats_update_s.clone();
         let weights_table    = self.weights_table.clone();
         let sampler_state    = self.sampler_state.clone();
-        for _ in 0..num_threads {
+        let seed             = self.seed;
+        for thread_index in 0..num_threads {
             let strata           = strata.clone();
             let sampled_examples = sampled_examples.clone();
             let updated_examples = updated_examples.clone();
@@ -98,6 +104,7 @@ impl Samplers {
                 sampler(
                     strata, sampled_examples, updated_examples,
                     model, stats_update_s, weights_table, sampler_state,
+                    seed, thread_index,
                 );
             });
         }
@@ -113,7 +120,10 @@ fn sampler(
     stats_update_s: Sender<(i8, (i32, f64))>,
     weights_table: WeightTableRead,
     sampler_state: Arc<RwLock<bool>>,
+    seed: u64,
+    thread_index: usize,
 ) {
+    let mut rng = get_rng(seed, &[RNG_SAMPLER, thread_index]);
     let mut pm_update = PerformanceMonitor::new();
     let mut pm_sample = PerformanceMonitor::new();
     pm_update.start();
@@ -129,7 +139,7 @@ fn sampler(
         pm_sample.write_log("sampler-sample");
 
         // STEP 1: Sample which strata to get next sample
-        let index = super::sample_weights_table(&weights_table);
+        let index = super::sample_weights_table(&weights_table, &mut rng);
         if index.is_none() {
             // stratified storage is empty, wait for data loading
             debug!("sampler, Sampler sleeps waiting for data loading");
@@ -158,7 +168,7 @@ fn sampler(
                 2f32.powi((index + 1) as i32)
             }
         };
-        let grid = grids.entry(index).or_insert(rand::random::<f32>() * grid_size);
+        let grid = grids.entry(index).or_insert(rng.gen::<f32>() * grid_size);
         let mut sampled_example = None;
         let mut sampled_trials = 0;
         let mut retrieve_pm = PerformanceMonitor::new();
@@ -180,11 +190,8 @@ fn sampler(
                 break;
             }
             let (example, (score, version)) = recv.unwrap();
-            let (updated_score, model_size) = {
-                let latest_model = &model.read().unwrap();
-                let (inc_score, (model_size, _)) = latest_model.get_prediction(&example, version);
-                (score + inc_score, model_size)
-            };
+            let (updated_score, model_size) =
+                model.read().unwrap().get_updated_score(&example, score, version);
             let updated_weight = get_weight(&example, updated_score);
             if updated_weight.log2() as i8 == index {
                 *grid += updated_weight;
@@ -1,5 +1,10 @@
+use rand::Rng;
+use rand::StdRng;
+
 use Example;
 use TFeature;
+use commons::get_rng;
+use commons::RNG_KDTREE;
 
 pub type Grid = Vec<(usize, TFeature, bool)>;
 pub type Grids = Vec<Grid>;
@@ -20,10 +25,11 @@ pub struct KdTree {
 }
 
 impl KdTree {
-    pub fn new(examples: Vec<Example>, min_size: usize) -> Self {
+    pub fn new(examples: Vec<Example>, min_size: usize, seed: u64) -> Self {
         let mut tree = KdTree::empty(examples[0].feature.len());
+        let mut rng = get_rng(seed, &[RNG_KDTREE]);
         tree.examples = examples;
-        tree.create_tree(min_size);
+        tree.create_tree(min_size, &mut rng);
         tree
     }
 
@@ -57,12 +63,12 @@ impl KdTree {
         }
     }
 
-    fn create_tree(&mut self, min_size: usize) {
+    fn create_tree(&mut self, min_size: usize, rng: &mut StdRng) {
         if self.examples.len() <= min_size * 2 {
             return;  // cannot further split because of the insufficient number of examples
         }
 
-        let dim = rand::random::<usize>() % self.num_features;
+        let dim = rng.gen_range(0, self.num_features);
         let mut values: Vec<TFeature> = self.examples.iter().map(|t| t.feature[dim]).collect();
 
         self.split_dimension = dim;
@@ -78,9 +84,9 @@ impl KdTree {
                 right.add_to_bucket(example)
             }
         }
-        left.create_tree(min_size);
+        left.create_tree(min_size, rng);
         self.left = Some(left);
-        right.create_tree(min_size);
+        right.create_tree(min_size, rng);
         self.right = Some(right);
     }
 
@@ -119,7 +125,7 @@ mod tests {
                                                                     .map(|(example, _)| { example })
                                                                     .collect();
         let min_size = 10;
-        let mut kdtree = KdTree::new(examples.clone(), min_size);
+        let mut kdtree = KdTree::new(examples.clone(), min_size, 0);
 
         let grids = kdtree.get_leaves();
         let mut counts: Vec<usize> = vec![0; grids.len()];
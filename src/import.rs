@@ -0,0 +1,376 @@
+use serde_json;
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+use commons::bins::Bins;
+use commons::model::Model;
+use commons::tree::Tree;
+
+
+/*
+Both XGBoost and LightGBM (with the binary logistic objective) output the margin `m` with
+`P(y = 1) = 1 / (1 + exp(-m))`, while the score `s` of Sparrow gives
+`P(y = 1) = 1 / (1 + exp(-2s))`, so the leaf values are scaled by a half.
+
+The thresholds on the raw feature values are converted to the split indices of the closest bin
+boundaries, so the imported model is an approximation of the original model if the thresholds
+do not fall on the bin boundaries. The handling of the missing values is ignored.
+
+The dumps do not include the constant margin that is added to all predictions (e.g. the
+`base_score` of XGBoost), which is given separately as `base_margin` and is added to the model
+as a tree with a constant prediction.
+*/
+const MARGIN_SCALE: f32 = 0.5;
+
+
+/// The formats of the models trained by other libraries
+pub enum ModelFormat {
+    XGBoost,
+    LightGBM,
+}
+
+impl ModelFormat {
+    pub fn new(format: &String) -> ModelFormat {
+        match format.to_lowercase().as_str() {
+            "xgboost"  => ModelFormat::XGBoost,
+            "lightgbm" => ModelFormat::LightGBM,
+            _          => {
+                error!("Unrecognized initial model format. Use xgboost by default.");
+                ModelFormat::XGBoost
+            }
+        }
+    }
+}
+
+
+/// A tree on the raw feature values, where the examples with `x[feature] < threshold`
+/// go to the left child
+enum Node {
+    Leaf(f32),
+    Split {
+        feature:   usize,
+        threshold: f32,
+        left:      Box<Node>,
+        right:     Box<Node>,
+    },
+}
+
+
+/// Read a model trained by XGBoost (the JSON dump generated by `dump_model` with
+/// `dump_format='json'`) or by LightGBM (the text model generated by `save_model`)
+/// from `filename`, and convert it to a Sparrow model on `bins`.
+/// `base_margin` is the margin that the other library adds to all predictions, which is
+/// not part of the dumped trees.
+pub fn import_model(
+    filename: &String, format: &ModelFormat, bins: &Vec<Bins>, base_margin: f32,
+) -> Result<Model, String> {
+    let content = read_to_string(filename).map_err(|err| {
+        format!("Cannot read the file, {}", err)
+    })?;
+    let mut trees = match format {
+        ModelFormat::XGBoost  => parse_xgboost(&content)?,
+        ModelFormat::LightGBM => parse_lightgbm(&content)?,
+    };
+    if base_margin != 0.0 {
+        trees.insert(0, Node::Leaf(base_margin));
+    }
+    let mut model = Model::new();
+    for root in trees.iter() {
+        model.append(to_tree(root, bins)?);
+    }
+    info!("Imported {} trees from `{}`", model.size(), filename);
+    Ok(model)
+}
+
+
+fn to_tree(root: &Node, bins: &Vec<Bins>) -> Result<Tree, String> {
+    fn count_splits(node: &Node) -> usize {
+        match node {
+            Node::Leaf(_)                        => 0,
+            Node::Split { ref left, ref right, .. } => 1 + count_splits(left) + count_splits(right),
+        }
+    }
+
+    // the prediction of an internal node is zero, so the prediction of a child is added to
+    // the node when the node is split
+    fn get_predict(node: &Node) -> f32 {
+        match node {
+            Node::Leaf(val) => val * MARGIN_SCALE,
+            _               => 0.0,
+        }
+    }
+
+    fn add_node(
+        tree: &mut Tree, index: usize, node: &Node, bins: &Vec<Bins>,
+    ) -> Result<(), String> {
+        if let Node::Split { feature, threshold, ref left, ref right } = *node {
+            if feature >= bins.len() {
+                return Err(format!("The feature {} is out of range", feature));
+            }
+            let (left_index, right_index) = tree.split(
+                index, feature, bins[feature].get_threshold_index(threshold),
+                get_predict(left), get_predict(right));
+            add_node(tree, left_index, left, bins)?;
+            add_node(tree, right_index, right, bins)?;
+        }
+        Ok(())
+    }
+
+    match root {
+        // the prediction of the root cannot be set directly, so the root is split into
+        // two leaves with the same prediction
+        Node::Leaf(_) => {
+            let mut tree = Tree::new(1);
+            tree.split(0, 0, 0, get_predict(root), get_predict(root));
+            Ok(tree)
+        },
+        _ => {
+            let mut tree = Tree::new(count_splits(root));
+            add_node(&mut tree, 0, root, bins)?;
+            Ok(tree)
+        },
+    }
+}
+
+
+// XGBoost: a JSON array of the trees, in which each internal node has the fields
+// `nodeid`, `split`, `split_condition`, `yes`, `no` and `children`, and each leaf has
+// the fields `nodeid` and `leaf`. An example goes to the `yes` child if `x < split_condition`.
+fn parse_xgboost(content: &String) -> Result<Vec<Node>, String> {
+    fn parse_node(node: &Value) -> Result<Node, String> {
+        if let Some(leaf) = node.get("leaf") {
+            let leaf = leaf.as_f64().ok_or(format!("Cannot parse the leaf value `{}`", leaf))?;
+            return Ok(Node::Leaf(leaf as f32));
+        }
+        let split = node["split"].as_str()
+                                 .map(|t| t.to_string())
+                                 .unwrap_or(node["split"].to_string());
+        let feature = split.trim_start_matches('f').parse().map_err(|_| {
+            format!("Cannot parse the split feature `{}`, which should be `f<index>`", split)
+        })?;
+        let threshold = node["split_condition"].as_f64().ok_or(format!(
+            "Cannot parse the split condition `{}`", node["split_condition"]))?;
+        let children = node["children"].as_array()
+                                       .ok_or("Cannot find the children of a node")?;
+        let get_child = |key: &str| -> Result<Box<Node>, String> {
+            let node_id = &node[key];
+            let child = children.iter().find(|t| t["nodeid"] == *node_id)
+                                .ok_or(format!("Cannot find the child `{}`", node_id))?;
+            Ok(Box::new(parse_node(child)?))
+        };
+        Ok(Node::Split {
+            feature:   feature,
+            threshold: threshold as f32,
+            left:      get_child("yes")?,
+            right:     get_child("no")?,
+        })
+    }
+
+    let trees: Vec<Value> = serde_json::from_str(content).map_err(|err| {
+        format!("Cannot parse the XGBoost JSON dump, {}", err)
+    })?;
+    trees.iter().map(|tree| parse_node(tree)).collect()
+}
+
+
+// LightGBM: a block for each tree that starts with `Tree=<index>`, in which the nodes are
+// described by the arrays `split_feature`, `threshold`, `decision_type`, `left_child`,
+// `right_child`, and `leaf_value`. A negative child index `c` refers to the leaf `-c - 1`.
+// An example goes to the left child if `x <= threshold`, which is treated as `x < threshold`
+// since the thresholds of LightGBM are the mid-points between the feature values.
+fn parse_lightgbm(content: &String) -> Result<Vec<Node>, String> {
+    fn parse_array<T: ::std::str::FromStr>(
+        fields: &HashMap<&str, &str>, key: &str,
+    ) -> Result<Vec<T>, String> {
+        fields.get(key).map(|t| {
+            t.split_whitespace()
+             .map(|val| val.parse().map_err(|_| format!("Cannot parse the field `{}`", key)))
+             .collect()
+        }).unwrap_or(Ok(vec![]))
+    }
+
+    // `parent` is the index of the parent node, and the children are always after the parent
+    fn parse_node(
+        index: i64, parent: i64, tree: &(Vec<usize>, Vec<f32>, Vec<i64>, Vec<i64>, Vec<f32>),
+    ) -> Result<Node, String> {
+        let (ref features, ref thresholds, ref left, ref right, ref leaves) = *tree;
+        if index < 0 {
+            let leaf = leaves.get((-index - 1) as usize)
+                             .ok_or(format!("The leaf {} is out of range", -index - 1))?;
+            return Ok(Node::Leaf(*leaf));
+        }
+        let node = index as usize;
+        if index <= parent || node >= features.len() || node >= thresholds.len() ||
+                node >= left.len() || node >= right.len() {
+            return Err(format!("The node {} is invalid", index));
+        }
+        Ok(Node::Split {
+            feature:   features[node],
+            threshold: thresholds[node],
+            left:      Box::new(parse_node(left[node], index, tree)?),
+            right:     Box::new(parse_node(right[node], index, tree)?),
+        })
+    }
+
+    let content = content.split("end of trees").next().unwrap();
+    content.split("Tree=").skip(1).map(|block| {
+        let fields: HashMap<&str, &str> = block.lines().filter_map(|line| {
+            let mut kv = line.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(val)) => Some((key.trim(), val.trim())),
+                _                      => None,
+            }
+        }).collect();
+        let decision_types: Vec<u32> = parse_array(&fields, "decision_type")?;
+        if decision_types.iter().any(|t| t & 1 != 0) {
+            return Err("The categorical splits of LightGBM are not supported".to_string());
+        }
+        let leaves: Vec<f32> = parse_array(&fields, "leaf_value")?;
+        if leaves.len() == 1 {
+            return Ok(Node::Leaf(leaves[0]));
+        }
+        let tree = (
+            parse_array(&fields, "split_feature")?, parse_array(&fields, "threshold")?,
+            parse_array(&fields, "left_child")?, parse_array(&fields, "right_child")?, leaves,
+        );
+        parse_node(0, -1, &tree)
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use std::env;
+    use std::fs::remove_file;
+
+    use commons::bins::Bins;
+    use commons::io::write_all;
+    use commons::labeled_data::LabeledData;
+    use commons::model::Model;
+    use super::ModelFormat;
+    use super::import_model;
+    use super::parse_lightgbm;
+    use super::parse_xgboost;
+    use super::to_tree;
+
+    fn get_bins() -> Vec<Bins> {
+        let bins = "{\"size\": 4, \"vals\": [0.0, 1.0, 2.0, 3.0]}";
+        (0..3).map(|_| serde_json::from_str(bins).unwrap()).collect()
+    }
+
+    fn predict(model: &Model, bins: &Vec<Bins>, features: Vec<f32>) -> f32 {
+        let example = LabeledData::new(
+            features.iter().enumerate().map(|(k, t)| bins[k].get_split_index(*t)).collect(), 1);
+        model.get_prediction(&example, 0).0
+    }
+
+    #[test]
+    fn test_threshold_index() {
+        let bins = &get_bins()[0];
+        assert_eq!(bins.get_threshold_index(-1.0), 0);
+        assert_eq!(bins.get_threshold_index(1.0), 0);
+        assert_eq!(bins.get_threshold_index(1.4), 0);
+        assert_eq!(bins.get_threshold_index(1.6), 1);
+        assert_eq!(bins.get_threshold_index(2.0), 1);
+        assert_eq!(bins.get_threshold_index(3.0), 2);
+        assert_eq!(bins.get_threshold_index(4.0), 3);
+    }
+
+    #[test]
+    fn test_import_xgboost() {
+        let dump = r#"[
+            { "nodeid": 0, "depth": 0, "split": "f1", "split_condition": 2, "yes": 1, "no": 2,
+              "missing": 1, "children": [
+                { "nodeid": 1, "depth": 1, "split": "f2", "split_condition": 1, "yes": 3, "no": 4,
+                  "missing": 3, "children": [
+                    { "nodeid": 3, "leaf": 0.4 },
+                    { "nodeid": 4, "leaf": -0.2 }
+                ]},
+                { "nodeid": 2, "leaf": 0.6 }
+            ]},
+            { "nodeid": 0, "leaf": 0.1 }
+        ]"#;
+        let bins = get_bins();
+        let mut model = Model::new();
+        parse_xgboost(&dump.to_string()).unwrap().iter()
+                                        .for_each(|t| model.append(to_tree(t, &bins).unwrap()));
+        assert_eq!(model.size(), 2);
+        assert!((predict(&model, &bins, vec![0.0, 1.0, 0.5]) - 0.25).abs() < 1e-6);
+        assert!((predict(&model, &bins, vec![0.0, 1.0, 1.0]) - (-0.05)).abs() < 1e-6);
+        assert!((predict(&model, &bins, vec![0.0, 2.0, 0.0]) - 0.35).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_import_base_margin() {
+        let filename = env::temp_dir().join("sparrow_test_import_base_margin.json");
+        let filename = filename.to_str().unwrap().to_string();
+        write_all(&filename, br#"[{ "nodeid": 0, "leaf": 0.1 }]"#).unwrap();
+        let bins = get_bins();
+        let model = import_model(&filename, &ModelFormat::XGBoost, &bins, 0.0).unwrap();
+        assert_eq!(model.size(), 1);
+        assert!((predict(&model, &bins, vec![0.0, 1.0, 0.5]) - 0.05).abs() < 1e-6);
+        let model = import_model(&filename, &ModelFormat::XGBoost, &bins, 0.4).unwrap();
+        assert_eq!(model.size(), 2);
+        assert!((predict(&model, &bins, vec![0.0, 1.0, 0.5]) - 0.25).abs() < 1e-6);
+        remove_file(&filename).unwrap();
+        assert!(import_model(&filename, &ModelFormat::XGBoost, &bins, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_import_malformed() {
+        let bins = get_bins();
+        let dumps = vec![
+            r#"[{ "nodeid": 0, "leaf": "a" }]"#,
+            // the split feature is not `f<index>`
+            r#"[{ "nodeid": 0, "split": "x1", "split_condition": 2, "yes": 1, "no": 2,
+                  "children": [{ "nodeid": 1, "leaf": 0.1 }, { "nodeid": 2, "leaf": 0.1 }]}]"#,
+            // the child is missing
+            r#"[{ "nodeid": 0, "split": "f1", "split_condition": 2, "yes": 1, "no": 3,
+                  "children": [{ "nodeid": 1, "leaf": 0.1 }, { "nodeid": 2, "leaf": 0.1 }]}]"#,
+            "{",
+        ];
+        dumps.iter().for_each(|dump| assert!(parse_xgboost(&dump.to_string()).is_err()));
+        // the feature is out of range
+        let dump = r#"[{ "nodeid": 0, "split": "f5", "split_condition": 2, "yes": 1, "no": 2,
+            "children": [{ "nodeid": 1, "leaf": 0.1 }, { "nodeid": 2, "leaf": 0.1 }]}]"#;
+        let trees = parse_xgboost(&dump.to_string()).unwrap();
+        assert!(to_tree(&trees[0], &bins).is_err());
+
+        let texts = vec![
+            // the child refers to its parent
+            "Tree=0\nsplit_feature=0\nthreshold=1.5\ndecision_type=2\n\
+             left_child=0\nright_child=-1\nleaf_value=0.2 -0.4\n",
+            // the leaf is out of range
+            "Tree=0\nsplit_feature=0\nthreshold=1.5\ndecision_type=2\n\
+             left_child=-1\nright_child=-3\nleaf_value=0.2 -0.4\n",
+            // categorical split
+            "Tree=0\nsplit_feature=0\nthreshold=1.5\ndecision_type=1\n\
+             left_child=-1\nright_child=-2\nleaf_value=0.2 -0.4\n",
+            "Tree=0\nsplit_feature=a\nthreshold=1.5\ndecision_type=2\n\
+             left_child=-1\nright_child=-2\nleaf_value=0.2 -0.4\n",
+        ];
+        texts.iter().for_each(|text| assert!(parse_lightgbm(&text.to_string()).is_err()));
+    }
+
+    #[test]
+    fn test_import_lightgbm() {
+        let text = "tree\nversion=v3\nnum_class=1\n\n\
+                    Tree=0\nnum_leaves=3\nsplit_feature=0 2\nthreshold=1.5 2.5\n\
+                    decision_type=2 2\nleft_child=-1 -2\nright_child=1 -3\n\
+                    leaf_value=0.2 -0.4 0.8\nshrinkage=1\n\n\n\
+                    Tree=1\nnum_leaves=1\nleaf_value=-0.1\nshrinkage=1\n\n\n\
+                    end of trees\n\nfeature_importances:\nColumn_0=1\n";
+        let bins = get_bins();
+        let mut model = Model::new();
+        parse_lightgbm(&text.to_string()).unwrap().iter()
+                                         .for_each(|t| model.append(to_tree(t, &bins).unwrap()));
+        assert_eq!(model.size(), 2);
+        assert!((predict(&model, &bins, vec![1.0, 0.0, 0.0]) - 0.05).abs() < 1e-6);
+        assert!((predict(&model, &bins, vec![2.0, 0.0, 0.0]) - (-0.25)).abs() < 1e-6);
+        assert!((predict(&model, &bins, vec![2.0, 0.0, 3.0]) - 0.35).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,235 @@
+use serde_json;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use TFeature;
+use commons::bins::Bins;
+use commons::calibration::Calibrator;
+use commons::model::Model;
+use commons::persistent_io::read_bins_disk;
+use commons::persistent_io::read_model_from_file;
+use commons::tree::SplitStats;
+use commons::tree::Tree;
+
+
+/// A node of a tree. The threshold of an internal node is the bin index of the split,
+/// and `raw_threshold` maps it back to the feature values, i.e. the examples with
+/// `x < raw_threshold` go to the left child (`None` if all examples go to the left child,
+/// or if the bins are not available).
+#[derive(Serialize, Debug)]
+pub struct NodeInfo {
+    pub index:         usize,
+    pub depth:         usize,
+    pub predict:       f32,
+    pub feature:       Option<usize>,
+    pub threshold:     Option<TFeature>,
+    pub raw_threshold: Option<f32>,
+    pub left:          Option<usize>,
+    pub right:         Option<usize>,
+    /// the statistics of the weak rule recorded by the learner, if available
+    pub stats:         Option<SplitStats>,
+}
+
+/// The split counts and the importance of a feature over all trees.
+///
+/// The gain of a split is half of the difference between the predictions of its children,
+/// i.e. the step size the booster assigned to the weak rule, which increases with its edge.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FeatureImportance {
+    pub feature:    usize,
+    pub num_splits: usize,
+    pub gain:       f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModelInfo {
+    pub num_trees:       usize,
+    pub base_size:       usize,
+    pub growth_policy:   String,
+    pub best_iteration:  Option<usize>,
+    pub calibrator:      Option<Calibrator>,
+    /// number of splits of a tree -> number of trees
+    pub tree_sizes:      BTreeMap<usize, usize>,
+    /// depth of a tree -> number of trees
+    pub tree_depths:     BTreeMap<usize, usize>,
+    /// sorted by the gain in the descending order
+    pub features:        Vec<FeatureImportance>,
+    pub trees:           Vec<Vec<NodeInfo>>,
+}
+
+
+/// Print the structure and the statistics of the model persisted in `model_filename`.
+///
+/// The thresholds are mapped back to the feature values if the bins are available at
+/// `models/bins.json`.
+pub fn inspect(model_filename: &String, json: bool) {
+    let (_, _, model) = read_model_from_file(model_filename);
+    let bins = {
+        if Path::new("models/bins.json").exists() {
+            Some(read_bins_disk())
+        } else {
+            None
+        }
+    };
+    let info = get_model_info(&model, bins.as_ref());
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+    } else {
+        print!("{}", format_model_info(&info));
+    }
+}
+
+
+pub fn get_model_info(model: &Model, bins: Option<&Vec<Bins>>) -> ModelInfo {
+    let trees: Vec<Vec<NodeInfo>> =
+        model.get_trees().iter().map(|tree| get_nodes(tree, bins)).collect();
+    let mut tree_sizes = BTreeMap::new();
+    let mut tree_depths = BTreeMap::new();
+    let mut features: BTreeMap<usize, FeatureImportance> = BTreeMap::new();
+    trees.iter().for_each(|nodes| {
+        let num_splits = nodes.iter().filter(|node| node.feature.is_some()).count();
+        let depth = nodes.iter().map(|node| node.depth).max().unwrap_or(0);
+        *tree_sizes.entry(num_splits).or_insert(0) += 1;
+        *tree_depths.entry(depth).or_insert(0) += 1;
+        nodes.iter().filter(|node| node.feature.is_some()).for_each(|node| {
+            let feature = node.feature.unwrap();
+            let gain = 0.5 * (
+                nodes[node.left.unwrap()].predict - nodes[node.right.unwrap()].predict).abs();
+            let importance = features.entry(feature).or_insert(FeatureImportance {
+                feature:    feature,
+                num_splits: 0,
+                gain:       0.0,
+            });
+            importance.num_splits += 1;
+            importance.gain += gain;
+        });
+    });
+    let mut features: Vec<FeatureImportance> = features.into_iter().map(|(_, v)| v).collect();
+    features.sort_by(|a, b| b.gain.partial_cmp(&a.gain).unwrap());
+    ModelInfo {
+        num_trees:      model.size(),
+        base_size:      model.base_size,
+        growth_policy:  model.growth_policy.clone(),
+        best_iteration: model.best_iteration,
+        calibrator:     model.calibrator.clone(),
+        tree_sizes:     tree_sizes,
+        tree_depths:    tree_depths,
+        features:       features,
+        trees:          trees,
+    }
+}
+
+
+fn get_nodes(tree: &Tree, bins: Option<&Vec<Bins>>) -> Vec<NodeInfo> {
+    (0..tree.num_nodes).map(|index| {
+        let split = tree.get_split(index);
+        NodeInfo {
+            index:         index,
+            depth:         tree.get_depth(index),
+            predict:       tree.get_predict(index),
+            feature:       split.map(|t| t.0),
+            threshold:     split.map(|t| t.1),
+            raw_threshold: split.and_then(|(feature, threshold, _, _)| {
+                // an example is in the bin `k` if `vals[k] <= x < vals[k + 1]`
+                bins.and_then(|bins| bins[feature].get_vals().get(threshold as usize + 1))
+                    .map(|t| *t)
+            }),
+            left:          split.map(|t| t.2),
+            right:         split.map(|t| t.3),
+            stats:         tree.get_split_stats(index).cloned(),
+        }
+    }).collect()
+}
+
+
+fn format_model_info(info: &ModelInfo) -> String {
+    let mut ret = vec![
+        format!("Number of trees: {} (base size: {})", info.num_trees, info.base_size),
+        format!("Growth policy: {}", info.growth_policy),
+        format!("Best iteration: {}",
+                info.best_iteration.map(|t| t.to_string()).unwrap_or("not set".to_string())),
+        format!("Calibration: {}", match info.calibrator {
+            Some(Calibrator::Platt { a, b })          => format!("platt (a = {}, b = {})", a, b),
+            Some(Calibrator::Isotonic { ref scores, .. }) => {
+                format!("isotonic ({} points)", scores.len())
+            },
+            None                                      => "not set".to_string(),
+        }),
+        "".to_string(),
+        "Tree sizes (number of splits: number of trees):".to_string(),
+    ];
+    info.tree_sizes.iter().for_each(|(k, v)| ret.push(format!("  {:>4}: {}", k, v)));
+    ret.push("Tree depths (depth: number of trees):".to_string());
+    info.tree_depths.iter().for_each(|(k, v)| ret.push(format!("  {:>4}: {}", k, v)));
+    ret.push("".to_string());
+    ret.push("Feature importance (feature, number of splits, gain):".to_string());
+    info.features.iter().for_each(|t| {
+        ret.push(format!("  {:>6}, {:>6}, {:.6}", t.feature, t.num_splits, t.gain));
+    });
+    info.trees.iter().enumerate().for_each(|(i, nodes)| {
+        ret.push("".to_string());
+        ret.push(format!("Tree {}:", i));
+        format_node(nodes, 0, &mut ret);
+    });
+    ret.push("".to_string());
+    ret.join("\n")
+}
+
+
+fn format_node(nodes: &Vec<NodeInfo>, index: usize, output: &mut Vec<String>) {
+    let node = &nodes[index];
+    let indent = "  ".repeat(node.depth + 1);
+    if node.feature.is_none() {
+        output.push(format!("{}[{}] leaf, predict = {}", indent, index, node.predict));
+        return;
+    }
+    let condition = match node.raw_threshold {
+        Some(t) => format!("x[{}] < {}", node.feature.unwrap(), t),
+        None    => format!("bin(x[{}]) <= {}", node.feature.unwrap(), node.threshold.unwrap()),
+    };
+    let stats = match node.stats {
+        Some(ref t) => format!(", gamma = {}, num_scanned = {}", t.gamma, t.num_scanned),
+        None        => "".to_string(),
+    };
+    output.push(format!("{}[{}] if {}, predict = {}{}",
+                        indent, index, condition, node.predict, stats));
+    format_node(nodes, node.left.unwrap(), output);
+    format_node(nodes, node.right.unwrap(), output);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use commons::model::Model;
+    use commons::tree::Tree;
+    use super::FeatureImportance;
+    use super::format_model_info;
+    use super::get_model_info;
+
+    #[test]
+    fn test_model_info() {
+        let mut model = Model::new();
+        let mut tree = Tree::new(2);
+        tree.split(0, 3, 10, 0.0, 0.0);
+        tree.split(1, 1, 5, -0.5, 0.5);
+        model.append(tree);
+        let mut tree = Tree::new(2);
+        tree.split(0, 1, 2, 0.25, -0.25);
+        model.append(tree);
+
+        let info = get_model_info(&model, None);
+        assert_eq!(info.num_trees, 2);
+        assert_eq!(info.tree_sizes.get(&2), Some(&1));
+        assert_eq!(info.tree_sizes.get(&1), Some(&1));
+        assert_eq!(info.tree_depths.get(&2), Some(&1));
+        assert_eq!(info.tree_depths.get(&1), Some(&1));
+        assert_eq!(info.features, vec![
+            FeatureImportance { feature: 1, num_splits: 2, gain: 0.75 },
+            FeatureImportance { feature: 3, num_splits: 1, gain: 0.0 },
+        ]);
+        assert_eq!(info.trees[0][3].predict, -0.5);
+        assert_eq!(info.trees[0][3].depth, 2);
+        assert!(format_model_info(&info).contains("[3] leaf, predict = -0.5"));
+    }
+}
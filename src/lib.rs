@@ -34,6 +34,18 @@ mod commons;
 mod config;
 /// Validating models
 mod testing;
+/// Evaluation metrics of the models
+mod metrics;
+/// Inspecting the structure and the statistics of models
+mod inspect;
+/// Exporting models to other formats
+mod export;
+/// Importing models trained by other libraries
+mod import;
+/// Truncating, slicing, merging, and rescaling models
+mod edit;
+/// Serving the predictions of models over HTTP
+mod serve;
 /// Implementation of the components running on head node, specifically the scanner
 /// and the model manager
 pub mod head;
@@ -50,9 +62,23 @@ use scanner::start_scanner;
 use scanner::handle_network_send;
 use head::start_head;
 use testing::validate;
+use testing::PerformanceFormat;
+use testing::get_checkpoints;
+use testing::read_models_table;
+use testing::calibrate;
+use commons::calibration::CalibrationMethod;
+use inspect::inspect;
+use export::export;
+use export::ExportFormat;
+use edit::edit;
+use edit::EditOperation;
+use import::import_model;
+use import::ModelFormat;
+use serve::serve;
 
 use commons::bins::load_bins;
 use commons::io::clear_s3_bucket;
+use commons::model_format::ModelFileFormat;
 use commons::persistent_io::read_model;
 
 // Types
@@ -100,19 +126,36 @@ pub fn training(config_filepath: &String) {
         let (mut network, new_updates_receiver) = start_scanner(config, sample_mode, bins);
         handle_network_send(&mut network, new_updates_receiver);
     } else { // if config.sampler_scanner == "sampler"
-        let init_tree: Model = {
+        let mut init_tree: Model = {
             if config.resume_training && config.sampler_scanner == "sampler" {
                 // Resuming from an earlier training
                 debug!("resume_training is enabled");
-                let (_, _, mut model) = read_model();
+                let (_, _, mut model) =
+                    read_model(&ModelFileFormat::new(&config.model_format), &config.model_dir);
                 model.set_base_size();
                 debug!("Loaded an existing model");
                 model
+            } else if let Some(ref filename) = config.init_model_filename {
+                // Warm-start from a model trained by another library
+                let model = import_model(
+                    filename, &ModelFormat::new(&config.init_model_format), &bins,
+                    config.init_model_base_margin);
+                let mut model = match model {
+                    Ok(model) => model,
+                    Err(err)  => {
+                        error!("Cannot import the initial model `{}`: {}", filename, err);
+                        return;
+                    },
+                };
+                model.set_base_size();
+                debug!("Imported an initial model");
+                model
             } else {
                 debug!("Created a new model");
                 Model::new()
             }
         };
+        init_tree.set_growth_policy(&config.growth_policy);
         start_head(config, sample_mode, bins, init_tree);
     }
 }
@@ -126,14 +169,148 @@ pub fn training(config_filepath: &String) {
 pub fn testing(config_filepath: &String) {
     // Load configurations
     let config: Config = Config::new(config_filepath);
+    let (models, incremental_testing) = match config.testing_model_dir {
+        Some(ref model_dir) => {
+            let checkpoints = get_checkpoints(model_dir)
+                .expect(&format!("Cannot read the models in `{}`", model_dir));
+            (checkpoints, true)
+        },
+        None                => {
+            (read_models_table(&config.models_table_filename), config.incremental_testing)
+        },
+    };
     validate(
-        config.models_table_filename.clone(),
+        models,
         config.testing_filename.clone(),
         config.num_testing_examples,
         config.num_features,
         config.batch_size,
         config.positive.clone(),
-        config.incremental_testing,
+        incremental_testing,
         config.testing_scores_only,
+        config.testing_explain,
+        &config.get_testing_metrics(),
+        &config.testing_performance_filename,
+        &PerformanceFormat::new(&config.testing_performance_format),
+        config.testing_reliability_diagram,
+    );
+}
+
+
+/// Print the trees, the feature importance, and the tree statistics of a model
+///
+/// Parameter:
+///
+/// * model_filepath: the filepath to the persisted model
+/// * json: print the results in JSON instead of the human-readable text
+pub fn inspect_model(model_filepath: &String, json: bool) {
+    inspect(model_filepath, json);
+}
+
+
+/// Export a model to another format
+///
+/// Parameter:
+///
+/// * model_filepath: the filepath to the persisted model
+/// * output_filepath: the filepath to write the exported model
+/// * format: the format of the exported model, `onnx-classifier`, `onnx-regressor`, `rust`, or `c`
+pub fn export_model(model_filepath: &String, output_filepath: &String, format: &String) {
+    if let Some(format) = ExportFormat::new(format) {
+        export(model_filepath, output_filepath, &format);
+    }
+}
+
+
+/// Keep the first `num_trees` trees of a model, e.g. the best iteration found by validation
+///
+/// Parameter:
+///
+/// * model_filepath: the filepath to the persisted model
+/// * num_trees: the number of trees to keep
+/// * output_filepath: the filepath to write the truncated model
+pub fn truncate_model(model_filepath: &String, num_trees: usize, output_filepath: &String) {
+    edit(model_filepath, output_filepath, &EditOperation::Truncate(num_trees));
+}
+
+
+/// Extract the trees in the range `[start, end)` of a model
+///
+/// Parameter:
+///
+/// * model_filepath: the filepath to the persisted model
+/// * start: the index of the first tree to keep
+/// * end: the index after the last tree to keep
+/// * output_filepath: the filepath to write the extracted model
+pub fn slice_model(model_filepath: &String, start: usize, end: usize, output_filepath: &String) {
+    edit(model_filepath, output_filepath, &EditOperation::Slice(start, end));
+}
+
+
+/// Concatenate two models trained on the same bins
+///
+/// Parameter:
+///
+/// * model_filepath: the filepath to the first persisted model
+/// * other_model_filepath: the filepath to the model whose trees are appended
+/// * output_filepath: the filepath to write the merged model
+/// * force: merge the models even if they cannot be verified to be trained on the same bins,
+///   i.e. if either model is not in the binary format, or if the bins are different
+pub fn merge_models(
+    model_filepath: &String, other_model_filepath: &String, output_filepath: &String,
+    force: bool,
+) {
+    edit(model_filepath, output_filepath,
+         &EditOperation::Merge(other_model_filepath.clone(), force));
+}
+
+
+/// Multiply the outputs of all trees of a model by a factor
+///
+/// Parameter:
+///
+/// * model_filepath: the filepath to the persisted model
+/// * factor: the factor applied to the predictions of the trees
+/// * output_filepath: the filepath to write the rescaled model
+pub fn rescale_model(model_filepath: &String, factor: f32, output_filepath: &String) {
+    edit(model_filepath, output_filepath, &EditOperation::Rescale(factor));
+}
+
+
+/// Calibrate the scores of a model into probabilities on the validation data
+///
+/// Parameter:
+///
+/// * config_filepath: the filepath to the configuration file, which specifies the validation data
+/// * model_filepath: the filepath to the persisted model
+/// * method: the calibration method, `platt` or `isotonic`
+/// * output_filepath: the filepath to write the calibrated model
+pub fn calibrate_model(
+    config_filepath: &String, model_filepath: &String, method: &String, output_filepath: &String,
+) {
+    let config: Config = Config::new(config_filepath);
+    let validation_filename = config.validation_filename.as_ref().expect(
+        "The validation data for calibration is not set (`validation_filename`)");
+    calibrate(
+        model_filepath,
+        output_filepath,
+        &CalibrationMethod::new(method),
+        validation_filename,
+        config.num_validation_examples,
+        config.num_features,
+        &config.positive,
     );
 }
+
+
+/// Serve the predictions of a model over HTTP on localhost, and reload the model when
+/// a newer checkpoint is written. The bins are read from `models/bins.json`.
+///
+/// Parameter:
+///
+/// * model_path: the filepath to the persisted model, or the directory of the checkpoints
+///   written by the model manager, in which case the latest checkpoint is served
+/// * port: the port to listen on
+pub fn serve_model(model_path: &String, port: u16) {
+    serve(model_path, port);
+}
@@ -0,0 +1,341 @@
+use metricslib::validate as mvalidate;
+use metricslib::EvalFunc;
+
+use TLabel;
+
+// The number of equal-width bins of the probabilities for the calibration error
+const NUM_CALIBRATION_BINS: usize = 10;
+// The probabilities are clipped to `[EPSILON, 1 - EPSILON]` for the log-loss
+const EPSILON: f32 = 1e-7;
+
+
+/*
+The log-loss, the calibration error, and the confusion matrix are computed on the probabilities
+of the examples being positive, which are given by the calibrator if the model is calibrated, or
+`1 / (1 + exp(-2s))` for the score `s` otherwise. The labels are 1 for the positive examples,
+and -1 for the negative examples.
+*/
+/// The metrics for evaluating the models
+#[derive(Clone, Debug, PartialEq)]
+pub enum Metric {
+    Loss,
+    AUPRC,
+    AUROC,
+    ErrorRate,
+    LogLoss,
+    /// the fraction of the positive examples among the `k` examples with the highest scores
+    PrecisionAtK(usize),
+    /// the fraction of the positive examples that are among the `k` examples with
+    /// the highest scores
+    RecallAtK(usize),
+    /// the expected calibration error of the probabilities
+    CalibrationError,
+    /// the numbers of the true positive, false positive, true negative, and false negative
+    /// examples when the examples with the probabilities not smaller than the threshold are
+    /// classified as positive
+    ConfusionMatrix(f32),
+}
+
+impl Metric {
+    /// Parse a metric, e.g. `loss`, `auroc`, `precision@100`, or `confusion@0.5`, returns `None`
+    /// if the metric is not recognized
+    pub fn new(metric: &String) -> Option<Metric> {
+        let metric = metric.to_lowercase();
+        let (name, param) = {
+            let mut t = metric.splitn(2, '@');
+            (t.next().unwrap().to_string(), t.next().map(|t| t.to_string()))
+        };
+        match (name.as_str(), param.as_ref().map(|t| t.as_str())) {
+            ("loss", None)          => Some(Metric::Loss),
+            ("auprc", None)         => Some(Metric::AUPRC),
+            ("auroc", None)         => Some(Metric::AUROC),
+            ("error-rate", None)    => Some(Metric::ErrorRate),
+            ("log-loss", None)      => Some(Metric::LogLoss),
+            ("calibration", None)   => Some(Metric::CalibrationError),
+            ("precision", Some(k))  => k.parse().ok().map(|k| Metric::PrecisionAtK(k)),
+            ("recall", Some(k))     => k.parse().ok().map(|k| Metric::RecallAtK(k)),
+            ("confusion", Some(t))  => t.parse().ok().map(|t| Metric::ConfusionMatrix(t)),
+            _                       => None,
+        }
+    }
+
+    /// The names of the values of the metric, which has four values for the confusion matrix
+    /// and one value otherwise
+    pub fn get_names(&self) -> Vec<String> {
+        match self {
+            Metric::Loss                       => vec!["loss".to_string()],
+            Metric::AUPRC                      => vec!["auprc".to_string()],
+            Metric::AUROC                      => vec!["auroc".to_string()],
+            Metric::ErrorRate                  => vec!["error-rate".to_string()],
+            Metric::LogLoss                    => vec!["log-loss".to_string()],
+            Metric::PrecisionAtK(k)            => vec![format!("precision@{}", k)],
+            Metric::RecallAtK(k)               => vec![format!("recall@{}", k)],
+            Metric::CalibrationError           => vec!["calibration".to_string()],
+            Metric::ConfusionMatrix(threshold) => {
+                vec!["tp", "fp", "tn", "fn"].iter()
+                                            .map(|t| format!("{}@{}", t, threshold))
+                                            .collect()
+            },
+        }
+    }
+
+    /// Return true if the larger values of the metric are better, which is only defined
+    /// for the metrics with a single value
+    pub fn is_larger_better(&self) -> bool {
+        match self {
+            Metric::AUPRC | Metric::AUROC | Metric::PrecisionAtK(_) | Metric::RecallAtK(_) => true,
+            _ => false,
+        }
+    }
+
+    fn get_eval_func(&self) -> Option<EvalFunc> {
+        match self {
+            Metric::Loss      => Some(EvalFunc::AdaBoostLoss),
+            Metric::AUPRC     => Some(EvalFunc::AUPRC),
+            Metric::AUROC     => Some(EvalFunc::AUROC),
+            Metric::ErrorRate => Some(EvalFunc::ErrorRate),
+            _                 => None,
+        }
+    }
+}
+
+
+/// Evaluate the scores of the examples on each of `metrics`, and return the values in the order
+/// of the names given by `Metric::get_names`. `probs` are the calibrated probabilities of
+/// the scores, or `None` if the model is not calibrated.
+pub fn get_performance(
+    scores: &[f32], probs: Option<&[f32]>, labels: &[TLabel], metrics: &Vec<Metric>,
+) -> Vec<f32> {
+    // sorted by the scores in the descending order
+    let sorted_scores_labels = {
+        let mut scores_labels: Vec<(f32, f32)> =
+            scores.iter().zip(labels.iter())
+                  .map(|(a, b)| (*a as f32, *b as f32)).collect();
+        scores_labels.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().reverse());
+        scores_labels
+    };
+    let probs_labels: Vec<(f32, bool)> = match probs {
+        Some(probs) => {
+            probs.iter().zip(labels.iter()).map(|(prob, label)| (*prob, *label > 0)).collect()
+        },
+        None        => {
+            scores.iter().zip(labels.iter())
+                  .map(|(score, label)| (get_probability(*score), *label > 0)).collect()
+        },
+    };
+    metrics.iter().flat_map(|metric| {
+        if let Some(eval_func) = metric.get_eval_func() {
+            return mvalidate(&sorted_scores_labels, &vec![eval_func]);
+        }
+        match metric {
+            Metric::LogLoss                    => {
+                if probs.is_some() {
+                    vec![get_log_loss_of_probs(&probs_labels)]
+                } else {
+                    vec![get_log_loss(&sorted_scores_labels)]
+                }
+            },
+            Metric::PrecisionAtK(k)            => {
+                let (positive, k) = count_top_k_positive(&sorted_scores_labels, *k);
+                vec![if k > 0 { positive / k as f32 } else { 0.0 }]
+            },
+            Metric::RecallAtK(k)               => {
+                let (positive, _) = count_top_k_positive(&sorted_scores_labels, *k);
+                let (total, _) =
+                    count_top_k_positive(&sorted_scores_labels, sorted_scores_labels.len());
+                vec![if total > 0.0 { positive / total } else { 0.0 }]
+            },
+            Metric::CalibrationError           => vec![get_calibration_error(&probs_labels)],
+            Metric::ConfusionMatrix(threshold) => get_confusion_matrix(&probs_labels, *threshold),
+            _                                  => unreachable!(),
+        }
+    }).collect()
+}
+
+
+/// The probability of the score being positive without calibration
+pub fn get_probability(score: f32) -> f32 {
+    1.0 / (1.0 + (-2.0 * score).exp())
+}
+
+
+fn get_log_loss(scores_labels: &Vec<(f32, f32)>) -> f32 {
+    if scores_labels.is_empty() {
+        return 0.0;
+    }
+    // log(1 + exp(z)) computed without overflow
+    let softplus = |z: f32| z.max(0.0) + (-z.abs()).exp().ln_1p();
+    let sum: f32 = scores_labels.iter().map(|(score, label)| softplus(-2.0 * label * score)).sum();
+    sum / scores_labels.len() as f32
+}
+
+
+fn get_log_loss_of_probs(probs_labels: &Vec<(f32, bool)>) -> f32 {
+    if probs_labels.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = probs_labels.iter().map(|(prob, is_positive)| {
+        let prob = prob.max(EPSILON).min(1.0 - EPSILON);
+        if *is_positive { -prob.ln() } else { -(1.0 - prob).ln() }
+    }).sum();
+    sum / probs_labels.len() as f32
+}
+
+
+// Return the number of the positive examples among the top `k` examples, and the actual `k`
+fn count_top_k_positive(sorted_scores_labels: &Vec<(f32, f32)>, k: usize) -> (f32, usize) {
+    let k = k.min(sorted_scores_labels.len());
+    let positive = sorted_scores_labels[..k].iter().filter(|(_, label)| *label > 0.0).count();
+    (positive as f32, k)
+}
+
+
+fn get_calibration_error(probs_labels: &Vec<(f32, bool)>) -> f32 {
+    let total = probs_labels.len() as f32;
+    get_reliability_bins(probs_labels).iter().map(|(_, count, mean_prob, positive_rate)| {
+        (*count as f32 / total) * (mean_prob - positive_rate).abs()
+    }).sum()
+}
+
+
+/// Return the reliability diagram of the probabilities of the examples over equal-width bins,
+/// i.e. `(lower bound, upper bound, number of examples, mean probability, fraction of
+/// the positive examples)` of each non-empty bin
+pub fn get_reliability_diagram(
+    probs: &[f32], labels: &[TLabel],
+) -> Vec<(f32, f32, usize, f32, f32)> {
+    let probs_labels: Vec<(f32, bool)> =
+        probs.iter().zip(labels.iter()).map(|(prob, label)| (*prob, *label > 0)).collect();
+    let num_bins = NUM_CALIBRATION_BINS as f32;
+    get_reliability_bins(&probs_labels).into_iter().map(|(index, count, mean_prob, positive_rate)| {
+        (index as f32 / num_bins, (index + 1) as f32 / num_bins, count, mean_prob, positive_rate)
+    }).collect()
+}
+
+
+// (index, number of examples, mean probability, fraction of the positive examples) of
+// each non-empty bin
+fn get_reliability_bins(probs_labels: &Vec<(f32, bool)>) -> Vec<(usize, usize, f32, f32)> {
+    // (count, sum of the probabilities, number of the positive examples) of each bin
+    let mut bins = vec![(0, 0.0, 0.0); NUM_CALIBRATION_BINS];
+    probs_labels.iter().for_each(|(prob, is_positive)| {
+        let index = ((prob * NUM_CALIBRATION_BINS as f32) as usize).min(NUM_CALIBRATION_BINS - 1);
+        bins[index].0 += 1;
+        bins[index].1 += prob;
+        if *is_positive {
+            bins[index].2 += 1.0;
+        }
+    });
+    bins.into_iter().enumerate().filter(|(_, (count, _, _))| *count > 0)
+        .map(|(index, (count, sum_prob, positive))| {
+            (index, count, sum_prob / count as f32, positive / count as f32)
+        }).collect()
+}
+
+
+fn get_confusion_matrix(probs_labels: &Vec<(f32, bool)>, threshold: f32) -> Vec<f32> {
+    // true positive, false positive, true negative, false negative
+    let mut matrix = vec![0.0; 4];
+    probs_labels.iter().for_each(|(prob, is_positive)| {
+        let index = match (*prob >= threshold, *is_positive) {
+            (true, true)   => 0,
+            (true, false)  => 1,
+            (false, false) => 2,
+            (false, true)  => 3,
+        };
+        matrix[index] += 1.0;
+    });
+    matrix
+}
+
+
+#[cfg(test)]
+mod tests {
+    use TLabel;
+    use super::Metric;
+    use super::get_performance;
+    use super::get_reliability_diagram;
+
+    fn is_close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn test_parse_metrics() {
+        assert_eq!(Metric::new(&"AUROC".to_string()), Some(Metric::AUROC));
+        assert_eq!(Metric::new(&"log-loss".to_string()), Some(Metric::LogLoss));
+        assert_eq!(Metric::new(&"precision@100".to_string()), Some(Metric::PrecisionAtK(100)));
+        assert_eq!(Metric::new(&"recall@5".to_string()), Some(Metric::RecallAtK(5)));
+        assert_eq!(Metric::new(&"confusion@0.7".to_string()),
+                   Some(Metric::ConfusionMatrix(0.7)));
+        assert_eq!(Metric::new(&"precision".to_string()), None);
+        assert_eq!(Metric::new(&"recall@x".to_string()), None);
+        assert_eq!(Metric::new(&"auc".to_string()), None);
+        assert_eq!(Metric::ConfusionMatrix(0.5).get_names(),
+                   vec!["tp@0.5", "fp@0.5", "tn@0.5", "fn@0.5"]);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let scores = vec![2.0, -1.0, 0.5, 0.0, -0.2, 1.0];
+        let labels: Vec<TLabel> = vec![1, -1, -1, 1, -1, 1];
+        let metrics = vec![
+            Metric::LogLoss, Metric::PrecisionAtK(3), Metric::RecallAtK(2), Metric::RecallAtK(10),
+            Metric::ConfusionMatrix(0.5), Metric::CalibrationError,
+        ];
+        let values = get_performance(&scores, None, &labels, &metrics);
+        assert_eq!(values.len(), 9);
+
+        let expected_log_loss: f32 = scores.iter().zip(labels.iter()).map(|(s, y)| {
+            (1.0 + (-2.0 * *y as f32 * s).exp()).ln()
+        }).sum::<f32>() / 6.0;
+        assert!(is_close(values[0], expected_log_loss));
+        // the top 3 examples are 2.0 (+), 1.0 (+), 0.5 (-)
+        assert!(is_close(values[1], 2.0 / 3.0));
+        assert!(is_close(values[2], 2.0 / 3.0));
+        assert!(is_close(values[3], 1.0));
+        // the examples with the scores not smaller than 0 are predicted positive
+        assert_eq!(&values[4..8], &[3.0, 1.0, 2.0, 0.0]);
+
+        // perfectly calibrated if the probabilities in a bin match the fraction of positives
+        let scores = vec![0.0; 4];
+        let labels: Vec<TLabel> = vec![1, -1, 1, -1];
+        let calibration = vec![Metric::CalibrationError];
+        assert!(is_close(get_performance(&scores, None, &labels, &calibration)[0], 0.0));
+        let labels: Vec<TLabel> = vec![1, 1, 1, 1];
+        assert!(is_close(get_performance(&scores, None, &labels, &calibration)[0], 0.5));
+    }
+
+    #[test]
+    fn test_metrics_of_calibrated_probs() {
+        let scores = vec![2.0, -1.0, 0.5, 0.0];
+        let probs = vec![0.9, 0.2, 0.6, 0.3];
+        let labels: Vec<TLabel> = vec![1, -1, -1, 1];
+        let metrics = vec![
+            Metric::LogLoss, Metric::ConfusionMatrix(0.5), Metric::CalibrationError, Metric::AUROC,
+        ];
+        let values = get_performance(&scores, Some(&probs), &labels, &metrics);
+        let expected_log_loss = -(0.9f32.ln() + 0.8f32.ln() + 0.4f32.ln() + 0.3f32.ln()) / 4.0;
+        assert!(is_close(values[0], expected_log_loss));
+        // the score 0.0 has the uncalibrated probability 0.5, but the calibrated probability 0.3
+        assert_eq!(&values[1..5], &[1.0, 1.0, 1.0, 1.0]);
+        // all probabilities are in separate bins
+        assert!(is_close(values[5], (0.1 + 0.2 + 0.6 + 0.7) / 4.0));
+        // the ranking metrics are computed on the scores
+        assert_eq!(values[6], get_performance(&scores, None, &labels, &vec![Metric::AUROC])[0]);
+    }
+
+    #[test]
+    fn test_reliability_diagram() {
+        let probs = vec![0.05, 0.12, 0.18, 0.95, 1.0];
+        let labels: Vec<TLabel> = vec![-1, 1, -1, 1, 1];
+        let diagram = get_reliability_diagram(&probs, &labels);
+        assert_eq!(diagram.len(), 3);
+        assert_eq!((diagram[0].2, diagram[0].4), (1, 0.0));
+        assert!(is_close(diagram[1].0, 0.1) && is_close(diagram[1].1, 0.2));
+        assert_eq!(diagram[1].2, 2);
+        assert!(is_close(diagram[1].3, 0.15) && is_close(diagram[1].4, 0.5));
+        assert!(is_close(diagram[2].0, 0.9) && is_close(diagram[2].1, 1.0));
+        assert_eq!((diagram[2].2, diagram[2].4), (2, 1.0));
+    }
+}
@@ -0,0 +1,193 @@
+use rand::seq::sample_indices;
+
+use config::Config;
+use commons::get_rng;
+use commons::RNG_FEATURE_SAMPLER;
+
+
+/// Column sampling for the learner. A subset of the features is sampled for each tree,
+/// and a subset of the tree features is sampled for each candidate node of the tree.
+/// The samples only depend on the seed, the index of the tree and the index of the node,
+/// so that the same tree is always grown on the same features.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FeatureSampler {
+    num_features: usize,
+    fraction_per_tree: f32,
+    fraction_per_node: f32,
+    seed: u64,
+
+    tree_index: usize,
+    tree_features: Vec<usize>,
+    // node index -> (feature index -> is selected)
+    node_features: Vec<Vec<bool>>,
+}
+
+impl FeatureSampler {
+    pub fn new(num_features: usize, config: &Config) -> FeatureSampler {
+        FeatureSampler {
+            num_features: num_features,
+            fraction_per_tree: config.feature_fraction_per_tree,
+            fraction_per_node: config.feature_fraction_per_node,
+            seed: config.seed,
+
+            tree_index: 0,
+            tree_features: (0..num_features).collect(),
+            node_features: vec![],
+        }
+    }
+
+    /// Sample the features for the tree with the index `tree_index` in the model
+    pub fn sample_tree(&mut self, tree_index: usize) {
+        self.tree_index = tree_index;
+        self.tree_features = {
+            let amount = get_sample_size(self.num_features, self.fraction_per_tree);
+            if amount >= self.num_features {
+                (0..self.num_features).collect()
+            } else {
+                let mut rng = get_rng(self.seed, &[RNG_FEATURE_SAMPLER, tree_index]);
+                let mut features = sample_indices(&mut rng, self.num_features, amount);
+                features.sort();
+                features
+            }
+        };
+        self.node_features.clear();
+        debug!("feature-sampler, sampled tree features, {}, {}",
+               tree_index, self.tree_features.len());
+    }
+
+    /// Sample the features for all candidate nodes with an index smaller than `num_nodes`
+    pub fn sample_nodes(&mut self, num_nodes: usize) {
+        while self.node_features.len() < num_nodes {
+            let node_index = self.node_features.len();
+            let num_tree_features = self.tree_features.len();
+            let amount = get_sample_size(num_tree_features, self.fraction_per_node);
+            let mut mask = vec![false; self.num_features];
+            if amount >= num_tree_features {
+                self.tree_features.iter().for_each(|i| mask[*i] = true);
+            } else {
+                let mut rng = get_rng(
+                    self.seed, &[RNG_FEATURE_SAMPLER, self.tree_index, node_index + 1]);
+                sample_indices(&mut rng, num_tree_features, amount).into_iter()
+                    .for_each(|k| mask[self.tree_features[k]] = true);
+            }
+            self.node_features.push(mask);
+        }
+    }
+
+    pub fn get_tree_features(&self) -> &Vec<usize> {
+        &self.tree_features
+    }
+
+    /// Check if `feature` is sampled for the candidate node `node_index`
+    pub fn is_selected(&self, node_index: usize, feature: usize) -> bool {
+        if node_index < self.node_features.len() {
+            self.node_features[node_index][feature]
+        } else {
+            true
+        }
+    }
+}
+
+
+fn get_sample_size(num_features: usize, fraction: f32) -> usize {
+    let amount = (num_features as f32 * fraction).round() as usize;
+    if amount < 1 {
+        1
+    } else {
+        amount
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use commons::test_helper::get_test_config;
+    use super::FeatureSampler;
+
+    const NUM_FEATURES: usize = 20;
+
+    fn get_sampler(fraction_per_tree: f32, fraction_per_node: f32, seed: u64) -> FeatureSampler {
+        let mut config = get_test_config(NUM_FEATURES);
+        config.feature_fraction_per_tree = fraction_per_tree;
+        config.feature_fraction_per_node = fraction_per_node;
+        config.seed = seed;
+        FeatureSampler::new(NUM_FEATURES, &config)
+    }
+
+    fn get_node_features(sampler: &FeatureSampler, node_index: usize) -> Vec<usize> {
+        (0..NUM_FEATURES).filter(|i| sampler.is_selected(node_index, *i)).collect()
+    }
+
+    #[test]
+    fn test_sample_sizes() {
+        let mut sampler = get_sampler(0.5, 0.4, 1);
+        for tree_index in 0..5 {
+            sampler.sample_tree(tree_index);
+            sampler.sample_nodes(7);
+            assert_eq!(sampler.get_tree_features().len(), 10);
+            for node_index in 0..7 {
+                assert_eq!(get_node_features(&sampler, node_index).len(), 4);
+            }
+        }
+
+        // at least one feature is sampled
+        let mut sampler = get_sampler(0.01, 0.01, 1);
+        sampler.sample_tree(0);
+        sampler.sample_nodes(3);
+        assert_eq!(sampler.get_tree_features().len(), 1);
+        assert_eq!(get_node_features(&sampler, 2).len(), 1);
+
+        // all features are selected without sampling
+        let mut sampler = get_sampler(1.0, 1.0, 1);
+        sampler.sample_tree(3);
+        sampler.sample_nodes(3);
+        assert_eq!(sampler.get_tree_features(), &(0..NUM_FEATURES).collect::<Vec<usize>>());
+        assert_eq!(get_node_features(&sampler, 2), (0..NUM_FEATURES).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_node_features_in_tree_features() {
+        for fraction_per_node in vec![0.3, 1.0] {
+            let mut sampler = get_sampler(0.5, fraction_per_node, 2);
+            for tree_index in 0..5 {
+                sampler.sample_tree(tree_index);
+                sampler.sample_nodes(9);
+                let tree_features = sampler.get_tree_features().clone();
+                for node_index in 0..9 {
+                    let node_features = get_node_features(&sampler, node_index);
+                    assert!(node_features.iter().all(|i| tree_features.contains(i)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_resampling_is_reproducible() {
+        let mut sampler = get_sampler(0.5, 0.5, 3);
+        sampler.sample_tree(4);
+        sampler.sample_nodes(5);
+        let tree_features = sampler.get_tree_features().clone();
+        let node_features: Vec<Vec<usize>> =
+            (0..5).map(|node_index| get_node_features(&sampler, node_index)).collect();
+
+        // sample other trees in between, and the nodes in a different number of steps
+        let mut other = get_sampler(0.5, 0.5, 3);
+        other.sample_tree(1);
+        other.sample_nodes(3);
+        other.sample_tree(4);
+        other.sample_nodes(2);
+        other.sample_nodes(5);
+        assert_eq!(other.get_tree_features(), &tree_features);
+        for node_index in 0..5 {
+            assert_eq!(get_node_features(&other, node_index), node_features[node_index]);
+        }
+
+        // the samples depend on the seed, the tree and the node
+        let mut other = get_sampler(0.5, 0.5, 4);
+        other.sample_tree(4);
+        assert!(other.get_tree_features() != &tree_features);
+        sampler.sample_tree(5);
+        assert!(sampler.get_tree_features() != &tree_features);
+        assert!(node_features.iter().skip(1).any(|features| features != &node_features[0]));
+    }
+}
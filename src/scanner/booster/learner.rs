@@ -2,39 +2,41 @@ use rayon::prelude::*;
 
 use std::collections::HashMap;
 
-use Example;
 use TFeature;
-use commons::ExampleInSampleSet;
+use config::Config;
+use config::GrowthPolicy;
+use commons::tree::SplitStats;
 use commons::tree::Tree;
 use commons::bins::Bins;
+use commons::stopping_rule::StoppingRuleParams;
 
 use commons::is_zero;
+use super::feature_sampler::FeatureSampler;
 use super::learner_helpers;
+use super::scoreboard::ScoreBoard;
+use super::scoreboard::COUNT;
+use super::scoreboard::SLOT_SIZE;
+use super::scoreboard::WEIGHT;
+use super::scoreboard::get_offsets;
+use scanner::buffer_loader::batch::Batch;
 
 // TODO: The tree generation and score updates are for AdaBoost only,
 // extend it to other potential functions
 
 /*
 TODO: extend support to regression tasks
-TODO: re-use ScoreBoard space of the generated rules to reduce the memory footprint by half
-      (just adding a mapping from the index here to the index in the tree should do the job)
-
-ScoreBoard structure:
-(candidate splitting node index) -> (3-level Scoreboard)
-(3-level ScoreBoard): Feature index -> split value index -> prediction types
 
 Each split corresponds to 2 types of predictions,
     1. Left +1, Right -1;
     2. Left -1, Right +1;
+The statistics of the candidate weak rules are stored in `ScoreBoard`s (see `scoreboard.rs`).
 */
 pub const NUM_PREDS: usize = 2;
 const NUM_RULES: usize = NUM_PREDS;
 pub const PREDS: [(f32, f32); NUM_PREDS] = [(-1.0, 1.0), (1.0, -1.0)];
-// (pred1, false), (pred1, true), (pred2, false), (pred2, true)
-type ScoreBoard = Vec<Vec<[f32; NUM_RULES]>>;
-type ScoreBoard1 = Vec<Vec<f32>>;
-// (f32, f32) -> Stats if falls under left, and if falls under right
-pub type RuleStats = [[(f32, f32); 2]; NUM_PREDS];
+// The statistics are kept after a split only if the examples scanned since the last reset are
+// at most this many times the examples scanned since the last split (see `is_rescan_bounded`)
+const MAX_RESCAN_RATIO: usize = 2;
 
 
 /// A weak rule with an edge larger or equal to the targetting value of `gamma`
@@ -51,6 +53,7 @@ pub struct TreeNode {
     pub bound: f32,
     pub num_scanned: usize,
     pub fallback: bool,
+    pub stopping_rule: &'static str,
 
     pub positive: usize,
     pub negative: usize,
@@ -59,9 +62,26 @@ pub struct TreeNode {
 }
 
 impl TreeNode {
+    /// The statistics of the weak rule to be recorded on the tree
+    pub fn get_split_stats(&self) -> SplitStats {
+        SplitStats {
+            gamma:           self.gamma,
+            raw_martingale:  self.raw_martingale,
+            sum_c:           self.sum_c,
+            sum_c_squared:   self.sum_c_squared,
+            bound:           self.bound,
+            num_scanned:     self.num_scanned,
+            positive:        self.positive,
+            negative:        self.negative,
+            positive_weight: self.positive_weight,
+            negative_weight: self.negative_weight,
+            fallback:        self.fallback,
+        }
+    }
+
     pub fn write_log(&self) {
         info!(
-            "tree-node-info, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+            "tree-node-info, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
             self.prt_index,
             self.feature,
             self.threshold,
@@ -82,213 +102,418 @@ impl TreeNode {
             self.negative_weight,
 
             self.fallback,
+            self.stopping_rule,
         );
     }
 }
 
 
+/// Constraints on the shape of the trees and the values of their leaves
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TreeConstraints {
+    pub max_depth:       usize,
+    pub min_leaf_count:  usize,
+    pub min_leaf_weight: f32,
+    pub l1_reg:          f32,
+    pub l2_reg:          f32,
+}
+
+impl TreeConstraints {
+    pub fn new(config: &Config) -> TreeConstraints {
+        TreeConstraints {
+            max_depth:       config.max_depth,
+            min_leaf_count:  config.min_leaf_count,
+            min_leaf_weight: config.min_leaf_weight,
+            l1_reg:          config.l1_reg,
+            l2_reg:          config.l2_reg,
+        }
+    }
+
+    /// Check if a leaf at `depth` is allowed to be split
+    pub fn is_expandable(&self, depth: usize) -> bool {
+        self.max_depth == 0 || depth < self.max_depth
+    }
+
+    /// Check if both children of a split receive enough examples.
+    /// `left` and `right` are the (count, sum of weights) of the examples in each child.
+    pub fn is_valid_split(&self, left: (f32, f32), right: (f32, f32)) -> bool {
+        let min_count = self.min_leaf_count as f32;
+        left.0 >= min_count && right.0 >= min_count &&
+            left.1 >= self.min_leaf_weight && right.1 >= self.min_leaf_weight
+    }
+
+    /// Apply the L1 and L2 penalties to the prediction of a leaf whose examples sum up to
+    /// `weight`, i.e. return the minimizer of
+    /// `0.5 * weight * (x - predict)^2 + l1_reg * |x| + 0.5 * l2_reg * x^2`.
+    /// The penalties shrink the predictions of the leaves with small weights more.
+    pub fn regularize(&self, predict: f32, weight: f32) -> f32 {
+        if self.l1_reg == 0.0 && self.l2_reg == 0.0 {
+            return predict;
+        }
+        let weight = weight.max(0.0);
+        let shrinked = (weight * predict.abs() - self.l1_reg).max(0.0) / (weight + self.l2_reg);
+        if shrinked.is_finite() {
+            shrinked * predict.signum()
+        } else {
+            0.0
+        }
+    }
+}
+
+
 /// Statisitics of all weak rules that are being evaluated.
 /// The objective of `Learner` is to find a weak rule that satisfies the condition of
 /// the stopping rule.
-// [i][k][j][rule_id] => bin i slot j candidate-node k
 #[derive(Serialize, Deserialize)]
 pub struct Learner {
     bins: Vec<Bins>,
+    // the position of the first slot of each feature in the scoreboards
+    offsets: Vec<usize>,
     pub num_features:   usize,
     num_candid: usize,
 
     pub rho_gamma:        f32,
     pub _expand_node:      usize,
+    pub constraints:      TreeConstraints,
+    pub feature_sampler:  FeatureSampler,
+    growth_policy:        GrowthPolicy,
+    stopping_rule:        StoppingRuleParams,
     // global trackers
     pub total_count:  usize,
     total_weight:     f32,
     total_weight_sq:  f32,
-    // trackers for each candidate
-    weak_rules_score: Vec<ScoreBoard>,
-    sum_c_squared:    Vec<ScoreBoard>,
-    // trackers for debugging
-    num_positive:     Vec<ScoreBoard1>,
-    num_negative:     Vec<ScoreBoard1>,
-    weight_positive:  Vec<ScoreBoard1>,
-    weight_negative:  Vec<ScoreBoard1>,
-    // track the number of examples exposed to each splitting candidates
-    // count:            usize,
-    // sum_weight:       f32,
+    max_weight:       f32,
+    // trackers for the candidate nodes that are being scanned
+    scoreboards:      HashMap<usize, ScoreBoard>,
+    // the ranges of the examples in the sample set that are scanned since the last reset
+    scanned_ranges:   Vec<(usize, usize)>,
+    // the number of examples scanned since the last reset when the last split happened
+    split_count:      usize,
 }
 
 impl Learner {
     /// Create a `Learner` that search for valid weak rules.
     /// `gamma` is the initial value of the edge `gamma`.
     /// `bins` is vectors of the all thresholds on all candidate features for generating weak rules.
+    /// `constraints` restricts the splits and the leaf values of the generated weak rules.
+    /// `feature_sampler` decides the features to be scanned for each candidate node.
+    /// `growth_policy` decides which candidate node to split when several rules are valid.
+    /// `stopping_rule` decides when a weak rule is valid.
     pub fn new(
         gamma: f32,
         bins: Vec<Bins>,
         num_features: usize,
-        num_splits: usize,
+        constraints: TreeConstraints,
+        feature_sampler: FeatureSampler,
+        growth_policy: GrowthPolicy,
+        stopping_rule: StoppingRuleParams,
     ) -> Learner {
-        let mut learner = Learner {
+        Learner {
+            offsets: get_offsets(&bins),
             bins: bins,
             num_features: num_features.clone(),
             num_candid: 0,
 
             rho_gamma:        gamma.clone(),
             _expand_node:      0,
+            constraints:      constraints,
+            feature_sampler:  feature_sampler,
+            growth_policy:    growth_policy,
+            stopping_rule:    stopping_rule,
             total_count:      0,
             total_weight:     0.0,
             total_weight_sq:  0.0,
-            weak_rules_score: vec![],
-            sum_c_squared:    vec![],
-
-            num_positive:     vec![],
-            num_negative:     vec![],
-            weight_positive:  vec![],
-            weight_negative:  vec![],
-        };
-        let bin_size: Vec<usize> = learner.bins.iter().map(|bin| bin.len()).collect();
-        let new_scoreboard =
-            || bin_size.iter().map(|size| vec![[0.0; NUM_RULES]; *size]).collect();
-        let new_scoreboard1 = || bin_size.iter().map(|size| vec![0.0; *size]).collect();
-        let num_nodes = (num_splits + 1) * 2 - 1;
-        for _ in 0..num_nodes {
-            learner.weak_rules_score.push(new_scoreboard());
-            learner.sum_c_squared.push(new_scoreboard());
-            learner.num_positive.push(new_scoreboard1());
-            learner.num_negative.push(new_scoreboard1());
-            learner.weight_positive.push(new_scoreboard1());
-            learner.weight_negative.push(new_scoreboard1());
+            max_weight:       0.0,
+            scoreboards:      HashMap::new(),
+            scanned_ranges:   vec![],
+            split_count:      0,
         }
-        learner
     }
 
     /// Reset the statistics of all candidate weak rules
     /// (except gamma, because the advantage of the root node is not likely to increase)
-    /// Trigger when the model or the gamma is changed
+    /// Trigger when the model or the tree is changed
     pub fn reset(&mut self) {
-        for t in 0..self.num_candid {
-            for i in 0..self.num_features {
-                for j in 0..self.bins[i].len() {
-                    for k in 0..NUM_RULES {
-                        self.weak_rules_score[t][i][j][k] = 0.0;
-                        self.sum_c_squared[t][i][j][k]    = 0.0;
-                    }
-                    self.num_positive[t][i][j] = 0.0;
-                    self.num_negative[t][i][j] = 0.0;
-                    self.weight_positive[t][i][j] = 0.0;
-                    self.weight_negative[t][i][j] = 0.0;
-                }
-            }
-        }
+        // free the scoreboards, they are allocated again once the nodes are scanned
+        self.scoreboards.clear();
+        self.scanned_ranges.clear();
+        self.split_count = 0;
         trace!("learner, learner is reset, {}, {}, {}",
                self._expand_node, self.total_count, self.total_weight);
         self.total_count = 0;
         self.total_weight = 0.0;
         self.total_weight_sq = 0.0;
+        self.max_weight = 0.0;
     }
 
     pub fn get_max_empirical_ratio_tree_node(&self) -> TreeNode {
         let mut max_ratio = 0.0;
         let mut actual_ratio = 0.0;
-        let mut rule_id = (0, 0, 0, 0);
-        for t in 0..self.num_candid {
+        let mut rule_id = None;
+        let mut candidates: Vec<&usize> = self.scoreboards.keys().collect();
+        candidates.sort();
+        for t in candidates {
+            let scoreboard = &self.scoreboards[t];
             for i in 0..self.num_features {
-                for j in 0..self.bins[i].len() {
+                if !self.feature_sampler.is_selected(*t, i) {
+                    continue;
+                }
+                let mut left = [0.0; SLOT_SIZE];
+                let histogram = scoreboard.get_histogram(&self.offsets, i);
+                for (j, slot) in histogram.chunks(SLOT_SIZE).take(self.bins[i].len()).enumerate() {
+                    for k in 0..SLOT_SIZE {
+                        left[k] += slot[k];
+                    }
+                    if !self.is_valid_split(scoreboard, &left) {
+                        continue;
+                    }
                     for k in 0..NUM_RULES {
                         // max ratio considers absent examples, actual ratio does not
-                        let ratio = self.weak_rules_score[t][i][j][k] / self.total_weight;
+                        let (weak_rules_score, _) =
+                            learner_helpers::get_rule_scores(&left, &scoreboard.totals, k);
+                        let ratio = weak_rules_score / self.total_weight;
                         if ratio >= max_ratio {
                             max_ratio = ratio;
                             actual_ratio = ratio;
-                            rule_id = (t, i, j, k);
+                            rule_id = Some((*t, i, j, k));
                         }
                     }
                 }
             }
         }
 
-        let (t, i, j, k) = rule_id;
-        let mut tree_node = learner_helpers::gen_tree_node(t, i, j, k, actual_ratio);
-        let weak_rules_score = self.weak_rules_score[t][i][j][k];
+        if rule_id.is_none() {
+            return learner_helpers::gen_tree_node(
+                0, 0, 0, 0, 0.0, (0.0, 0.0), &self.constraints);
+        }
+        let (t, i, j, k) = rule_id.unwrap();
+        let scoreboard = &self.scoreboards[&t];
+        let mut left = [0.0; SLOT_SIZE];
+        scoreboard.get_histogram(&self.offsets, i).chunks(SLOT_SIZE).take(j + 1).for_each(|slot| {
+            for k in 0..SLOT_SIZE {
+                left[k] += slot[k];
+            }
+        });
+        let weights = (left[WEIGHT], scoreboard.totals.weight - left[WEIGHT]);
+        let mut tree_node = learner_helpers::gen_tree_node(
+            t, i, j, k, actual_ratio, weights, &self.constraints);
+        let (weak_rules_score, weighted_score) =
+            learner_helpers::get_rule_scores(&left, &scoreboard.totals, k);
         let gamma = self.rho_gamma;
+        let (positive, negative, positive_weight, negative_weight) =
+            scoreboard.totals.get_debug_info();
         tree_node.raw_martingale  = weak_rules_score;
         tree_node.sum_c           = weak_rules_score - 2.0 * gamma * self.total_weight;
-        tree_node.sum_c_squared   = self.sum_c_squared[t][i][j][k]
-                                    + 4.0 * gamma * gamma * self.total_weight_sq;
+        tree_node.sum_c_squared   = learner_helpers::get_sum_c_squared(
+            scoreboard.totals.weight_sq, weighted_score, gamma, self.total_weight_sq);
         tree_node.bound           = self.total_weight;
         tree_node.num_scanned     = self.total_count;
-        tree_node.positive        = self.num_positive[t][i][j] as usize;
-        tree_node.negative        = self.num_negative[t][i][j] as usize;
-        tree_node.positive_weight = self.weight_positive[t][i][j];
-        tree_node.negative_weight = self.weight_negative[t][i][j];
+        tree_node.positive        = positive;
+        tree_node.negative        = negative;
+        tree_node.positive_weight = positive_weight;
+        tree_node.negative_weight = negative_weight;
         tree_node
     }
 
+    // `left` sums the statistics of the examples that fall into the left child of the split
+    fn is_valid_split(&self, scoreboard: &ScoreBoard, left: &[f32]) -> bool {
+        let totals = &scoreboard.totals;
+        self.constraints.is_valid_split(
+            (left[COUNT], left[WEIGHT]), (totals.count - left[COUNT], totals.weight - left[WEIGHT]))
+    }
+
     /// Update the statistics of all candidate weak rules using current batch of
     /// training examples.
     pub fn update(
         &mut self,
         tree: &Tree,
-        data: &[ExampleInSampleSet],
+        batch: &Batch,
     ) -> Option<TreeNode> {
-        // update global stats
+        // update global stats, the parallel sums are not deterministic due to the order of
+        // the floating point additions
+        let data = batch.examples;
         self.total_count       += data.len();
         self.total_weight      += data.par_iter().map(|t| (t.1).0).sum::<f32>();
         self.total_weight_sq   += data.par_iter().map(|t| ((t.1).0) * ((t.1).0)).sum::<f32>();
+        self.max_weight         = data.iter().fold(self.max_weight, |acc, t| acc.max((t.1).0));
         self.num_candid         = tree.num_nodes;
+        self.scanned_ranges.push(batch.get_range());
+        self.feature_sampler.sample_nodes(self.num_candid);
 
         let rho_gamma = self.rho_gamma;
 
-        // preprocess examples - Complexity: O(Examples * NumRules)
-        let data: Vec<(usize, f32, (&Example, RuleStats))> = learner_helpers::preprocess_data(
-            data, tree, rho_gamma);
+        // preprocess examples - Complexity: O(Examples)
+        let (leaves, weights, labeled_weights) = learner_helpers::preprocess_data(batch, tree);
+        let labels = batch.get_labels();
 
         // Put examples into bins by where they fall on the tree - Complexity: O(Examples)
-        let mut data_by_node: HashMap<usize, Vec<(f32, (&Example, RuleStats))>> = HashMap::new();
-        data.into_iter().for_each(|(index, weight, stats)| {
-            data_by_node.entry(index).or_insert(Vec::new()).push((weight, stats));
+        let mut data_by_node: HashMap<usize, Vec<usize>> = HashMap::new();
+        leaves.into_iter().enumerate().for_each(|(row, index)| {
+            data_by_node.entry(index).or_insert(Vec::new()).push(row);
         });
+        // free the scoreboards of the nodes that are no longer leaves
+        self.scoreboards.retain(|index, _| tree.is_leaf(*index));
 
         // Update each weak rule - Complexity: O(Bins * Splits)
         let count = self.total_count;
         let total_weight = self.total_weight;
         let total_weight_sq = self.total_weight_sq;
+        let max_weight = self.max_weight;
+        let stopping_rule = self.stopping_rule.build();
+        let stopping_rule = stopping_rule.as_ref();
+        let constraints = &self.constraints;
+        let feature_sampler = &self.feature_sampler;
+        let offsets = &self.offsets;
+        let num_slots = *offsets.last().unwrap();
+        let keep_best = self.growth_policy == GrowthPolicy::BestFirst;
+        // the level-wise policy only expands the shallowest expandable leaves
+        let level = (0..self.num_candid)
+            .filter(|index| tree.is_leaf(*index))
+            .map(|index| tree.get_depth(index))
+            .filter(|depth| constraints.is_expandable(*depth))
+            .min();
+        let mut best_tree_node: Option<TreeNode> = None;
         for index in 0..self.num_candid { // Splitting node candidate index
-            if !data_by_node.contains_key(&index) {
+            if !data_by_node.contains_key(&index) ||
+                    !constraints.is_expandable(tree.get_depth(index)) {
                 continue;
             }
-            let data = &data_by_node[&index];
-            let tree_node = {
-                // all bins read data in parallel
-                self.bins.par_iter().zip(
-                    self.weak_rules_score[index].par_iter_mut()
-                ).zip(
-                    self.sum_c_squared[index].par_iter_mut()
-                ).zip(
-                    self.num_positive[index].par_iter_mut()
-                        .zip(self.num_negative[index].par_iter_mut())
-                        .zip(self.weight_positive[index].par_iter_mut())
-                        .zip(self.weight_negative[index].par_iter_mut())
-                ).enumerate()
-                .map(|(i, zipped_values)| {
-                    let (((bin, weak_rules_score), sum_c_squared), debug_info) = zipped_values;
-                    learner_helpers::find_tree_node(
-                        &data, i, rho_gamma, count, total_weight, total_weight_sq,
-                        index, bin, weak_rules_score, sum_c_squared, debug_info)
-                })
-                .find_any(|t| t.is_some())
-                .unwrap_or(None)
+            let rows = &data_by_node[&index];
+            // allocate the scoreboard when the node is scanned for the first time
+            let scoreboard = self.scoreboards.entry(index)
+                                             .or_insert_with(|| ScoreBoard::new(num_slots));
+            let is_searched = {
+                if self.growth_policy == GrowthPolicy::LevelWise {
+                    best_tree_node.is_none() && Some(tree.get_depth(index)) == level
+                } else {
+                    self.growth_policy == GrowthPolicy::BestFirst || best_tree_node.is_none()
+                }
             };
-            if tree_node.is_some() {
-                return tree_node;
+            if !is_searched {
+                // the statistics of the nodes that are not searched, i.e. the nodes after
+                // the one with a valid rule or the ones not on the level being expanded,
+                // are kept up-to-date, so that they sum all scanned examples as the histogram
+                // subtraction in `split` expects
+                let features: Vec<bool> = (0..self.num_features).map(|i| {
+                    feature_sampler.is_selected(index, i)
+                }).collect();
+                scoreboard.add_examples(
+                    offsets, rows, batch, &weights, &labeled_weights, &features);
+                continue;
+            }
+            rows.iter().for_each(|k| scoreboard.totals.add(weights[*k], labels[*k]));
+            let totals = scoreboard.totals;
+            // all bins read data in parallel
+            let candidates = self.bins.par_iter().zip(
+                scoreboard.split_by_features(offsets).into_par_iter()
+            ).enumerate()
+            .map(|(i, (bin, histogram))| {
+                if !feature_sampler.is_selected(index, i) {
+                    return None;
+                }
+                learner_helpers::find_tree_node(
+                    rows, batch.get_column(i), &weights, &labeled_weights, i,
+                    rho_gamma, count, total_weight, total_weight_sq, max_weight,
+                    index, &totals, bin, histogram, constraints, stopping_rule, keep_best)
+            });
+            if self.growth_policy == GrowthPolicy::BestFirst {
+                // all candidates share the same total weight, so the rule with the largest
+                // martingale has the largest empirical edge
+                let tree_node = candidates.filter_map(|t| t)
+                                          .max_by(|a, b| {
+                                              a.raw_martingale.partial_cmp(&b.raw_martingale)
+                                                              .unwrap()
+                                          });
+                best_tree_node = max_edge_tree_node(best_tree_node, tree_node);
+            } else {
+                // all candidates are collected, so that the histograms of all features are
+                // updated, and the first valid rule is independent of the thread scheduling
+                best_tree_node = candidates.collect::<Vec<Option<TreeNode>>>()
+                                           .into_iter()
+                                           .filter_map(|t| t)
+                                           .next();
+            }
+        }
+        best_tree_node
+    }
+
+    /// The ranges of the examples in the sample set that are scanned since the last reset
+    pub fn get_scanned_ranges(&self) -> &Vec<(usize, usize)> {
+        &self.scanned_ranges
+    }
+
+    /// Check if the examples scanned since the last reset, which `split` scans again, are at
+    /// most `MAX_RESCAN_RATIO` times the new examples scanned since the last split.
+    /// Keeping the statistics only in this case bounds the examples scanned again in total
+    /// to `MAX_RESCAN_RATIO` times the new examples.
+    pub fn is_rescan_bounded(&self) -> bool {
+        self.total_count <= MAX_RESCAN_RATIO * (self.total_count - self.split_count)
+    }
+
+    /// Keep the statistics after the node `parent` is split on `tree`, instead of resetting
+    /// the learner. The scoreboard of the child with fewer examples is rebuilt from `batches`,
+    /// which are the examples scanned since the last reset (see `get_scanned_ranges`), and
+    /// the scoreboard of its sibling is the scoreboard of the parent minus the one of the child.
+    /// The histograms of the features that were not sampled for the parent are rebuilt for
+    /// both children.
+    ///
+    /// The learner is reset if the statistics of the parent are not available.
+    pub fn split(&mut self, tree: &Tree, parent: usize, batches: &[Batch]) {
+        let (feature, threshold, left, right) = tree.get_split(parent).unwrap();
+        self.split_count = self.total_count;
+        let parent_scoreboard = self.scoreboards.remove(&parent);
+        if parent_scoreboard.is_none() {
+            self.reset();
+            return;
+        }
+        let parent_scoreboard = parent_scoreboard.unwrap();
+        let left_count: f32 = parent_scoreboard.get_histogram(&self.offsets, feature)
+                                               .chunks(SLOT_SIZE)
+                                               .take(threshold as usize + 1)
+                                               .map(|slot| slot[COUNT])
+                                               .sum();
+        let (small, large) = {
+            if 2.0 * left_count <= parent_scoreboard.totals.count {
+                (left, right)
+            } else {
+                (right, left)
+            }
+        };
+        let num_slots = *self.offsets.last().unwrap();
+        let mut small_scoreboard = ScoreBoard::new(num_slots);
+        let mut large_scoreboard = ScoreBoard::new(num_slots);
+        let all_features = vec![true; self.num_features];
+        let missing_features: Vec<bool> = (0..self.num_features).map(|i| {
+            !self.feature_sampler.is_selected(parent, i)
+        }).collect();
+        for batch in batches {
+            let (leaves, weights, labeled_weights) = learner_helpers::preprocess_data(batch, tree);
+            let small_rows: Vec<usize> = (0..leaves.len()).filter(|k| leaves[*k] == small)
+                                                          .collect();
+            let large_rows: Vec<usize> = (0..leaves.len()).filter(|k| leaves[*k] == large)
+                                                          .collect();
+            small_scoreboard.add_examples(
+                &self.offsets, &small_rows, batch, &weights, &labeled_weights, &all_features);
+            large_scoreboard.add_examples(
+                &self.offsets, &large_rows, batch, &weights, &labeled_weights, &missing_features);
+        }
+        let sampled_features: Vec<bool> = missing_features.iter().map(|t| !t).collect();
+        large_scoreboard.set_difference(
+            &self.offsets, &parent_scoreboard, &small_scoreboard, &sampled_features);
+        debug!("learner, split statistics, {}, {}, {}, {}, {}",
+               parent, small, small_scoreboard.totals.count, large, large_scoreboard.totals.count);
+        for (index, scoreboard) in vec![(small, small_scoreboard), (large, large_scoreboard)] {
+            if self.constraints.is_expandable(tree.get_depth(index)) {
+                self.scoreboards.insert(index, scoreboard);
             }
         }
-        None
     }
 
     pub fn set_gamma(&mut self, gamma: f32) -> bool {
         if !is_zero(gamma - self.rho_gamma) {
             debug!("set-gamma, {}, {}", self.rho_gamma, gamma);
+            // the accumulated statistics do not depend on gamma, so they are kept
             self.rho_gamma = gamma;
-            self.reset();
             true
         } else {
             false
@@ -305,3 +530,444 @@ impl Learner {
         }
     }
 }
+
+
+fn max_edge_tree_node(a: Option<TreeNode>, b: Option<TreeNode>) -> Option<TreeNode> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            if b.raw_martingale > a.raw_martingale {
+                Some(b)
+            } else {
+                Some(a)
+            }
+        },
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+
+    use TFeature;
+    use TLabel;
+    use config::GrowthPolicy;
+    use commons::ExampleInSampleSet;
+    use commons::io::create_bufreader;
+    use commons::io::read_k_labeled_data;
+    use commons::stopping_rule::MartingaleStats;
+    use commons::stopping_rule::StoppingRuleParams;
+    use commons::test_helper::get_synthetic_bins;
+    use commons::test_helper::get_test_config;
+    use commons::tree::Tree;
+    use scanner::buffer_loader::batch::Batch;
+    use scanner::buffer_loader::batch::ColumnMajorSample;
+    use super::super::feature_sampler::FeatureSampler;
+    use super::super::learner_helpers::get_rule_scores;
+    use super::super::learner_helpers::get_sum_c_squared;
+    use super::super::scoreboard::SLOT_SIZE;
+    use super::Learner;
+    use super::NUM_RULES;
+    use super::TreeConstraints;
+    use super::TreeNode;
+
+    const SAMPLE_FILENAME: &str = "tests/data/sample_scan.libsvm";
+    const SAMPLE_NUM_EXAMPLES: usize = 1500;
+    const SAMPLE_NUM_FEATURES: usize = 16;
+    const SAMPLE_NUM_BINS: usize = 32;
+
+    // The sample dataset has 16 features with the bin indices 0 to 31 as the values, and
+    // the labels depend on the features 0, 3, and 5 with 10% noise
+    fn get_sample_data() -> Vec<ExampleInSampleSet> {
+        let mut reader = create_bufreader(&SAMPLE_FILENAME.to_string());
+        let examples = read_k_labeled_data::<TFeature, TLabel>(
+            &mut reader, SAMPLE_NUM_EXAMPLES, 0, SAMPLE_NUM_FEATURES, &"1".to_string());
+        examples.into_iter().map(|example| (example, (1.0, 0.0, 0, 0))).collect()
+    }
+
+    fn get_learner(gamma: f32) -> Learner {
+        get_learner_with_policy(gamma, "first-to-fire")
+    }
+
+    fn get_learner_with_policy(gamma: f32, growth_policy: &str) -> Learner {
+        let mut config = get_test_config(SAMPLE_NUM_FEATURES);
+        config.growth_policy = growth_policy.to_string();
+        Learner::new(
+            gamma, get_synthetic_bins(SAMPLE_NUM_FEATURES, SAMPLE_NUM_BINS), SAMPLE_NUM_FEATURES,
+            TreeConstraints::new(&config), FeatureSampler::new(SAMPLE_NUM_FEATURES, &config),
+            GrowthPolicy::new(&config.growth_policy), StoppingRuleParams::new(&config))
+    }
+
+    fn get_batches<'a>(
+        data: &'a Vec<ExampleInSampleSet>, sample: &'a ColumnMajorSample, batch_size: usize,
+    ) -> Vec<Batch<'a>> {
+        (0..data.len()).step_by(batch_size).map(|begin| {
+            let end = (begin + batch_size).min(data.len());
+            Batch::new(&data[begin..end], sample, begin)
+        }).collect()
+    }
+
+    // Scan the batches for at most `num_passes` passes until a valid rule is found
+    fn find_rule(
+        learner: &mut Learner, tree: &Tree, batches: &Vec<Batch>, num_passes: usize,
+    ) -> Option<TreeNode> {
+        for _ in 0..num_passes {
+            for batch in batches {
+                let rule = learner.update(tree, batch);
+                if rule.is_some() {
+                    return rule;
+                }
+            }
+        }
+        None
+    }
+
+    fn get_constraints(l1_reg: f32, l2_reg: f32) -> TreeConstraints {
+        TreeConstraints {
+            max_depth:       0,
+            min_leaf_count:  10,
+            min_leaf_weight: 5.0,
+            l1_reg:          l1_reg,
+            l2_reg:          l2_reg,
+        }
+    }
+
+    #[test]
+    fn test_regularize() {
+        let constraints = get_constraints(0.0, 0.0);
+        assert_eq!(constraints.regularize(0.3, 10.0), 0.3);
+        assert_eq!(constraints.regularize(-0.3, 0.0), -0.3);
+
+        let constraints = get_constraints(1.0, 2.0);
+        // (10.0 * 0.3 - 1.0) / (10.0 + 2.0)
+        assert!((constraints.regularize(0.3, 10.0) - 2.0 / 12.0).abs() < 1e-6);
+        assert!((constraints.regularize(-0.3, 10.0) + 2.0 / 12.0).abs() < 1e-6);
+        // the penalties shrink the predictions on the leaves with more weights less
+        assert!(constraints.regularize(0.3, 1000.0) > constraints.regularize(0.3, 10.0));
+        assert!((constraints.regularize(0.3, 1e6) - 0.3).abs() < 1e-3);
+        // the L1 penalty zeros the leaves with small weights
+        assert_eq!(constraints.regularize(0.3, 2.0), 0.0);
+        assert_eq!(constraints.regularize(-0.3, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_is_valid_split() {
+        let constraints = get_constraints(0.0, 0.0);
+        assert!(constraints.is_valid_split((10.0, 5.0), (20.0, 8.0)));
+        assert!(!constraints.is_valid_split((9.0, 5.0), (20.0, 8.0)));
+        assert!(!constraints.is_valid_split((10.0, 5.0), (20.0, 4.9)));
+        assert!(!constraints.is_valid_split((20.0, 8.0), (9.0, 5.0)));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, 100);
+        let mut tree = Tree::new(10);
+        let rule = find_rule(&mut get_learner(0.02), &tree, &batches, 4).unwrap();
+        tree.split(rule.prt_index, rule.feature, rule.threshold, rule.predict.0, rule.predict.1);
+        // the children of the root are split without the limit
+        let rule = find_rule(&mut get_learner(0.02), &tree, &batches, 4).unwrap();
+        assert_eq!(tree.get_depth(rule.prt_index), 1);
+
+        let mut learner = get_learner(0.02);
+        learner.constraints.max_depth = 1;
+        assert!(find_rule(&mut learner, &tree, &batches, 4).is_none());
+        assert!(learner.scoreboards.is_empty());
+    }
+
+    #[test]
+    fn test_regularized_rules() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, 100);
+        let tree = Tree::new(3);
+        let rule = find_rule(&mut get_learner(0.05), &tree, &batches, 2).unwrap();
+        // the L1 penalty is larger than the unregularized predictions, but the children
+        // with large weights still receive non-zero predictions
+        let mut learner = get_learner(0.05);
+        learner.constraints.l1_reg = 1.0;
+        learner.constraints.l2_reg = 1.0;
+        let regularized = find_rule(&mut learner, &tree, &batches, 2).unwrap();
+        assert!(rule.predict.0.abs() < learner.constraints.l1_reg);
+        assert!(regularized.predict.0 != 0.0 || regularized.predict.1 != 0.0);
+        assert!(regularized.predict.0.abs() <= rule.predict.0.abs());
+        assert!(regularized.predict.1.abs() <= rule.predict.1.abs());
+    }
+
+    // The candidate nodes and the martingales of all weak rules that satisfy the stopping rule,
+    // computed from the statistics kept by the learner
+    fn get_valid_rules(learner: &Learner) -> Vec<(usize, f32)> {
+        let stopping_rule = learner.stopping_rule.build();
+        let gamma = learner.rho_gamma;
+        let range_scale = 1.0 + 2.0 * gamma;
+        let mut rules = vec![];
+        for (index, scoreboard) in learner.scoreboards.iter() {
+            for i in 0..learner.num_features {
+                let mut left = [0.0; SLOT_SIZE];
+                let histogram = scoreboard.get_histogram(&learner.offsets, i);
+                for slot in histogram.chunks(SLOT_SIZE).take(learner.bins[i].len()) {
+                    for k in 0..SLOT_SIZE {
+                        left[k] += slot[k];
+                    }
+                    if !learner.is_valid_split(scoreboard, &left) {
+                        continue;
+                    }
+                    for k in 0..NUM_RULES {
+                        let (score, weighted_score) =
+                            get_rule_scores(&left, &scoreboard.totals, k);
+                        let sum_c = score - 2.0 * gamma * learner.total_weight;
+                        let bound = stopping_rule.get_bound(&MartingaleStats {
+                            sum_c:             sum_c,
+                            sum_c_squared:     get_sum_c_squared(
+                                scoreboard.totals.weight_sq, weighted_score, gamma,
+                                learner.total_weight_sq),
+                            sum_range_squared: range_scale * range_scale * learner.total_weight_sq,
+                            max_range:         range_scale * learner.max_weight,
+                            count:             learner.total_count,
+                        });
+                        if sum_c > 0.0 && sum_c > bound {
+                            rules.push((*index, score));
+                        }
+                    }
+                }
+            }
+        }
+        rules
+    }
+
+    #[test]
+    fn test_best_first() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, data.len());
+        let mut tree = Tree::new(10);
+        tree.split(0, 10, 13, 0.0, 0.0);
+        // valid rules are found on both children of the root in the same batch
+        let mut learner = get_learner_with_policy(0.01, "best-first");
+        let rule = find_rule(&mut learner, &tree, &batches, 4).unwrap();
+        let rules = get_valid_rules(&learner);
+        assert!(rules.iter().any(|t| t.0 == 1) && rules.iter().any(|t| t.0 == 2));
+        // the rule with the largest martingale is selected, not the first one to fire
+        let best = rules.iter().fold(0.0f32, |acc, t| acc.max(t.1));
+        assert!((rule.raw_martingale - best).abs() < 1e-3);
+        assert_eq!(rule.prt_index, 2);
+        let rule = find_rule(&mut get_learner_with_policy(0.01, "first-to-fire"),
+                             &tree, &batches, 4).unwrap();
+        assert_eq!(rule.prt_index, 1);
+    }
+
+    #[test]
+    fn test_level_wise() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, 100);
+        let mut tree = Tree::new(10);
+        tree.split(0, 10, 24, 0.0, 0.0);
+        tree.split(1, 3, 15, 0.0, 0.0);
+        // the first rule to fire is on a leaf deeper than the leaf 2
+        let rule = find_rule(&mut get_learner(0.01), &tree, &batches, 10).unwrap();
+        assert_eq!(tree.get_depth(rule.prt_index), 2);
+
+        let mut learner = get_learner_with_policy(0.01, "level-wise");
+        let mut num_splits = 0;
+        while let Some(rule) = find_rule(&mut learner, &tree, &batches, 10) {
+            let level = (0..tree.num_nodes).filter(|index| tree.is_leaf(*index))
+                                           .map(|index| tree.get_depth(index))
+                                           .min();
+            assert_eq!(Some(tree.get_depth(rule.prt_index)), level);
+            tree.split(rule.prt_index, rule.feature, rule.threshold,
+                       rule.predict.0, rule.predict.1);
+            learner.reset();
+            num_splits += 1;
+        }
+        // the leaf 2 is split before the leaves on the next level
+        assert!(num_splits >= 2);
+        assert!(!tree.is_leaf(2));
+    }
+
+    #[test]
+    fn test_split_statistics() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, 100);
+        // a large gamma so that the stopping rule never fires
+        let mut learner = get_learner(0.49);
+        let mut tree = Tree::new(3);
+        batches.iter().for_each(|batch| assert!(learner.update(&tree, batch).is_none()));
+        tree.split(0, 3, 7, 0.1, -0.1);
+        let ranges = learner.get_scanned_ranges().clone();
+        let rescanned: Vec<Batch> = ranges.iter().map(|(begin, end)| {
+            Batch::new(&data[*begin..*end], &sample, *begin)
+        }).collect();
+        learner.split(&tree, 0, &rescanned);
+
+        // the statistics derived from the parent are the same as the ones scanned directly
+        let mut expected = get_learner(0.49);
+        batches.iter().for_each(|batch| assert!(expected.update(&tree, batch).is_none()));
+        for index in 1..3 {
+            let (a, b) = (&learner.scoreboards[&index], &expected.scoreboards[&index]);
+            assert_eq!(a.totals.count, b.totals.count);
+            for i in 0..SAMPLE_NUM_FEATURES {
+                a.get_histogram(&learner.offsets, i).iter()
+                 .zip(b.get_histogram(&expected.offsets, i).iter())
+                 .for_each(|(x, y)| assert!((x - y).abs() < 1e-3));
+            }
+        }
+        assert_eq!(learner.total_count, data.len());
+    }
+
+    // Check if the statistics of all leaves kept by `learner` are the same as the ones of
+    // scanning `batches` directly on `tree`
+    fn check_leaf_statistics(learner: &Learner, tree: &Tree, batches: &Vec<Batch>) {
+        let mut expected = get_learner(0.49);
+        batches.iter().for_each(|batch| assert!(expected.update(tree, batch).is_none()));
+        for (index, a) in learner.scoreboards.iter() {
+            assert!(tree.is_leaf(*index));
+            if a.totals.count == 0.0 {
+                // no example falls into the leaf
+                assert!(!expected.scoreboards.contains_key(index));
+                continue;
+            }
+            let b = &expected.scoreboards[index];
+            assert_eq!(a.totals.count, b.totals.count);
+            assert!((a.totals.weight - b.totals.weight).abs() < 1e-3);
+            for i in 0..SAMPLE_NUM_FEATURES {
+                a.get_histogram(&learner.offsets, i).iter()
+                 .zip(b.get_histogram(&expected.offsets, i).iter())
+                 .for_each(|(x, y)| assert!((x - y).abs() < 1e-3));
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_statistics_multiple_splits() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, 100);
+        // the policies that stop searching the nodes once a valid rule is found
+        for growth_policy in vec!["first-to-fire", "level-wise"] {
+            let mut learner = get_learner_with_policy(0.02, growth_policy);
+            let mut tree = Tree::new(6);
+            let mut k = 0;
+            while tree.num_nodes < 13 && k < 100 * batches.len() {
+                let batch = &batches[k % batches.len()];
+                k += 1;
+                if let Some(rule) = learner.update(&tree, batch) {
+                    tree.split(rule.prt_index, rule.feature, rule.threshold,
+                               rule.predict.0, rule.predict.1);
+                    let rescanned: Vec<Batch> = learner.get_scanned_ranges().iter()
+                        .map(|(begin, end)| Batch::new(&data[*begin..*end], &sample, *begin))
+                        .collect();
+                    learner.split(&tree, rule.prt_index, &rescanned);
+                    check_leaf_statistics(&learner, &tree, &rescanned);
+                }
+            }
+            assert_eq!(tree.num_nodes, 13);
+        }
+    }
+
+    #[test]
+    fn test_rescan_bounded() {
+        let data = get_sample_data();
+        let sample = ColumnMajorSample::new(&data);
+        let batches = get_batches(&data, &sample, 100);
+        let mut learner = get_learner(0.49);
+        let mut tree = Tree::new(3);
+        assert!(learner.update(&tree, &batches[0]).is_none());
+        assert!(learner.is_rescan_bounded());
+        tree.split(0, 3, 7, 0.1, -0.1);
+        learner.split(&tree, 0, &batches[..1]);
+        // the examples scanned before the split are scanned again after the next split,
+        // which is only allowed after scanning as many new examples
+        assert!(learner.update(&tree, &batches[1]).is_none());
+        assert!(learner.is_rescan_bounded());
+        tree.split(1, 5, 7, 0.1, -0.1);
+        learner.split(&tree, 1, &batches[..2]);
+        assert!(learner.update(&tree, &batches[2]).is_none());
+        assert!(!learner.is_rescan_bounded());
+        assert!(learner.update(&tree, &batches[3]).is_none());
+        assert!(learner.is_rescan_bounded());
+        learner.reset();
+        assert!(learner.is_rescan_bounded());
+    }
+
+    // Grow a tree with `num_splits` splits, and return the number of new examples scanned and
+    // the number of examples scanned again after the splits
+    fn grow_tree(
+        data: &Vec<ExampleInSampleSet>, num_splits: usize, subtraction: bool,
+    ) -> (usize, usize) {
+        let sample = ColumnMajorSample::new(data);
+        let batches = get_batches(data, &sample, 100);
+        let mut learner = get_learner(0.05);
+        let mut tree = Tree::new(num_splits);
+        let mut num_scanned = 0;
+        let mut num_rescanned = 0;
+        let mut k = 0;
+        while tree.num_nodes < 2 * num_splits + 1 {
+            let batch = &batches[k % batches.len()];
+            k += 1;
+            num_scanned += batch.len();
+            if let Some(rule) = learner.update(&tree, batch) {
+                tree.split(rule.prt_index, rule.feature, rule.threshold,
+                           rule.predict.0, rule.predict.1);
+                if subtraction && learner.is_rescan_bounded() {
+                    let rescanned: Vec<Batch> = learner.get_scanned_ranges().iter()
+                        .map(|(begin, end)| Batch::new(&data[*begin..*end], &sample, *begin))
+                        .collect();
+                    num_rescanned += rescanned.iter().map(|batch| batch.len()).sum::<usize>();
+                    learner.split(&tree, rule.prt_index, &rescanned);
+                } else {
+                    learner.reset();
+                }
+            }
+        }
+        (num_scanned, num_rescanned)
+    }
+
+    // Run with `cargo test --release bench_learner_update -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_learner_update() {
+        let num_batches = 2000;
+        let batch_size = 1000;
+        let data = get_sample_data();
+        // a large gamma so that the stopping rule never fires
+        let mut learner = get_learner(0.49);
+        let mut tree = Tree::new(10);
+        tree.split(0, 0, 0, 0.0, 0.0);
+
+        let sample = ColumnMajorSample::new(&data);
+        let start = Instant::now();
+        for i in 0..num_batches {
+            let begin = (i * batch_size) % data.len();
+            let end = (begin + batch_size).min(data.len());
+            let batch = Batch::new(&data[begin..end], &sample, begin);
+            assert!(learner.update(&tree, &batch).is_none());
+        }
+        let duration = start.elapsed();
+        let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9;
+        println!("learner throughput, {} examples, {:.3} seconds, {:.0} examples/second",
+                 learner.total_count, seconds, learner.total_count as f64 / seconds);
+
+        // the examples scanned for growing a tree with 4 splits, and the time for growing
+        // the tree `num_repeats` times
+        let num_repeats = 100;
+        for subtraction in vec![false, true] {
+            let start = Instant::now();
+            let (num_scanned, num_rescanned) = grow_tree(&data, 4, subtraction);
+            for _ in 1..num_repeats {
+                grow_tree(&data, 4, subtraction);
+            }
+            let duration = start.elapsed();
+            let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9;
+            println!("grow a tree, histogram subtraction {}, {} new examples, \
+                      {} examples scanned again, {:.3} seconds for {} trees",
+                     subtraction, num_scanned, num_rescanned, seconds, num_repeats);
+        }
+    }
+}
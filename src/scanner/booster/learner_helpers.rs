@@ -1,152 +1,169 @@
 use rayon::prelude::*;
 
-use Example;
 use TFeature;
-use commons::ExampleInSampleSet;
 use commons::bins::Bins;
 use commons::tree::Tree;
 
-use commons::get_bound;
+use commons::is_zero;
+use commons::stopping_rule::MartingaleStats;
+use commons::stopping_rule::StoppingRule;
+use scanner::buffer_loader::batch::Batch;
 
 use super::learner::NUM_PREDS;
 use super::learner::PREDS;
-use super::learner::RuleStats;
+use super::learner::TreeConstraints;
 use super::learner::TreeNode;
+use super::scoreboard::NodeTotals;
+use super::scoreboard::COUNT;
+use super::scoreboard::LABELED_WEIGHT;
+use super::scoreboard::LABELED_WEIGHT_SQ;
+use super::scoreboard::SLOT_SIZE;
+use super::scoreboard::WEIGHT;
+use super::scoreboard::add_to_histogram;
+
+
+// Find the leaf of each example, and compute the labeled weights `y * w` of the examples.
+// The statistics of the examples do not depend on `gamma`, so that the statistics accumulated
+// by the learner remain valid after `gamma` is changed.
+pub fn preprocess_data(batch: &Batch, tree: &Tree) -> (Vec<usize>, Vec<f32>, Vec<f32>) {
+    let leaves = batch.examples.par_iter()
+                               .map(|(example, _)| tree.get_leaf_index_prediction(example).0)
+                               .collect();
+    let weights = batch.get_weights();
+    let labeled_weights = weights.iter()
+                                 .zip(batch.get_labels().iter())
+                                 .map(|(w, y)| w * y)
+                                 .collect();
+    (leaves, weights, labeled_weights)
+}
 
+/// Sum of the squared increments of the martingale of a weak rule.
+///
+/// The increment of an example is `c = w * (y * h - 2 * gamma)` if it falls into the candidate
+/// node, and `c = -2 * gamma * w` otherwise, so the sum of `c^2` can be computed from
+/// `gamma` and three sums that do not depend on `gamma`:
+/// `node_weight_sq` (sum of `w^2` in the node), `weighted_score` (sum of `w^2 * y * h`),
+/// and `total_weight_sq` (sum of `w^2` of all examples).
+#[inline]
+pub fn get_sum_c_squared(
+    node_weight_sq: f32, weighted_score: f32, rho_gamma: f32, total_weight_sq: f32,
+) -> f32 {
+    node_weight_sq - 4.0 * rho_gamma * weighted_score +
+        4.0 * rho_gamma * rho_gamma * total_weight_sq
+}
 
-pub fn preprocess_data<'a>(
-    data: &'a[ExampleInSampleSet], tree: &Tree, rho_gamma: f32,
-) -> Vec<(usize, f32, (&'a Example, RuleStats))> {
-    data.par_iter().map(|(example, (weight, _, _, _))| {
-        let labeled_weight = weight * (example.label as f32);
-        let null_weight = 2.0 * rho_gamma * weight;
-        let mut vals: RuleStats = [[(0.0, 0.0); 2]; NUM_PREDS];
-        PREDS.iter().enumerate().for_each(|(i, pred)| {
-            let abs_val = (pred.0 * labeled_weight, pred.1 * labeled_weight);
-            let ci      = (abs_val.0 - null_weight, abs_val.1 - null_weight);
-            vals[i][0]  = abs_val;
-            vals[i][1]  = (
-                ci.0 * ci.0 - null_weight * null_weight,
-                ci.1 * ci.1 - null_weight * null_weight
-            );
-        });
-        (tree.get_leaf_index_prediction(example).0, *weight, (example, vals))
-    }).collect()
+/// The scores of the weak rule with the prediction type `pred_idx` on a split.
+/// `left` sums the statistics of the examples that fall into the left child of the split,
+/// and the ones of the right child are derived from the totals of the node.
+/// Returns the sum of `y * h * w` (weak_rules_score) and the sum of `y * h * w^2`
+/// (weighted_score), where `h` is the prediction of the weak rule.
+#[inline]
+pub fn get_rule_scores(left: &[f32], totals: &NodeTotals, pred_idx: usize) -> (f32, f32) {
+    let pred = PREDS[pred_idx];
+    let right_labeled_weight    = totals.labeled_weight - left[LABELED_WEIGHT];
+    let right_labeled_weight_sq = totals.labeled_weight_sq - left[LABELED_WEIGHT_SQ];
+    (pred.0 * left[LABELED_WEIGHT] + pred.1 * right_labeled_weight,
+     pred.0 * left[LABELED_WEIGHT_SQ] + pred.1 * right_labeled_weight_sq)
 }
 
 // if `total_weight` put into account those examples that a node abstained, the comparison
 // is then among all 'specialists'.
+// `rows` are the indices of the examples in the batch that fall into the candidate node,
+// and `column`, `weights`, and `labeled_weights` are the values of all examples in the batch.
+// `totals` sums the statistics of all examples that fall into the candidate node,
+// including the ones in the batch.
+// If `keep_best` is true, the valid rule with the largest edge on this feature is returned,
+// otherwise the valid rule on the last bin is returned.
 pub fn find_tree_node<'a>(
-    data: &'a Vec<(f32, (&Example, RuleStats))>, feature_index: usize,
-    rho_gamma: f32, count: usize, total_weight: f32, total_weight_sq: f32, parent_node: usize,
-    bin: &'a Bins, weak_rules_score: &'a mut Vec<[f32; 2]>, sum_c_squared: &'a mut Vec<[f32; 2]>,
-    debug_info: (((&'a mut Vec<f32>, &'a mut Vec<f32>), &'a mut Vec<f32>), &'a mut Vec<f32>),
+    rows: &[usize], column: &[TFeature], weights: &[f32], labeled_weights: &[f32],
+    feature_index: usize,
+    rho_gamma: f32, count: usize, total_weight: f32, total_weight_sq: f32, max_weight: f32,
+    parent_node: usize, totals: &NodeTotals,
+    bin: &'a Bins, histogram: &'a mut [f32], constraints: &TreeConstraints,
+    stopping_rule: &dyn StoppingRule, keep_best: bool,
 ) -> Option<TreeNode> {
-    let (((num_positive, num_negative), weight_positive), weight_negative) = debug_info;
-
-    // <Split, NodeId, RuleId, stats, LeftOrRight>
-    // the last element of is for the examples that are larger than all split values
-    let mut bin_accum_vals: Vec<RuleStats> =
-        vec![[[(0.0, 0.0); 2]; NUM_PREDS]; bin.len() + 1];
-    // Counts the total weights and the counts for both positive and negative examples
-    let mut counts: [usize; 2] = [0, 0];
-    let mut weights: [f32; 2]  = [0.0, 0.0];
-    data.iter()
-        .for_each(|(w, (example, vals))| {
-            let flip_index = example.feature[feature_index] as usize;
-            let t = &mut bin_accum_vals[flip_index];
-            for j in 0..NUM_PREDS {
-                for k in 0..t[j].len() {
-                    t[j][k].0 += vals[j][k].0;
-                    t[j][k].1 += vals[j][k].1;
-                }
-            }
-            if example.label > 0 {
-                counts[0]  += 1;
-                weights[0] += w;
-            } else {
-                counts[1]  += 1;
-                weights[1] += w;
-            }
-        });
+    // Update the histogram with the new examples - Complexity: O(Examples)
+    add_to_histogram(histogram, rows, column, weights, labeled_weights);
 
-    let mut accum_left  = [[0.0; 2]; NUM_PREDS];
-    let mut accum_right = [[0.0; 2]; NUM_PREDS];
-    // Accumulate sum of the stats of all examples that go to the right child
-    for j in 0..bin.len() { // Split value
-        for pred_idx in 0..NUM_PREDS { // Types of rule
-            for it in 0..accum_right[pred_idx].len() {
-                accum_right[pred_idx][it] +=
-                    bin_accum_vals[j][pred_idx][it].1;
-            }
-        }
+    let base_pred = 0.5 * ((0.5 + rho_gamma) / (0.5 - rho_gamma)).ln();
+    if is_zero(base_pred) {
+        return None;
     }
-    // Now update each splitting values of the bin
+    // each increment of the martingale is within `range_scale` times the example weight
+    let range_scale = 1.0 + 2.0 * rho_gamma;
+    let debug_info = totals.get_debug_info();
+
+    // Check each splitting values of the bin - Complexity: O(Bins)
     let mut valid_weak_rule = None;
-    (0..bin.len()).for_each(|j| {
-        let num_positive = &mut num_positive[j];
-        let num_negative = &mut num_negative[j];
-        let weight_positive = &mut weight_positive[j];
-        let weight_negative = &mut weight_negative[j];
-        *num_positive       += counts[0] as f32;
-        *num_negative       += counts[1] as f32;
-        *weight_positive    += weights[0];
-        *weight_negative    += weights[1];
+    let mut left = [0.0; SLOT_SIZE];
+    histogram.chunks(SLOT_SIZE).take(bin.len()).enumerate().for_each(|(j, slot)| {
+        // Move examples from the right to the left child
+        for k in 0..SLOT_SIZE {
+            left[k] += slot[k];
+        }
+        // Check if both children receive enough examples
+        let is_valid_split = constraints.is_valid_split(
+            (left[COUNT], left[WEIGHT]),
+            (totals.count - left[COUNT], totals.weight - left[WEIGHT]),
+        );
+        if !is_valid_split {
+            return;
+        }
 
         for pred_idx in 0..NUM_PREDS { // Types of rule
-            // Move examples from the right to the left child
-            for it in 0..accum_left[pred_idx].len() {
-                accum_left[pred_idx][it]  +=
-                    bin_accum_vals[j][pred_idx][it].0;
-                accum_right[pred_idx][it] -=
-                    bin_accum_vals[j][pred_idx][it].1;
+            let (weak_rules_score, weighted_score) = get_rule_scores(&left, totals, pred_idx);
+
+            // Check stopping rule
+            let sum_c = weak_rules_score - 2.0 * rho_gamma * total_weight;
+            if sum_c <= 0.0 {
+                continue;
             }
-            let accum: Vec<f32> = accum_left[pred_idx].iter()
-                                                      .zip(accum_right[pred_idx].iter())
-                                                      .map(|(a, b)| *a + *b)
-                                                      .collect();
-            {
-                let rule_idx = pred_idx;
-                let weak_rules_score =
-                    &mut weak_rules_score[j][rule_idx];
-                let sum_c_squared    = &mut sum_c_squared[j][rule_idx];
-                *weak_rules_score   += accum[0];
-                *sum_c_squared      += accum[1];
-
-                // Check stopping rule
-                let sum_c = *weak_rules_score - 2.0 * rho_gamma * total_weight;
-                let sum_c_squared = *sum_c_squared +
-                    4.0 * rho_gamma * rho_gamma * total_weight_sq;
-                let bound = get_bound(sum_c, sum_c_squared);
-                if sum_c > bound {
-                    let base_pred = 0.5 * (
-                        (0.5 + rho_gamma) / (0.5 - rho_gamma)
-                    ).ln();
-                    let real_pred =
-                        (base_pred * PREDS[pred_idx].0, base_pred * PREDS[pred_idx].1);
-                    valid_weak_rule = Some(
-                        TreeNode {
-                            prt_index:      parent_node,
-                            feature:        feature_index,
-                            threshold:      j as TFeature,
-                            predict:        real_pred,
-
-                            gamma:          rho_gamma,
-                            raw_martingale: *weak_rules_score,
-                            sum_c:          sum_c,
-                            sum_c_squared:  sum_c_squared,
-                            bound:          bound,
-                            num_scanned:    count,
-
-                            positive:        *num_positive as usize,
-                            negative:        *num_negative as usize,
-                            positive_weight: *weight_positive,
-                            negative_weight: *weight_negative,
-
-                            fallback:       false,
-                        }
-                    );
+            let sum_c_squared = get_sum_c_squared(
+                totals.weight_sq, weighted_score, rho_gamma, total_weight_sq);
+            let bound = stopping_rule.get_bound(&MartingaleStats {
+                sum_c:             sum_c,
+                sum_c_squared:     sum_c_squared,
+                sum_range_squared: range_scale * range_scale * total_weight_sq,
+                max_range:         range_scale * max_weight,
+                count:             count,
+            });
+            let is_better = !keep_best ||
+                            valid_weak_rule.as_ref()
+                                           .map(|t: &TreeNode| {
+                                               t.raw_martingale < weak_rules_score
+                                           })
+                                           .unwrap_or(true);
+            if sum_c > bound && is_better {
+                let real_pred = get_leaf_predictions(
+                    base_pred, pred_idx, (left[WEIGHT], totals.weight - left[WEIGHT]),
+                    constraints);
+                // the penalties leave nothing to learn from the rule
+                if is_zero(real_pred.0) && is_zero(real_pred.1) {
+                    continue;
                 }
+                valid_weak_rule = Some(
+                    TreeNode {
+                        prt_index:      parent_node,
+                        feature:        feature_index,
+                        threshold:      j as TFeature,
+                        predict:        real_pred,
+
+                        gamma:          rho_gamma,
+                        raw_martingale: weak_rules_score,
+                        sum_c:          sum_c,
+                        sum_c_squared:  sum_c_squared,
+                        bound:          bound,
+                        num_scanned:    count,
+
+                        positive:        debug_info.0,
+                        negative:        debug_info.1,
+                        positive_weight: debug_info.2,
+                        negative_weight: debug_info.3,
+
+                        fallback:       false,
+                        stopping_rule:  stopping_rule.name(),
+                    }
+                );
             }
         }
     });
@@ -154,15 +171,25 @@ pub fn find_tree_node<'a>(
 }
 
 
+// The predictions on the children of a split for the rule type `pred_idx`, after applying
+// the penalties with the sums of the weights of the examples in the children, `weights`
+#[inline]
+fn get_leaf_predictions(
+    base_pred: f32, pred_idx: usize, weights: (f32, f32), constraints: &TreeConstraints,
+) -> (f32, f32) {
+    (constraints.regularize(base_pred * PREDS[pred_idx].0, weights.0),
+     constraints.regularize(base_pred * PREDS[pred_idx].1, weights.1))
+}
+
+
+// `weights` are the sums of the weights of the examples in the left and the right children
 pub fn gen_tree_node(
     expand_node_index: usize, feature_index: usize, bin_index: usize, rule_index: usize, ratio: f32,
+    weights: (f32, f32), constraints: &TreeConstraints,
 ) -> TreeNode {
     let rho_gamma = ratio / 2.0;
-    let base_pred = 0.5 * (
-        (0.5 + rho_gamma) / (0.5 - rho_gamma)
-    ).ln();
-    let real_pred =
-        (base_pred * PREDS[rule_index].0, base_pred * PREDS[rule_index].1);
+    let base_pred = 0.5 * ((0.5 + rho_gamma) / (0.5 - rho_gamma)).ln();
+    let real_pred = get_leaf_predictions(base_pred, rule_index, weights, constraints);
     TreeNode {
         prt_index:      expand_node_index,
         feature:        feature_index,
@@ -171,6 +198,7 @@ pub fn gen_tree_node(
         gamma:          rho_gamma,
 
         fallback:        true,
+        stopping_rule:   "none",
 
         // other attributes are for debugging purpose only
         raw_martingale: 0.0,
@@ -185,3 +213,24 @@ pub fn gen_tree_node(
         negative_weight: 0.0,
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::get_sum_c_squared;
+
+    #[test]
+    fn test_sum_c_squared_after_gamma_change() {
+        // (weight, y * h), `y * h` is zero if the example does not fall into the node
+        let data = vec![(0.5, 1.0), (1.5, -1.0), (1.0, 0.0), (2.0, 1.0), (0.25, -1.0)];
+        let node_weight_sq: f32 = data.iter().filter(|(_, v)| *v != 0.0).map(|(w, _)| w * w).sum();
+        let weighted_score: f32 = data.iter().map(|(w, v)| w * w * v).sum();
+        let total_weight_sq: f32 = data.iter().map(|(w, _)| w * w).sum();
+        for gamma in vec![0.25, 0.1, 0.01] {
+            let expected: f32 =
+                data.iter().map(|(w, v)| (w * (v - 2.0 * gamma)).powi(2)).sum();
+            let actual = get_sum_c_squared(node_weight_sq, weighted_score, gamma, total_weight_sq);
+            assert!((expected - actual).abs() < 1e-5);
+        }
+    }
+}
@@ -1,21 +1,37 @@
+pub mod feature_sampler;
 pub mod learner;
 pub mod learner_helpers;
+pub mod scoreboard;
 
-use rand;
+use bincode::deserialize;
+use bincode::serialize;
 use rand::Rng;
+use serde_json;
 
+use std::fs::rename;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::fmt::Display;
 
 use config::Config;
+use config::GrowthPolicy;
+use config::RootSplit;
 use commons::model::Model;
 use commons::tree::Tree;
 use scanner::buffer_loader::BufferLoader;
 use commons::bins::Bins;
+use commons::io::read_all;
+use commons::io::write_all;
+use commons::get_rng;
+use commons::RNG_ROOT_NODE;
+use commons::model_format::get_bins_hash;
 use commons::packet::TaskPacket;
+use commons::stopping_rule::StoppingRuleParams;
 use commons::performance_monitor::PerformanceMonitor;
+use self::feature_sampler::FeatureSampler;
 use self::learner::Learner;
+use self::learner::TreeConstraints;
 use super::BoosterState;
 
 
@@ -26,6 +42,61 @@ pub enum BoostingResult {
 }
 
 
+/// The state of an interrupted booster, which allows the next booster to continue
+/// scanning for the same tree instead of starting over.
+///
+/// The statistics of the learner do not depend on `gamma`, so the checkpoint remains valid
+/// as long as the model, the sample set, and the configurations of the learner are not changed.
+#[derive(Serialize, Deserialize)]
+pub struct BoosterCheckpoint {
+    model: Model,
+    tree: Tree,
+    learner: Learner,
+    // the version of the sample set scanned by the learner
+    sample_version: usize,
+    // the position in the sample set where the learner stopped scanning
+    cursor: usize,
+    // the fingerprint of the configurations and the bins that the statistics depend on
+    config_hash: u64,
+}
+
+impl BoosterCheckpoint {
+    fn is_valid(&self, model: &Model, sample_version: usize, config_hash: u64) -> bool {
+        if self.model != *model {
+            debug!("booster, checkpoint discarded, the model is changed");
+            false
+        } else if self.sample_version != sample_version {
+            info!("Discard the booster checkpoint, the sample set is changed, {}, {}",
+                  self.sample_version, sample_version);
+            false
+        } else if self.config_hash != config_hash {
+            info!("Discard the booster checkpoint, the configurations or the bins are changed");
+            false
+        } else {
+            true
+        }
+    }
+
+    pub fn write(&self, filename: &String) {
+        let temp_filename = filename.clone() + "_WRITING";
+        write_all(&temp_filename, &serialize(self).unwrap())
+            .expect("Failed to write the learner checkpoint to file");
+        rename(temp_filename, filename).unwrap();
+    }
+
+    pub fn read(filename: &String) -> Option<BoosterCheckpoint> {
+        if !Path::new(filename).exists() {
+            return None;
+        }
+        let ret = deserialize(read_all(filename).as_ref());
+        if ret.is_err() {
+            error!("Cannot parse the learner checkpoint `{}`", filename);
+        }
+        ret.ok()
+    }
+}
+
+
 /// The boosting algorithm. It contains two functions, one for starting
 /// the network communication, the other for starting the training procedure.
 pub struct Boosting {
@@ -33,9 +104,19 @@ pub struct Boosting {
     training_loader: BufferLoader,
     learner: Learner,
     num_splits: usize,
+    root_split: RootSplit,
+    seed: u64,
+    // the version of the sample set when the booster starts
+    sample_version: usize,
+    config_hash: u64,
+    histogram_subtraction: bool,
+    // the version of the sample set when the learner is reset
+    scan_version: usize,
 
     init_packet: TaskPacket,
     curr_model: Model,
+    // the partially grown tree, restored from a checkpoint or kept when the booster is stopped
+    partial_tree: Option<Tree>,
 
     // save_process: bool,
     verbose: bool,
@@ -48,38 +129,85 @@ impl Boosting {
     /// over multiple workers, it might be a subset of the full feature set.
     /// * `max_sample_size`: the number of examples to scan for determining the percentiles for the features.
     /// * `default_gamma`: the initial value of the edge `gamma` of the candidate valid weak rules.
+    /// * `checkpoint`: the state of the previous booster, which is reused if its model is
+    /// the same as the model in `init_packet`, and it scanned the current sample set with
+    /// the same configurations of the learner.
     pub fn new(
         init_packet: TaskPacket,
         booster_state: Arc<RwLock<BoosterState>>,
-        training_loader: BufferLoader,
+        mut training_loader: BufferLoader,
         bins: Vec<Bins>,
         config: Config,
+        checkpoint: Option<BoosterCheckpoint>,
     ) -> Boosting {
         // TODO: make num_cadid a paramter
         let packet = init_packet.clone();
         let (mut model, gamma, expand_node) = (
             packet.model.unwrap(), packet.gamma.unwrap(), packet.expand_node.unwrap(),
         );
-        let mut learner = Learner::new(gamma, bins, config.num_features, config.num_splits);
-
         model.set_base_size();
+        let sample_version = training_loader.current_version;
+        let config_hash = get_config_hash(&config, &bins);
+        let (mut learner, partial_tree) = match checkpoint {
+            Some(checkpoint) if checkpoint.is_valid(&model, sample_version, config_hash) => {
+                info!("Resume the booster from the checkpoint, {}", checkpoint.learner.total_count);
+                // continue scanning the examples that are not scanned by the learner
+                training_loader.set_cursor(checkpoint.cursor);
+                let mut learner = checkpoint.learner;
+                learner.set_gamma(gamma);
+                (learner, Some(checkpoint.tree))
+            },
+            _ => {
+                let learner = Learner::new(
+                    gamma, bins, config.num_features, TreeConstraints::new(&config),
+                    FeatureSampler::new(config.num_features, &config),
+                    GrowthPolicy::new(&config.growth_policy), StoppingRuleParams::new(&config));
+                (learner, None)
+            },
+        };
+
+        let root_split = config.get_root_split();
+
         learner.set_expand_node(expand_node);
         Boosting {
             booster_state: booster_state,
             training_loader: training_loader,
             learner: learner,
             num_splits: config.num_splits,
+            root_split: root_split,
+            seed: config.seed,
+            sample_version: sample_version,
+            config_hash: config_hash,
+            histogram_subtraction: config.histogram_subtraction,
+            scan_version: sample_version,
 
             init_packet: init_packet,
             curr_model: model,
+            partial_tree: partial_tree,
 
             // save_process: config.save_process,
             verbose: false,
         }
     }
 
-    pub fn destroy(self) -> (TaskPacket, Model, BufferLoader) {
-        (self.init_packet, self.curr_model, self.training_loader)
+    /// Returns a checkpoint of the booster as the last element if the training was stopped
+    /// before the tree is fully grown.
+    pub fn destroy(self) -> (TaskPacket, Model, BufferLoader, Option<BoosterCheckpoint>) {
+        let checkpoint = match self.partial_tree {
+            // the statistics are discarded if the sample set was switched during the scanning
+            Some(tree) if self.training_loader.current_version == self.sample_version => {
+                Some(BoosterCheckpoint {
+                    model: self.curr_model.clone(),
+                    tree: tree,
+                    learner: self.learner,
+                    sample_version: self.sample_version,
+                    cursor: self.training_loader.get_cursor(),
+                    config_hash: self.config_hash,
+                })
+            },
+            _ => None,
+        };
+        (self.init_packet, self.curr_model, self.training_loader, checkpoint)
     }
 
     /// Start training the boosting algorithm.
@@ -91,8 +219,20 @@ impl Boosting {
         global_timer.start();
         // let mut last_logging_ts = global_timer.get_duration();
 
-        // split the root of the tree on the median of a randomly-selected feature dimension
-        let tree = self.get_root_node();
+        // sample the features to be scanned for the new tree
+        let tree_index = self.curr_model.size();
+        self.learner.feature_sampler.sample_tree(tree_index);
+
+        // continue with the tree from the checkpoint, or
+        // split the root of the tree according to the root split strategy
+        self.scan_version = self.training_loader.current_version;
+        let tree = match self.partial_tree.take() {
+            Some(tree) => Some(tree),
+            None => {
+                self.learner.reset();
+                self.get_root_node()
+            },
+        };
         if tree.is_none() {
             info!("Training is stopped because ess is too small, {:?}",
                 self.training_loader.ess);
@@ -102,9 +242,8 @@ impl Boosting {
         let mut tree = tree.unwrap();
         let mut is_booster_running = true;
         self.verbose = false;
-        while is_booster_running && !tree.is_full_tree() {
+        while is_booster_running && !tree.is_full_tree() && self.has_expandable_leaf(&tree) {
             let mut new_rule = None;
-            self.learner.reset();
             while is_booster_running && new_rule.is_none() && self.training_loader.is_ess_valid() &&
                     self.learner.total_count < self.training_loader.size {
                 // Logging for the status check
@@ -141,6 +280,12 @@ impl Boosting {
                     self.training_loader.ess);
                 return BoostingResult::LowESS;
             }
+            if new_rule.is_none() && !is_booster_running {
+                // keep the scanned statistics for the next booster
+                self.partial_tree = Some(tree);
+                info!("Training is stopped by the scanner.");
+                return BoostingResult::FailedToTrigger;
+            }
             if new_rule.is_none() {
                 info!("Training is stopped because stopping rule is failed to trigger.");
                 return BoostingResult::FailedToTrigger;
@@ -154,8 +299,10 @@ impl Boosting {
                 rule.predict.0,
                 rule.predict.1,
             );
+            tree.set_split_stats(rule.prt_index, rule.get_split_stats());
             info!("scanner, added new rule, {}, {}, {}, {}, {}", self.curr_model.size(),
                 rule.num_scanned, self.learner.total_count, left_index, right_index);
+            self.update_learner_after_split(&tree, rule.prt_index);
         }
         self.curr_model.append(tree);
         // write_model(&self.curr_model, global_timer.get_duration(), self.save_process);
@@ -163,11 +310,41 @@ impl Boosting {
         BoostingResult::Succeed
     }
 
+    // Reset the learner after `parent` is split, or derive the statistics of the children
+    // from the examples scanned since the last reset if they are still in the sample set,
+    // and scanning them again costs at most a constant times the new examples
+    fn update_learner_after_split(&mut self, tree: &Tree, parent: usize) {
+        if self.histogram_subtraction &&
+                self.training_loader.current_version == self.scan_version &&
+                self.learner.is_rescan_bounded() {
+            let ranges = self.learner.get_scanned_ranges().clone();
+            let batches = self.training_loader.get_batches(&ranges, &self.curr_model);
+            self.learner.split(tree, parent, &batches);
+        } else {
+            self.learner.reset();
+            self.scan_version = self.training_loader.current_version;
+        }
+    }
+
     pub fn get_root_node(&mut self) -> Option<Tree> {
-        let root_index = 0;
-        let mut rng = rand::thread_rng();
-        let selected_feature: usize = rng.gen_range(0, self.learner.num_features);
+        match self.root_split {
+            RootSplit::RandomMedian => {
+                let mut rng = get_rng(self.seed, &[RNG_ROOT_NODE, self.curr_model.size()]);
+                let selected_feature: usize = {
+                    let features = self.learner.feature_sampler.get_tree_features();
+                    features[rng.gen_range(0, features.len())]
+                };
+                self.get_median_root_node(selected_feature)
+            },
+            RootSplit::Forced(selected_feature) => self.get_median_root_node(selected_feature),
+            // the root is left unsplit, and would be found by the learner
+            RootSplit::Learned => Some(Tree::new(self.num_splits)),
+        }
+    }
 
+    // split the root of the tree on the median of `selected_feature` with zero predictions
+    fn get_median_root_node(&mut self, selected_feature: usize) -> Option<Tree> {
+        let root_index = 0;
         let mut feature_vals = Vec::with_capacity(self.training_loader.size);
         let mut count = 0;
         while self.training_loader.is_ess_valid() && count < self.training_loader.size {
@@ -198,6 +375,13 @@ impl Boosting {
         }
     }
 
+    // check if any leaf of the tree satisfies the depth constraint
+    fn has_expandable_leaf(&self, tree: &Tree) -> bool {
+        (0..tree.num_nodes).any(|index| {
+            tree.is_leaf(index) && self.learner.constraints.is_expandable(tree.get_depth(index))
+        })
+    }
+
     #[allow(dead_code)]
     fn print_log(&self) {
         debug!("booster, status, {}",
@@ -215,4 +399,22 @@ impl Boosting {
             debug!("booster, verbose, {}", message);
         }
     }
-}
\ No newline at end of file
+}
+
+
+// The fingerprint of the configurations and the bins that the statistics of the learner
+// depend on
+fn get_config_hash(config: &Config, bins: &Vec<Bins>) -> u64 {
+    let params = serde_json::to_string(&(
+        config.num_features, config.num_splits, config.max_depth, config.min_leaf_count,
+        config.min_leaf_weight, config.l1_reg, config.l2_reg,
+        config.feature_fraction_per_tree, config.feature_fraction_per_node,
+        &config.growth_policy, config.seed, &config.stopping_rule, config.stopping_rule_delta,
+        config.stopping_rule_shrink, config.stopping_rule_threshold_factor,
+        config.histogram_subtraction,
+    )).unwrap();
+    // FNV-1a
+    params.bytes().fold(get_bins_hash(bins), |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,209 @@
+use rayon::prelude::*;
+
+use TFeature;
+use commons::bins::Bins;
+use scanner::buffer_loader::batch::Batch;
+
+
+/*
+ScoreBoard structure:
+All statistics of a candidate node are stored in one contiguous array, which holds a histogram
+for each feature. The histogram of a feature has one slot for each split value of the feature,
+and one more slot for the examples that are larger than all split values. A slot sums the
+following statistics of the examples that fall into the bin,
+
+    COUNT:              the number of examples
+    WEIGHT:             sum of the weights `w`
+    LABELED_WEIGHT:     sum of `y * w`
+    LABELED_WEIGHT_SQ:  sum of `y * w^2`
+
+The histograms are persistent, i.e. they are updated incrementally with each batch of examples.
+The statistics of the left child of a split are the prefix sums of the histogram, and
+the statistics of its sibling, the right child, are the totals of the node minus the ones of
+the left child.
+
+Scoreboards are only allocated for the leaves that are being scanned, and are freed once the
+tree is split. If the statistics are kept after a split (`histogram_subtraction`), the
+scoreboard of the child with fewer examples is rebuilt from the scanned examples, and the one
+of its sibling is the scoreboard of the parent minus the one of the child.
+*/
+pub const COUNT:             usize = 0;
+pub const WEIGHT:            usize = 1;
+pub const LABELED_WEIGHT:    usize = 2;
+pub const LABELED_WEIGHT_SQ: usize = 3;
+pub const SLOT_SIZE:         usize = 4;
+
+
+/// The position of the first slot of each feature in the scoreboards
+pub fn get_offsets(bins: &Vec<Bins>) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(bins.len() + 1);
+    offsets.push(0);
+    bins.iter().for_each(|bin| {
+        let last = *offsets.last().unwrap();
+        offsets.push(last + bin.len() + 1);
+    });
+    offsets
+}
+
+
+/// Totals of the examples that fall into a candidate node
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NodeTotals {
+    pub count:             f32,
+    pub weight:            f32,
+    pub weight_sq:         f32,
+    pub labeled_weight:    f32,
+    pub labeled_weight_sq: f32,
+    // trackers for debugging
+    #[cfg(feature = "debug-trackers")]
+    pub positive_count:    f32,
+    #[cfg(feature = "debug-trackers")]
+    pub positive_weight:   f32,
+}
+
+impl NodeTotals {
+    /// Add an example with the weight `weight` and the label `label`
+    pub fn add(&mut self, weight: f32, label: f32) {
+        self.count             += 1.0;
+        self.weight            += weight;
+        self.weight_sq         += weight * weight;
+        self.labeled_weight    += label * weight;
+        self.labeled_weight_sq += label * weight * weight;
+        #[cfg(feature = "debug-trackers")]
+        {
+            if label > 0.0 {
+                self.positive_count  += 1.0;
+                self.positive_weight += weight;
+            }
+        }
+    }
+
+    /// The (positive count, negative count, positive weight, negative weight) of the examples,
+    /// which are all zeros without the `debug-trackers` feature
+    #[cfg(feature = "debug-trackers")]
+    pub fn get_debug_info(&self) -> (usize, usize, f32, f32) {
+        (self.positive_count as usize, (self.count - self.positive_count) as usize,
+         self.positive_weight, self.weight - self.positive_weight)
+    }
+
+    #[cfg(not(feature = "debug-trackers"))]
+    pub fn get_debug_info(&self) -> (usize, usize, f32, f32) {
+        (0, 0, 0.0, 0.0)
+    }
+}
+
+
+/// Statistics of all candidate weak rules on one candidate node
+#[derive(Serialize, Deserialize)]
+pub struct ScoreBoard {
+    pub totals: NodeTotals,
+    histograms: Vec<f32>,
+}
+
+impl ScoreBoard {
+    /// `num_slots` is the total number of slots over all features
+    pub fn new(num_slots: usize) -> ScoreBoard {
+        ScoreBoard {
+            totals:     NodeTotals::default(),
+            histograms: vec![0.0; num_slots * SLOT_SIZE],
+        }
+    }
+
+    /// Get the histogram of the feature `feature`
+    pub fn get_histogram(&self, offsets: &Vec<usize>, feature: usize) -> &[f32] {
+        &self.histograms[offsets[feature] * SLOT_SIZE..offsets[feature + 1] * SLOT_SIZE]
+    }
+
+    /// Add the examples `rows` of the batch to the totals, and to the histograms of
+    /// the features `i` where `features[i]` is true
+    pub fn add_examples(
+        &mut self, offsets: &Vec<usize>, rows: &[usize], batch: &Batch, weights: &[f32],
+        labeled_weights: &[f32], features: &[bool],
+    ) {
+        let labels = batch.get_labels();
+        rows.iter().for_each(|k| self.totals.add(weights[*k], labels[*k]));
+        self.split_by_features(offsets).into_par_iter().enumerate()
+            .filter(|(i, _)| features[*i])
+            .for_each(|(i, histogram)| {
+                add_to_histogram(histogram, rows, batch.get_column(i), weights, labeled_weights);
+            });
+    }
+
+    /// Set the histograms of the features `i` where `features[i]` is true to the histograms
+    /// of `parent` minus the ones of `sibling`
+    pub fn set_difference(
+        &mut self, offsets: &Vec<usize>, parent: &ScoreBoard, sibling: &ScoreBoard,
+        features: &[bool],
+    ) {
+        for i in 0..(offsets.len() - 1) {
+            if !features[i] {
+                continue;
+            }
+            let range = offsets[i] * SLOT_SIZE..offsets[i + 1] * SLOT_SIZE;
+            self.histograms[range.clone()].iter_mut()
+                .zip(parent.histograms[range.clone()].iter())
+                .zip(sibling.histograms[range].iter())
+                .for_each(|((val, parent_val), sibling_val)| *val = parent_val - sibling_val);
+        }
+    }
+
+    /// Split the array into the histograms of each feature, so that the features can be
+    /// updated in parallel
+    pub fn split_by_features(&mut self, offsets: &Vec<usize>) -> Vec<&mut [f32]> {
+        let mut ret = Vec::with_capacity(offsets.len() - 1);
+        let mut remaining: &mut [f32] = &mut self.histograms;
+        for i in 1..offsets.len() {
+            let curr = remaining;
+            let (head, tail) = curr.split_at_mut((offsets[i] - offsets[i - 1]) * SLOT_SIZE);
+            ret.push(head);
+            remaining = tail;
+        }
+        ret
+    }
+}
+
+
+/// Add the examples `rows` of a batch to the histogram of one feature, where `column` has
+/// the values of the feature, and `weights` and `labeled_weights` are the values of all
+/// examples in the batch
+pub fn add_to_histogram(
+    histogram: &mut [f32], rows: &[usize], column: &[TFeature], weights: &[f32], labeled_weights: &[f32],
+) {
+    let mut add = |value: TFeature, w: f32, labeled_weight: f32| {
+        let slot = value as usize * SLOT_SIZE;
+        histogram[slot + COUNT]             += 1.0;
+        histogram[slot + WEIGHT]            += w;
+        histogram[slot + LABELED_WEIGHT]    += labeled_weight;
+        histogram[slot + LABELED_WEIGHT_SQ] += labeled_weight * w;
+    };
+    if rows.len() == column.len() {
+        // all examples fall into the node, so the columns are read sequentially
+        column.iter().zip(weights.iter()).zip(labeled_weights.iter())
+              .for_each(|((value, w), labeled_weight)| add(*value, *w, *labeled_weight));
+    } else {
+        rows.iter().for_each(|k| add(column[*k], weights[*k], labeled_weights[*k]));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::ScoreBoard;
+    use super::COUNT;
+    use super::SLOT_SIZE;
+
+    #[test]
+    fn test_split_by_features() {
+        let offsets = vec![0, 3, 3, 7];
+        let mut scoreboard = ScoreBoard::new(7);
+        {
+            let features = scoreboard.split_by_features(&offsets);
+            assert_eq!(features.len(), 3);
+            assert_eq!(features[0].len(), 3 * SLOT_SIZE);
+            assert_eq!(features[1].len(), 0);
+            assert_eq!(features[2].len(), 4 * SLOT_SIZE);
+        }
+        scoreboard.split_by_features(&offsets)[2][SLOT_SIZE + COUNT] = 1.0;
+        assert_eq!(scoreboard.get_histogram(&offsets, 2)[SLOT_SIZE + COUNT], 1.0);
+    }
+}
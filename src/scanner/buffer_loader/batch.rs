@@ -0,0 +1,101 @@
+use rayon::prelude::*;
+
+use TFeature;
+use commons::ExampleInSampleSet;
+
+
+/// The features and the labels of a sample set in the column-major layout, i.e. the values
+/// of each feature over all examples are stored in one contiguous column. It is created once
+/// a new sample set is loaded, and allows the learner to scan the examples feature by feature
+/// without jumping between the feature vectors of different examples.
+pub struct ColumnMajorSample {
+    columns: Vec<Vec<TFeature>>,
+    labels:  Vec<f32>,
+}
+
+impl ColumnMajorSample {
+    pub fn new(examples: &[ExampleInSampleSet]) -> ColumnMajorSample {
+        let num_features =
+            examples.first().map(|(example, _)| example.feature.len()).unwrap_or(0);
+        let columns = (0..num_features).into_par_iter().map(|i| {
+            examples.iter().map(|(example, _)| example.feature[i]).collect()
+        }).collect();
+        let labels = examples.iter().map(|(example, _)| example.label as f32).collect();
+        ColumnMajorSample {
+            columns: columns,
+            labels:  labels,
+        }
+    }
+}
+
+
+/// A batch of examples in both the row-major layout (for evaluating the model and the tree)
+/// and the column-major layout (for scanning the features)
+pub struct Batch<'a> {
+    pub examples: &'a [ExampleInSampleSet],
+    sample: &'a ColumnMajorSample,
+    start: usize,
+}
+
+impl<'a> Batch<'a> {
+    /// `examples` are the examples of the sample set `sample` starting from the index `start`
+    pub fn new(
+        examples: &'a [ExampleInSampleSet], sample: &'a ColumnMajorSample, start: usize,
+    ) -> Batch<'a> {
+        assert!(start + examples.len() <= sample.labels.len());
+        Batch {
+            examples: examples,
+            sample: sample,
+            start: start,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.examples.len()
+    }
+
+    /// The range of the examples of the batch in the sample set
+    pub fn get_range(&self) -> (usize, usize) {
+        (self.start, self.start + self.examples.len())
+    }
+
+    /// The values of the feature `feature` of the examples in the batch
+    pub fn get_column(&self, feature: usize) -> &'a [TFeature] {
+        &self.sample.columns[feature][self.start..self.start + self.examples.len()]
+    }
+
+    /// The labels (+1 or -1) of the examples in the batch
+    pub fn get_labels(&self) -> &'a [f32] {
+        &self.sample.labels[self.start..self.start + self.examples.len()]
+    }
+
+    /// The current weights of the examples in the batch
+    pub fn get_weights(&self) -> Vec<f32> {
+        self.examples.iter().map(|(_, (weight, _, _, _))| *weight).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use commons::ExampleInSampleSet;
+    use commons::labeled_data::LabeledData;
+    use super::Batch;
+    use super::ColumnMajorSample;
+
+    #[test]
+    fn test_column_major_batch() {
+        let examples: Vec<ExampleInSampleSet> = (0..10).map(|i| {
+            let label = if i % 2 == 0 { 1 } else { -1 };
+            (LabeledData::new(vec![i as u8, (i * 2) as u8, 7], label), (i as f32, 0.0, 0, 0))
+        }).collect();
+        let sample = ColumnMajorSample::new(&examples);
+        let batch = Batch::new(&examples[3..6], &sample, 3);
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.get_column(0), &[3, 4, 5]);
+        assert_eq!(batch.get_column(1), &[6, 8, 10]);
+        assert_eq!(batch.get_column(2), &[7, 7, 7]);
+        assert_eq!(batch.get_labels(), &[-1.0, 1.0, -1.0]);
+        assert_eq!(batch.get_weights(), vec![3.0, 4.0, 5.0]);
+    }
+}
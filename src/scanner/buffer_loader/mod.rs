@@ -1,3 +1,5 @@
+/// The column-major layout of the sample set
+pub mod batch;
 mod loader;
 
 use rayon::prelude::*;
@@ -16,6 +18,8 @@ use commons::performance_monitor::PerformanceMonitor;
 use commons::persistent_io::LockedBuffer;
 use commons::ExampleInSampleSet;
 use commons::model::Model;
+use self::batch::Batch;
+use self::batch::ColumnMajorSample;
 use self::loader::Loader;
 
 
@@ -34,6 +38,9 @@ pub struct BufferLoader {
     num_batch: usize,
 
     examples: Vec<ExampleInSampleSet>,
+    // the same examples in the column-major layout, which doubles the memory used by
+    // the features of the sample set
+    columns: ColumnMajorSample,
     pub current_version: usize,
     pub new_buffer: LockedBuffer,
     loader: Loader,
@@ -75,6 +82,7 @@ impl BufferLoader {
             num_batch: num_batch,
 
             examples: vec![],
+            columns: ColumnMajorSample::new(&[]),
             current_version: 0,
             new_buffer: new_buffer,
             loader: loader,
@@ -102,21 +110,37 @@ impl BufferLoader {
     /// was ready. If it was, the loader will switched to the alternate buffer for
     /// reading the next batch of examples.
     pub fn get_next_batch(&mut self, allow_switch: bool) -> (&[ExampleInSampleSet], bool) {
-        let (examples, switched) = self.get_next_mut_batch(allow_switch);
-        (&*examples, switched)
+        let ((head, tail), switched) = self.get_next_batch_range(allow_switch);
+        (&self.examples[head..tail], switched)
     }
 
+    /// Read next batch of examples, and update their scores using `model`.
+    /// The batch also provides the examples in the column-major layout.
     pub fn get_next_batch_and_update(
         &mut self,
         allow_switch: bool,
         model: &Model,
-    ) -> (&[ExampleInSampleSet], bool) {
-        let (batch, switched) = self.get_next_mut_batch(allow_switch);
-        update_scores(batch, model);
-        (batch, switched)
+    ) -> (Batch<'_>, bool) {
+        let ((head, tail), switched) = self.get_next_batch_range(allow_switch);
+        update_scores(&mut self.examples[head..tail], model);
+        (Batch::new(&self.examples[head..tail], &self.columns, head), switched)
     }
 
-    fn get_next_mut_batch(&mut self, allow_switch: bool) -> (&mut [ExampleInSampleSet], bool) {
+    /// The batches of the examples in the ranges `ranges` of the current sample set, e.g.
+    /// the examples that are scanned again after a split. Their scores are updated using `model`.
+    pub fn get_batches(
+        &mut self, ranges: &[(usize, usize)], model: &Model,
+    ) -> Vec<Batch<'_>> {
+        ranges.iter().for_each(|(head, tail)| {
+            update_scores(&mut self.examples[*head..*tail], model);
+        });
+        let (examples, columns) = (&self.examples, &self.columns);
+        ranges.iter().map(|(head, tail)| {
+            Batch::new(&examples[*head..*tail], columns, *head)
+        }).collect()
+    }
+
+    fn get_next_batch_range(&mut self, allow_switch: bool) -> ((usize, usize), bool) {
         let mut switched = false;
         while self.examples.is_empty() {
             switched = self.try_switch();
@@ -134,7 +158,7 @@ impl BufferLoader {
 
         assert!(!self.examples.is_empty());
         let tail = min(self.curr_example + self.batch_size, self.size);
-        (&mut self.examples[self.curr_example..tail], switched)
+        ((self.curr_example, tail), switched)
     }
 
     fn try_switch(&mut self) -> bool {
@@ -156,6 +180,7 @@ impl BufferLoader {
         let old_version = self.current_version;
         self.current_version = new_version;
         self.examples = set_init_weight(new_examples);
+        self.columns = ColumnMajorSample::new(&self.examples);
         self.curr_example = 0;
 
         self.sampling_pm.pause();
@@ -182,6 +207,16 @@ impl BufferLoader {
         }
     }
 
+    /// The position of the last batch read from the current sample set
+    pub fn get_cursor(&self) -> usize {
+        self.curr_example
+    }
+
+    /// Continue reading the current sample set after the position returned by `get_cursor`
+    pub fn set_cursor(&mut self, cursor: usize) {
+        self.curr_example = min(cursor, self.size);
+    }
+
     // ESS and others
     pub fn is_ess_valid(&self) -> bool {
         self.ess.is_none() || self.ess.as_ref().unwrap() >= &self.min_ess
@@ -223,13 +258,7 @@ impl BufferLoader {
 
 /// Update the scores of the examples using `model`
 fn update_scores(data: &mut [ExampleInSampleSet], model: &Model) {
-    data.par_iter_mut().for_each(|example| {
-        let (_curr_weight, curr_score, curr_size, base_size) = example.1;
-        let (new_score, (new_version, _)) = model.get_prediction(&example.0, curr_size);
-        let updated_score = new_score + curr_score;
-        (*example).1 = (
-            get_weight(&example.0, updated_score), updated_score, new_version, base_size);
-    });
+    model.update_scores(data);
 }
 
 
@@ -14,6 +14,7 @@ use config::Config;
 use config::SampleMode;
 
 use self::booster::Boosting;
+use self::booster::BoosterCheckpoint;
 use self::booster::BoostingResult;
 use self::buffer_loader::BufferLoader;
 
@@ -55,6 +56,12 @@ pub fn start_scanner(
     let booster_state = Arc::new(RwLock::new(BoosterState::IDLE));
     let sampler_signal_sender = Mutex::new(sampler_signal_sender);
     let buffer_loader = Arc::new(Mutex::new(Some(buffer_loader)));
+    // the statistics of the last interrupted booster
+    let checkpoint = {
+        let checkpoint = config.learner_checkpoint_filename.as_ref()
+                               .and_then(|filename| BoosterCheckpoint::read(filename));
+        Arc::new(Mutex::new(checkpoint))
+    };
     let mut curr_packet: TaskPacket = TaskPacket::new();
 
     let network = Network::new(config.port, &vec![],
@@ -68,12 +75,14 @@ pub fn start_scanner(
                 sampler_signal_sender.send(new_version.clone()).unwrap();
                 drop(sampler_signal_sender);
                 curr_packet.new_sample_version = Some(new_version);
-                let (packet, booster_state, buffer_loader, new_updates_sender, bins, config) =
+                let (packet, booster_state, buffer_loader, new_updates_sender, bins, config,
+                     checkpoint) =
                     (curr_packet.clone(), booster_state.clone(),
                      buffer_loader.clone(), new_updates_sender.clone(), bins.clone(),
-                     config.clone());
+                     config.clone(), checkpoint.clone());
                 spawn(move || start_booster(
-                    packet, booster_state, buffer_loader, new_updates_sender, bins, config));
+                    packet, booster_state, buffer_loader, new_updates_sender, bins, config,
+                    checkpoint));
             } else if packet.model.is_none() {
                 info!("Packet is asking scanner to quit.");
                 let new_updates_sender = new_updates_sender.lock().unwrap();
@@ -83,12 +92,14 @@ pub fn start_scanner(
                 drop(new_updates_sender);
             } else if !curr_packet.equals(&packet) || packet.dest.is_some() {
                 curr_packet = packet.clone_with_expand(&curr_packet);
-                let (packet, booster_state, buffer_loader, new_updates_sender, bins, config) =
+                let (packet, booster_state, buffer_loader, new_updates_sender, bins, config,
+                     checkpoint) =
                     (curr_packet.clone(), booster_state.clone(),
                      buffer_loader.clone(), new_updates_sender.clone(), bins.clone(),
-                     config.clone());
+                     config.clone(), checkpoint.clone());
                 spawn(move || start_booster(
-                    packet, booster_state, buffer_loader, new_updates_sender, bins, config));
+                    packet, booster_state, buffer_loader, new_updates_sender, bins, config,
+                    checkpoint));
             } else {
                 info!("Package is ignored, {}", packet.packet_id);
             }
@@ -106,6 +117,7 @@ fn start_booster(
     new_updates_sender: Arc<Mutex<mpsc::Sender<UpdatePacket>>>,
     bins: Vec<Bins>,
     config: Config,
+    checkpoint: Arc<Mutex<Option<BoosterCheckpoint>>>,
 ) {
     if packet.new_sample_version.is_none() {
         debug!("booster, sample version is none, booster is not starting");
@@ -137,17 +149,25 @@ fn start_booster(
     *w_booster_state = BoosterState::RUNNING;
     drop(w_booster_state);
     let ro_booster_state = booster_state.clone();
+    let checkpoint_filename = config.learner_checkpoint_filename.clone();
+    let mut checkpoint = checkpoint.lock().unwrap();
     let mut booster = Boosting::new(
         packet,
         ro_booster_state,
         training_loader,
         bins,
         config,
+        checkpoint.take(),
     );
     info!("Booster ready to train");
     let booster_result = booster.training();
 
-    let (prev_packet, model, mut loader) = booster.destroy();
+    let (prev_packet, model, mut loader, new_checkpoint) = booster.destroy();
+    if new_checkpoint.is_some() && checkpoint_filename.is_some() {
+        new_checkpoint.as_ref().unwrap().write(checkpoint_filename.as_ref().unwrap());
+    }
+    *checkpoint = new_checkpoint;
+    drop(checkpoint);
     match booster_result {
         BoostingResult::Succeed => {
             // send out updates
@@ -0,0 +1,596 @@
+use serde_json;
+use threadpool::ThreadPool;
+
+use std::fs::metadata;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::panic::AssertUnwindSafe;
+use std::panic::catch_unwind;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread::sleep;
+use std::thread::spawn;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use Example;
+use TFeature;
+use commons::bins::Bins;
+use commons::inference::FlatModel;
+use commons::io::try_parse_libsvm_one_line;
+use commons::labeled_data::LabeledData;
+use commons::model::Model;
+use commons::persistent_io::read_bins_disk;
+use commons::persistent_io::try_read_model_with_header;
+use testing::get_checkpoints;
+
+const NUM_WORKERS: usize = 4;
+// The interval for checking if there is a newer model to load
+const RELOAD_INTERVAL_SECS: u64 = 1;
+const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+// The limits on the request line and the headers
+const MAX_LINE_SIZE: usize = 8 * 1024;
+const MAX_NUM_HEADERS: usize = 100;
+// The timeout of each read or write on a connection, so that the idle clients or the clients
+// that send less data than the content length do not hold the workers
+const CONNECTION_TIMEOUT_SECS: u64 = 10;
+
+
+/*
+The server only listens on localhost, and answers the following requests,
+
+    GET /model      the path, the timestamp, and the number of trees of the loaded model
+    POST /predict   the scores of the examples in the request body, which is either a JSON
+                    object `{"instances": [[x_0, x_1, ...], ...]}` with the raw feature values
+                    of each example, or the examples in the libsvm format (one example per line,
+                    the labels are optional and ignored) if the content type is `text/plain`
+
+The leaf indices and the per-feature contributions of the examples are also returned if
+`leaves` and `contributions` are set to true, either in the query string
+(e.g. `/predict?leaves=true`) or in the JSON object. All examples of a request are scored
+in a batch by the flat inference engine.
+
+If the model path is a directory, the model with the most trees in the directory
+(i.e. `model_<size>-v<size>.json` or `.bin` written by the model manager) is served, and
+the server switches to the newer model when it appears. If the model path is a file, it is
+reloaded when the file is modified. A model that cannot be parsed (e.g. it is being written)
+is skipped until the next check. If the newer model cannot be loaded, the current model is
+still served, and the error is returned by `GET /model` until a model is loaded.
+*/
+struct LoadedModel {
+    path:       String,
+    timestamp:  f32,
+    modified:   Option<SystemTime>,
+    model:      Model,
+    flat_model: FlatModel,
+}
+
+// The model being served, and the error of the last reload if the model is stale
+#[derive(Clone)]
+struct ServedModel {
+    model:        Arc<LoadedModel>,
+    reload_error: Option<String>,
+}
+
+type SharedModel = Arc<RwLock<ServedModel>>;
+
+// The reason why a model cannot be loaded, and if it should be checked again before it is
+// modified, e.g. if it is being written
+struct LoadError {
+    message: String,
+    retry:   bool,
+}
+
+
+#[derive(Deserialize, Default)]
+struct PredictRequest {
+    #[serde(default)]
+    instances:     Vec<Vec<f32>>,
+    #[serde(default)]
+    leaves:        bool,
+    #[serde(default)]
+    contributions: bool,
+}
+
+#[derive(Serialize)]
+struct PredictResponse<'a> {
+    model:         &'a String,
+    num_trees:     usize,
+    scores:        Vec<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    probabilities: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    leaves:        Option<Vec<Vec<usize>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contributions: Option<Vec<Vec<f32>>>,
+}
+
+#[derive(Serialize)]
+struct ModelResponse<'a> {
+    model:        &'a String,
+    timestamp:    f32,
+    num_trees:    usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reload_error: Option<&'a String>,
+}
+
+
+struct HttpRequest {
+    method:       String,
+    path:         String,
+    query:        String,
+    content_type: String,
+    body:         Vec<u8>,
+}
+
+
+/// Serve the predictions of the model at `model_path` (a model file, or a directory of
+/// the checkpoints) over HTTP on `localhost:<port>`. The bins are read from `models/bins.json`.
+pub fn serve(model_path: &String, port: u16) {
+    let bins = Arc::new(read_bins_disk());
+    let (path, modified) = get_model_file(model_path)
+        .expect(&format!("Cannot find a model in `{}`", model_path));
+    let model = load_model(&path, modified, &bins).unwrap_or_else(|err| {
+        panic!("Cannot load the model `{}`, {}", path, err.message)
+    });
+    info!("Loaded the model `{}` with {} trees", model.path, model.model.size());
+    let shared_model: SharedModel = Arc::new(RwLock::new(ServedModel {
+        model:        Arc::new(model),
+        reload_error: None,
+    }));
+
+    // reload the model when there is a newer one
+    {
+        let model_path = model_path.clone();
+        let shared_model = shared_model.clone();
+        let bins = bins.clone();
+        spawn(move || {
+            let mut last_checked = {
+                let current = get_served_model(&shared_model).model;
+                (current.path.clone(), current.modified)
+            };
+            loop {
+                sleep(Duration::from_secs(RELOAD_INTERVAL_SECS));
+                let model_file = get_model_file(&model_path);
+                if model_file.is_none() || model_file.as_ref() == Some(&last_checked) {
+                    continue;
+                }
+                let (path, modified) = model_file.unwrap();
+                // a bug in loading the model must not stop the reloading
+                let result = catch_unwind(AssertUnwindSafe(|| load_model(&path, modified, &bins)))
+                    .unwrap_or_else(|_| Err(LoadError {
+                        message: "Loading the model panicked".to_string(),
+                        retry:   false,
+                    }));
+                let mut served = shared_model.write().unwrap_or_else(|err| err.into_inner());
+                match result {
+                    Ok(model) => {
+                        info!("Reloaded the model `{}` with {} trees", model.path, model.model.size());
+                        served.model = Arc::new(model);
+                        served.reload_error = None;
+                        last_checked = (path, modified);
+                    },
+                    Err(err) => {
+                        let message = format!("Cannot load the model `{}`, {}", path, err.message);
+                        if err.retry {
+                            debug!("{}", message);
+                        } else {
+                            error!("{}. Still serving `{}`.", message, served.model.path);
+                            last_checked = (path, modified);
+                        }
+                        served.reload_error = Some(message);
+                    },
+                }
+            }
+        });
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .expect(&format!("Cannot listen on port {}", port));
+    info!("Serving the predictions on http://127.0.0.1:{}", port);
+    let pool = ThreadPool::new(NUM_WORKERS);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let shared_model = shared_model.clone();
+                let bins = bins.clone();
+                pool.execute(move || {
+                    handle_connection(stream, &get_served_model(&shared_model), &bins);
+                });
+            },
+            Err(err) => error!("Failed to accept a connection, {}", err),
+        }
+    }
+}
+
+
+// The lock is only held while the model is replaced or cloned, so the model is still valid if
+// the lock is poisoned
+fn get_served_model(shared_model: &SharedModel) -> ServedModel {
+    shared_model.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+
+// Return the path and the modification time of the model to serve
+fn get_model_file(model_path: &String) -> Option<(String, Option<SystemTime>)> {
+    let path = {
+        if Path::new(model_path).is_dir() {
+            get_checkpoints(model_path)?.pop()?
+        } else {
+            model_path.clone()
+        }
+    };
+    let modified = metadata(&path).and_then(|t| t.modified()).ok();
+    Some((path, modified))
+}
+
+
+// Load the model at `path`. The model is checked again if it cannot be parsed, since it may
+// be being written, but not if it is trained on different bins or if its trees are invalid.
+fn load_model(
+    path: &String, modified: Option<SystemTime>, bins: &Vec<Bins>,
+) -> Result<LoadedModel, LoadError> {
+    let (timestamp, _, model, header) = try_read_model_with_header(path).ok_or(LoadError {
+        message: "the model cannot be read or parsed".to_string(),
+        retry:   true,
+    })?;
+    if let Some(header) = header {
+        if !header.check(bins) {
+            return Err(LoadError {
+                message: "the model is trained on different bins".to_string(),
+                retry:   false,
+            });
+        }
+    }
+    let flat_model = FlatModel::new(&model, 0).map_err(|err| LoadError {
+        message: err,
+        retry:   false,
+    })?;
+    Ok(LoadedModel {
+        flat_model: flat_model,
+        path:       path.clone(),
+        timestamp:  timestamp,
+        modified:   modified,
+        model:      model,
+    })
+}
+
+
+fn handle_connection(stream: TcpStream, model: &ServedModel, bins: &Vec<Bins>) {
+    let timeout = Some(Duration::from_secs(CONNECTION_TIMEOUT_SECS));
+    if let Err(err) = stream.set_read_timeout(timeout).and(stream.set_write_timeout(timeout)) {
+        error!("Failed to set the timeout of the connection, {}", err);
+        return;
+    }
+    let mut reader = BufReader::new(stream);
+    let (status, body) = match read_request(&mut reader) {
+        Ok(request) => handle_request(&request, model, bins),
+        Err(message) => (400, get_error(&message)),
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status, get_reason(status), body.len(), body);
+    if let Err(err) = reader.get_mut().write_all(response.as_bytes()) {
+        error!("Failed to send the response, {}", err);
+    }
+}
+
+
+fn read_request<R: BufRead>(reader: &mut R) -> Result<HttpRequest, String> {
+    let line = read_line(reader)?;
+    let (method, target) = {
+        let mut t = line.split_whitespace();
+        match (t.next(), t.next()) {
+            (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+            _                            => return Err("Malformed request line".to_string()),
+        }
+    };
+    let mut content_length = 0;
+    let mut content_type = String::new();
+    let mut num_headers = 0;
+    loop {
+        let line = read_line(reader)?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        num_headers += 1;
+        if num_headers > MAX_NUM_HEADERS {
+            return Err("Too many headers".to_string());
+        }
+        let mut kv = line.splitn(2, ':');
+        let key = kv.next().unwrap().trim().to_lowercase();
+        let val = kv.next().unwrap_or("").trim();
+        if key == "content-length" {
+            content_length = val.parse().map_err(|_| "Malformed content length".to_string())?;
+        } else if key == "content-type" {
+            content_type = val.to_lowercase();
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err("The request body is too large".to_string());
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(|err| err.to_string())?;
+    let (path, query) = {
+        let mut t = target.splitn(2, '?');
+        (t.next().unwrap().to_string(), t.next().unwrap_or("").to_string())
+    };
+    Ok(HttpRequest {
+        method:       method,
+        path:         path,
+        query:        query,
+        content_type: content_type,
+        body:         body,
+    })
+}
+
+
+// Read a line of at most `MAX_LINE_SIZE` bytes
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE_SIZE as u64 + 1).read_line(&mut line)
+          .map_err(|err| err.to_string())?;
+    if line.len() > MAX_LINE_SIZE {
+        return Err("The request line or a header is too long".to_string());
+    }
+    Ok(line)
+}
+
+
+// Return the status code and the body of the response
+fn handle_request(request: &HttpRequest, served: &ServedModel, bins: &Vec<Bins>) -> (u16, String) {
+    let model = &served.model;
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/model") => {
+            let response = ModelResponse {
+                model:        &model.path,
+                timestamp:    model.timestamp,
+                num_trees:    model.model.size(),
+                reload_error: served.reload_error.as_ref(),
+            };
+            (200, serde_json::to_string(&response).unwrap())
+        },
+        ("POST", "/predict") => {
+            match parse_predict_request(request, bins) {
+                Ok((examples, leaves, contributions)) => {
+                    let response = predict(&examples, model, leaves, contributions);
+                    (200, serde_json::to_string(&response).unwrap())
+                },
+                Err(message) => (400, message),
+            }
+        },
+        _ => (404, get_error("Not found")),
+    }
+}
+
+
+// Return the binned examples, and if the leaf indices and the contributions are requested
+fn parse_predict_request(
+    request: &HttpRequest, bins: &Vec<Bins>,
+) -> Result<(Vec<Example>, bool, bool), String> {
+    let is_set = |key: &str| {
+        request.query.split('&').any(|t| t == format!("{}=true", key) || t == format!("{}=1", key))
+    };
+    let body = String::from_utf8(request.body.clone())
+        .map_err(|_| get_error("The request body is not valid UTF-8"))?;
+    let (instances, leaves, contributions) = {
+        if request.content_type.starts_with("text/plain") {
+            (parse_libsvm(&body, bins.len())?, false, false)
+        } else {
+            let request: PredictRequest = serde_json::from_str(&body)
+                .map_err(|err| get_error(&format!("Cannot parse the request, {}", err)))?;
+            (request.instances, request.leaves, request.contributions)
+        }
+    };
+    let examples = instances.iter().enumerate().map(|(index, features)| {
+        if features.len() != bins.len() {
+            return Err(get_error(&format!("The example {} has {} features, expected {}",
+                                          index, features.len(), bins.len())));
+        }
+        let features: Vec<TFeature> = features.iter().zip(bins.iter())
+                                              .map(|(val, bin)| bin.get_split_index(*val))
+                                              .collect();
+        Ok(LabeledData::new(features, 0))
+    }).collect::<Result<Vec<Example>, String>>()?;
+    Ok((examples, leaves || is_set("leaves"), contributions || is_set("contributions")))
+}
+
+
+// The missing features are set to 0, and the examples are parsed in the same way as
+// the training data
+fn parse_libsvm(body: &String, num_features: usize) -> Result<Vec<Vec<f32>>, String> {
+    let mut instances = vec![];
+    for line in body.lines() {
+        let example = try_parse_libsvm_one_line::<f32, f32>(line, 0.0, num_features, &String::new())
+            .map_err(|err| get_error(&err))?;
+        if let Some(example) = example {
+            instances.push(example.feature);
+        }
+    }
+    Ok(instances)
+}
+
+
+fn predict<'a>(
+    examples: &Vec<Example>, model: &'a LoadedModel, leaves: bool, contributions: bool,
+) -> PredictResponse<'a> {
+    let scores = model.flat_model.predict(examples);
+    let probabilities = model.model.calibrator.as_ref().map(|calibrator| {
+        scores.iter().map(|score| calibrator.get_probability(*score)).collect()
+    });
+    let leaves = {
+        if leaves {
+            Some(examples.iter().map(|example| {
+                model.model.get_trees().iter()
+                           .map(|tree| tree.get_leaf_index_prediction(example).0)
+                           .collect()
+            }).collect())
+        } else {
+            None
+        }
+    };
+    let contributions = {
+        if contributions {
+            Some(examples.iter().map(|example| model.model.explain(example)).collect())
+        } else {
+            None
+        }
+    };
+    PredictResponse {
+        model:         &model.path,
+        num_trees:     model.model.size(),
+        scores:        scores,
+        probabilities: probabilities,
+        leaves:        leaves,
+        contributions: contributions,
+    }
+}
+
+
+fn get_error(message: &str) -> String {
+    format!("{{\"error\": {}}}", serde_json::to_string(message).unwrap())
+}
+
+
+fn get_reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _   => "Internal Server Error",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use serde_json::Value;
+
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use commons::bins::Bins;
+    use commons::inference::FlatModel;
+    use commons::model::Model;
+    use commons::tree::Tree;
+    use super::LoadedModel;
+    use super::ServedModel;
+    use super::MAX_LINE_SIZE;
+    use super::MAX_NUM_HEADERS;
+    use super::handle_request;
+    use super::read_request;
+
+    fn get_bins() -> Vec<Bins> {
+        let bins = "{\"size\": 4, \"vals\": [0.0, 1.0, 2.0, 3.0]}";
+        (0..3).map(|_| serde_json::from_str(bins).unwrap()).collect()
+    }
+
+    fn get_model() -> ServedModel {
+        let mut model = Model::new();
+        let mut tree = Tree::new(2);
+        let (left, _) = tree.split(0, 1, 1, -0.5, 0.5);
+        tree.split(left, 2, 0, 0.75, 0.25);
+        model.append(tree);
+        let model = LoadedModel {
+            path:       "model_1-v1.json".to_string(),
+            timestamp:  2.0,
+            modified:   None,
+            flat_model: FlatModel::new(&model, 0).unwrap(),
+            model:      model,
+        };
+        ServedModel {
+            model:        Arc::new(model),
+            reload_error: None,
+        }
+    }
+
+    fn request(raw: &str) -> (u16, Value) {
+        request_served(raw, &get_model())
+    }
+
+    fn request_served(raw: &str, served: &ServedModel) -> (u16, Value) {
+        let request = read_request(&mut Cursor::new(raw.as_bytes())).unwrap();
+        let (status, body) = handle_request(&request, served, &get_bins());
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    fn post(path: &str, content_type: &str, body: &str) -> (u16, Value) {
+        request(&format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\n\
+                          Content-Length: {}\r\n\r\n{}", path, content_type, body.len(), body))
+    }
+
+    #[test]
+    fn test_predict_json() {
+        // the examples go to the leaves with the predictions -0.25, 0.5, and 0.25
+        let body = "{\"instances\": [[0.0, 0.0, 5.0], [0.0, 2.5, 0.0], [0.0, 1.0, 0.5]], \
+                    \"leaves\": true}";
+        let (status, response) = post("/predict", "application/json", body);
+        assert_eq!(status, 200);
+        assert_eq!(response["num_trees"], 1);
+        assert_eq!(response["scores"], json_array(&[-0.25, 0.5, 0.25]));
+        assert_eq!(response["leaves"][1][0], 2);
+        assert!(response.get("contributions").is_none());
+        assert!(response.get("probabilities").is_none());
+    }
+
+    #[test]
+    fn test_predict_libsvm() {
+        // the labels are optional, and `nan` is parsed as 0 as in the training data
+        let body = "1 1:2.5\n2:5.0 1:nan\n\n";
+        let (status, response) = post("/predict?contributions=true", "text/plain", body);
+        assert_eq!(status, 200);
+        assert_eq!(response["scores"], json_array(&[0.5, -0.25]));
+        assert_eq!(response["contributions"][0], json_array(&[0.0, 0.5, 0.0, 0.0]));
+        assert!(response.get("leaves").is_none());
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let (status, response) = post("/predict", "application/json", "{\"instances\": [[0.0]]}");
+        assert_eq!(status, 400);
+        assert!(response["error"].as_str().unwrap().contains("expected 3"));
+        assert_eq!(post("/predict", "text/plain", "1 7:1.0").0, 400);
+        assert_eq!(post("/predict", "application/json", "[").0, 400);
+        assert_eq!(request("GET /unknown HTTP/1.1\r\n\r\n").0, 404);
+        let (status, response) = request("GET /model HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 200);
+        assert_eq!(response["model"], "model_1-v1.json");
+        assert!(response.get("reload_error").is_none());
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let read = |raw: String| read_request(&mut Cursor::new(raw.into_bytes()));
+        assert!(read(format!("GET /model HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(100))).is_ok());
+        // the lines are bounded, even without the line breaks
+        assert!(read(format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_SIZE))).is_err());
+        assert!(read(format!("GET /model HTTP/1.1\r\nX: {}", "a".repeat(MAX_LINE_SIZE))).is_err());
+        let headers = "X: a\r\n".repeat(MAX_NUM_HEADERS + 1);
+        assert!(read(format!("GET /model HTTP/1.1\r\n{}\r\n", headers)).is_err());
+        // the body is shorter than the content length
+        assert!(read("POST /predict HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}".to_string())
+                .is_err());
+    }
+
+    #[test]
+    fn test_stale_model() {
+        let mut served = get_model();
+        served.reload_error = Some("Cannot load the model `model_2-v2.json`".to_string());
+        let (status, response) = request_served("GET /model HTTP/1.1\r\n\r\n", &served);
+        assert_eq!(status, 200);
+        assert_eq!(response["model"], "model_1-v1.json");
+        assert_eq!(response["reload_error"], "Cannot load the model `model_2-v2.json`");
+    }
+
+    fn json_array(vals: &[f32]) -> Value {
+        serde_json::to_value(vals).unwrap()
+    }
+}
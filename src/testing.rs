@@ -1,28 +1,209 @@
 use rayon::prelude::*;
 use serde_json;
-use metricslib::validate as mvalidate;
-use metricslib::EvalFunc;
 
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fs::read_dir;
 use std::io::BufRead;
 use std::io::Write;
+use commons::bins::Bins;
 use commons::bins::load_bins;
+use commons::calibration::CalibrationMethod;
+use commons::calibration::Calibrator;
+use commons::inference::FlatModel;
 use commons::io::create_bufreader;
 use commons::io::create_bufwriter;
-use commons::io::raw_read_all;
 use commons::io::write_all;
 use commons::model::Model;
+use commons::persistent_io::read_model_with_header;
+use commons::persistent_io::write_model_to_file;
 use head::sampler::stratified_storage::serial_storage::SerialStorage;
+use metrics::Metric;
+use metrics::get_performance;
+use metrics::get_probability;
+use metrics::get_reliability_diagram;
+use Example;
 use TLabel;
 
+// The number of examples read from the file at a time when loading a data set into memory
+const LOAD_BATCH_SIZE: usize = 10000;
+
+
+/// The formats of the performance of the models
+pub enum PerformanceFormat {
+    /// one line for each model, with a header line
+    Csv,
+    /// one JSON object for each model
+    JsonLines,
+}
+
+impl PerformanceFormat {
+    pub fn new(format: &String) -> PerformanceFormat {
+        match format.to_lowercase().as_str() {
+            "csv"  => PerformanceFormat::Csv,
+            "json" => PerformanceFormat::JsonLines,
+            _      => {
+                error!("Unrecognized performance format. Use csv by default.");
+                PerformanceFormat::Csv
+            }
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+struct PerformanceRecord<'a> {
+    model:     &'a String,
+    timestamp: f32,
+    num_trees: usize,
+    metrics:   BTreeMap<String, f32>,
+}
+
+
+/// Load all examples of `filename` into memory
+pub fn load_examples(
+    filename: &String, num_examples: usize, num_features: usize, positive: &String,
+    bins: &Vec<Bins>,
+) -> Vec<Example> {
+    let mut data = SerialStorage::new(
+        filename.clone(),
+        num_examples,
+        num_features,
+        true,
+        positive.clone(),
+        Some(bins.clone()),
+    );
+    let mut examples = Vec::with_capacity(num_examples);
+    while examples.len() < num_examples {
+        let batch_size = min(LOAD_BATCH_SIZE, num_examples - examples.len());
+        examples.extend(data.read(batch_size));
+    }
+    examples
+}
+
+
+/// Fit a calibrator of `method` on the scores of the model persisted in `model_filename`
+/// on the examples in `validation_filename`, and write the calibrated model to
+/// `output_filename` in the same format
+pub fn calibrate(
+    model_filename: &String,
+    output_filename: &String,
+    method: &CalibrationMethod,
+    validation_filename: &String,
+    num_examples: usize,
+    num_features: usize,
+    positive: &String,
+) {
+    let bins = load_bins("testing", None);
+    let (ts, _, mut model, header) = read_model_with_header(model_filename);
+    if let Some(ref header) = header {
+        assert!(header.check(&bins),
+                "The model `{}` is trained on different bins, and cannot be calibrated",
+                model_filename);
+    }
+    let examples = load_examples(validation_filename, num_examples, num_features, positive, &bins);
+    let scores = model.predict_batch(&examples);
+    let labels: Vec<f32> = examples.iter().map(|example| example.label as f32).collect();
+    let calibrator = Calibrator::fit(method, &scores, &labels);
+    info!("Calibrated the model `{}` on {} examples, {:?}",
+          model_filename, examples.len(), calibrator);
+    model.calibrator = Some(calibrator);
+    write_model_to_file(output_filename, &model, ts, header.as_ref());
+}
+
+
+// The calibrated probabilities of the scores, or the probabilities `1 / (1 + exp(-2s))` if
+// the model is not calibrated
+fn get_probabilities(model: &Model, scores: &[f32]) -> Vec<f32> {
+    scores.iter().map(|score| {
+        model.get_probability(*score).unwrap_or(get_probability(*score))
+    }).collect()
+}
+
+
+/// Read the paths to the models to be validated from `models_table`, which has one line
+/// for each model
+pub fn read_models_table(models_table: &String) -> Vec<String> {
+    let mut models_list = create_bufreader(models_table);
+    let mut models = vec![];
+    loop {
+        let mut line = String::new();
+        if models_list.read_line(&mut line).is_err() || line.trim() == "" {
+            break;
+        }
+        models.push(line.trim().to_string());
+    }
+    models
+}
+
+
+/// Find the models persisted by the model manager in `model_dir`, i.e.
+/// `model_<size>-v<size>.json` or `model_<size>-v<size>.bin`, sorted by their sizes.
+/// If a model is persisted in both formats, only the binary one is returned.
+/// Returns `None` if the directory cannot be read.
+pub fn get_checkpoints(model_dir: &String) -> Option<Vec<String>> {
+    let entries = match read_dir(model_dir) {
+        Ok(entries) => entries,
+        Err(err)    => {
+            error!("Cannot read the directory `{}`, {}", model_dir, err);
+            return None;
+        },
+    };
+    // (size, is not binary, path)
+    let mut checkpoints: Vec<(usize, bool, String)> = entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        let size = get_checkpoint_size(path.file_name()?.to_str()?)?;
+        let is_binary = path.extension()? == "bin";
+        Some((size, !is_binary, path.to_str()?.to_string()))
+    }).collect();
+    checkpoints.sort();
+    checkpoints.dedup_by_key(|t| t.0);
+    debug!("Found {} checkpoints in `{}`", checkpoints.len(), model_dir);
+    Some(checkpoints.into_iter().map(|(_, _, path)| path).collect())
+}
+
+
+// Parse the size of the model from the file name `model_<size>-v<size>.<ext>`
+fn get_checkpoint_size(filename: &str) -> Option<usize> {
+    let (stem, ext) = {
+        let mut t = filename.rsplitn(2, '.');
+        let ext = t.next()?;
+        (t.next()?, ext)
+    };
+    if (ext != "json" && ext != "bin") || !stem.starts_with("model_") {
+        return None;
+    }
+    let mut sizes = stem["model_".len()..].splitn(2, "-v");
+    let size: usize = sizes.next()?.parse().ok()?;
+    let version: usize = sizes.next()?.parse().ok()?;
+    if size == version {
+        Some(size)
+    } else {
+        None
+    }
+}
+
 
 /// Validating a list of models
 ///
-/// The file `models_table` should have one line for each model to be validated.
-/// Each line contains two strings separated by a comma, where the first string
-/// is the path to the persisted model, and the second string is the path to print
-/// the scores.
+/// `models` is the list of the paths to the persisted models. The scores of the testing
+/// examples are written to the model path with the suffix `_scores` if `scores_only` is true.
+/// If `explain` is true, the contributions of the features to the scores are
+/// written to the file with the suffix `_contributions` as well.
+///
+/// If `incremental_testing` is true, each model must extend the previous model in `models`, so
+/// only the new trees are evaluated on the testing examples.
+///
+/// If `scores_only` is false, the performance of each model on `metrics` is written to
+/// `performance_filename` in `performance_format`, along with the model path, the timestamp,
+/// and the number of trees. If `reliability_diagram` is true as well, the reliability diagram
+/// of the probabilities is written to the file with the suffix `_reliability.csv`.
+///
+/// The probabilities are given by the calibrator of the model if it is calibrated, which are
+/// used by the log-loss, the calibration error, and the confusion matrix, and are also written
+/// to the file with the suffix `_probabilities` if `scores_only` is true.
 pub fn validate(
-    models_table: String,
+    models: Vec<String>,
     testing_filename: String,
     num_examples: usize,
     num_features: usize,
@@ -30,85 +211,145 @@ pub fn validate(
     positive: String,
     incremental_testing: bool,
     scores_only: bool,
+    explain: bool,
+    metrics: &Vec<Metric>,
+    performance_filename: &String,
+    performance_format: &PerformanceFormat,
+    reliability_diagram: bool,
 ) {
-    // TODO: make eval_funcs a parameter
-    let eval_funcs = vec![EvalFunc::AdaBoostLoss, EvalFunc::AUPRC, EvalFunc::AUROC, EvalFunc::ErrorRate];
+    let metric_names: Vec<String> = metrics.iter().flat_map(|metric| metric.get_names()).collect();
     let mut performance_out = {
         if scores_only {
             None
         } else {
-            Some(create_bufwriter(&"models/performance.csv".to_string()))
+            let mut out = create_bufwriter(performance_filename);
+            if let PerformanceFormat::Csv = performance_format {
+                let header = format!("model,timestamp,size,version,{}\n", metric_names.join(","));
+                out.write(header.as_bytes())
+                   .expect("Failed to write the performance scores to file.");
+            }
+            Some(out)
         }
     };
     let bins = load_bins("testing", None);
-    let mut models_list = create_bufreader(&models_table);
     let mut data = SerialStorage::new(
         testing_filename,
         num_examples,
         num_features,
         false,
         positive,
-        Some(bins),
+        Some(bins.clone()),
     );
     let mut scores = vec![0.0; num_examples];
     let mut labels: Vec<TLabel> = vec![0.0 as TLabel; num_examples];
     let mut last_model_length = 0;
-    loop {
-        let mut line = String::new();
-        if models_list.read_line(&mut line).is_err() || line.trim() == "" {
-            break;
-        }
-        let filepath = line.to_string().trim().to_string();
-        line.clear();
+    for filepath in models {
         // validate model
-        let (ts, _, model): (f32, usize, Model) =
-            serde_json::from_str(
-                &raw_read_all(&filepath).expect(&format!("Cannot read `{}`", filepath))
-            ).expect(&format!("Cannot parse the model in `{}`", filepath));
+        let (ts, _, model, header) = read_model_with_header(&filepath);
+        if let Some(header) = header {
+            // the scores are not changed, so the next model is validated as if this model
+            // were not in the list
+            if !header.check(&bins) {
+                error!("The model `{}` is trained on different bins, and is skipped", filepath);
+                continue;
+            }
+        }
+        let mut contributions_out = {
+            if scores_only && explain {
+                Some(create_bufwriter(&(filepath.clone() + "_contributions")))
+            } else {
+                None
+            }
+        };
+        let flat_model = FlatModel::new(&model, last_model_length);
+        if let Err(ref err) = flat_model {
+            warn!("{}, the trees are evaluated one by one", err);
+        }
         let mut index = 0;
         while index < num_examples {
             let batch = data.read(batch_size);
             let end = std::cmp::min(index + batch.len(), num_examples);
-            batch.par_iter()
-                 .zip(scores[index..end].par_iter_mut())
-                 .for_each(|(example, score)| {
-                     *score += model.get_prediction(example, last_model_length).0;
-                 });
+            let new_scores = match flat_model {
+                Ok(ref flat_model) => flat_model.predict(&batch[0..(end - index)]),
+                Err(_)             => model.get_predictions(&batch[0..(end - index)],
+                                                            last_model_length),
+            };
+            scores[index..end].iter_mut()
+                              .zip(new_scores.iter())
+                              .for_each(|(score, new_score)| *score += new_score);
             batch.par_iter()
                     .zip(labels[index..end].par_iter_mut())
                     .for_each(|(example, label)| {
                         *label = example.label;
                     });
+            if let Some(out) = contributions_out.as_mut() {
+                let lines: Vec<String> = batch[0..(end - index)].par_iter().map(|example| {
+                    let contributions: Vec<String> =
+                        model.explain(example).iter().map(|t| t.to_string()).collect();
+                    contributions.join(",") + "\n"
+                }).collect();
+                out.write(lines.concat().as_bytes()).expect(
+                    &format!("Cannot write the contributions of the model `{}`", filepath));
+            }
             index += batch.len();
         }
 
         // output
         match performance_out.as_mut() {
             Some(out) => {
-                let sorted_scores_labels = {
-                    let mut scores_labels: Vec<(f32, f32)> =
-                        scores.iter().zip(labels.iter())
-                              .map(|(a, b)| (*a as f32, *b as f32)).collect();
-                    scores_labels.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().reverse());
-                    scores_labels
+                let probs = model.calibrator.as_ref().map(|_| get_probabilities(&model, &scores));
+                let performance_scores = get_performance(
+                    &scores, probs.as_ref().map(|t| t.as_slice()), &labels, metrics);
+                let output = match performance_format {
+                    PerformanceFormat::Csv       => {
+                        let meta_info = vec![
+                            filepath.clone(), ts.to_string(), model.size().to_string(),
+                            model.size().to_string()];
+                        let performance_scores: Vec<String> =
+                            performance_scores.iter().map(|t| t.to_string()).collect();
+                        format!("{},{}\n", meta_info.join(","), performance_scores.join(","))
+                    },
+                    PerformanceFormat::JsonLines => {
+                        let record = PerformanceRecord {
+                            model:     &filepath,
+                            timestamp: ts,
+                            num_trees: model.size(),
+                            metrics:   metric_names.iter().cloned()
+                                                   .zip(performance_scores.into_iter())
+                                                   .collect(),
+                        };
+                        serde_json::to_string(&record).unwrap() + "\n"
+                    },
                 };
-                let performance_scores: Vec<String> =
-                    mvalidate(&sorted_scores_labels, &eval_funcs).iter()
-                                                                 .map(|t| t.to_string())
-                                                                 .collect();
-                let meta_info = vec![
-                    filepath.clone(), ts.to_string(), model.size().to_string(),
-                    model.size().to_string()];
-                let output = format!("{},{}\n", meta_info.join(","), performance_scores.join(","));
                 out.write(output.as_bytes())
                    .expect("Failed to write the performance scores to file.");
                 info!("{}", output.trim());
+                if reliability_diagram {
+                    let probs = get_probabilities(&model, &scores);
+                    let lines: Vec<String> =
+                        get_reliability_diagram(&probs, &labels).iter().map(|t| {
+                            format!("{},{},{},{},{}", t.0, t.1, t.2, t.3, t.4)
+                        }).collect();
+                    let output = format!(
+                        "lower,upper,count,mean_probability,fraction_positive\n{}\n",
+                        lines.join("\n"));
+                    write_all(&(filepath.clone() + "_reliability.csv"), output.as_bytes())
+                        .expect(&format!("Cannot write the reliability diagram of the model `{}`",
+                                         filepath));
+                }
             },
             None => {
                 let outputpath = filepath.clone() + "_scores";
                 let preds: Vec<String> = scores.iter().map(|t| t.to_string()).collect();
                 write_all(&outputpath, &preds.join("\n").as_bytes()).expect(
                     &format!("Cannot write the predictions of the model `{}`", filepath));
+                if model.calibrator.is_some() {
+                    let outputpath = filepath.clone() + "_probabilities";
+                    let probs: Vec<String> =
+                        get_probabilities(&model, &scores).iter().map(|t| t.to_string()).collect();
+                    write_all(&outputpath, &probs.join("\n").as_bytes()).expect(
+                        &format!("Cannot write the probabilities of the model `{}`", filepath));
+                }
                 info!("Processed {}", filepath);
             },
         }
@@ -123,3 +364,45 @@ pub fn validate(
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+
+    use commons::io::write_all;
+    use super::get_checkpoint_size;
+    use super::get_checkpoints;
+
+    #[test]
+    fn test_checkpoint_size() {
+        assert_eq!(get_checkpoint_size("model_12-v12.json"), Some(12));
+        assert_eq!(get_checkpoint_size("model_3-v3.bin"), Some(3));
+        assert_eq!(get_checkpoint_size("model_3-v4.json"), None);
+        assert_eq!(get_checkpoint_size("model_3-v3.json_scores"), None);
+        assert_eq!(get_checkpoint_size("model.json"), None);
+        assert_eq!(get_checkpoint_size("bins.json"), None);
+    }
+
+    #[test]
+    fn test_get_checkpoints() {
+        let model_dir = env::temp_dir().join("sparrow_test_get_checkpoints");
+        create_dir_all(&model_dir).unwrap();
+        let filenames = vec!["model_10-v10.json", "model_2-v2.json", "model_1-v1.bin",
+                             "model_2-v2.json_scores", "performance.csv",
+                             "model_3-v3.json", "model_3-v3.bin"];
+        filenames.iter().for_each(|filename| {
+            write_all(&model_dir.join(filename).to_str().unwrap().to_string(), b"").unwrap();
+        });
+        let checkpoints = get_checkpoints(&model_dir.to_str().unwrap().to_string()).unwrap();
+        // the binary model is kept if a model is persisted in both formats
+        let expected: Vec<String> = vec![
+            "model_1-v1.bin", "model_2-v2.json", "model_3-v3.bin", "model_10-v10.json",
+        ].iter().map(|t| model_dir.join(t).to_str().unwrap().to_string()).collect();
+        assert_eq!(checkpoints, expected);
+        remove_dir_all(&model_dir).unwrap();
+        assert_eq!(get_checkpoints(&model_dir.to_str().unwrap().to_string()), None);
+    }
+}
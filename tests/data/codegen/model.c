@@ -0,0 +1,30 @@
+/* Generated by sparrow. Do not edit. */
+
+#include <math.h>
+
+static float sparrow_tree_0(const float* x) {
+    if (x[4] < 10.0f) {
+        if (x[1] < 2.5f) {
+            return -0.20000002f;
+        } else {
+            return -0.5f;
+        }
+    } else {
+        return 0.35000002f;
+    }
+}
+
+static float sparrow_tree_1(const float* x) {
+    if (x[2] < 5.0f) {
+        return 0.25f;
+    } else {
+        return -0.125f;
+    }
+}
+
+float sparrow_predict(const float* x) {
+    float score = 0.0f;
+    score += sparrow_tree_0(x);
+    score += sparrow_tree_1(x);
+    return score;
+}
@@ -0,0 +1,28 @@
+// Generated by sparrow. Do not edit.
+
+fn sparrow_tree_0(x: &[f32]) -> f32 {
+    if x[4] < 10.0 {
+        if x[1] < 2.5 {
+            -0.20000002
+        } else {
+            -0.5
+        }
+    } else {
+        0.35000002
+    }
+}
+
+fn sparrow_tree_1(x: &[f32]) -> f32 {
+    if x[2] < 5.0 {
+        0.25
+    } else {
+        -0.125
+    }
+}
+
+pub fn sparrow_predict(x: &[f32]) -> f32 {
+    let mut score = 0.0;
+    score += sparrow_tree_0(x);
+    score += sparrow_tree_1(x);
+    score
+}
@@ -0,0 +1,38 @@
+#!/usr/bin/env python3
+# Generate the outputs of onnxruntime on the exported models, which are compared with the
+# predictions of sparrow in `src/export/onnx.rs`.
+#
+# The models and the inputs are written by running the tests with `SPARROW_UPDATE_GOLDEN` set,
+#
+#     SPARROW_UPDATE_GOLDEN=1 cargo test test_onnx_golden_models
+#     pip install onnxruntime numpy
+#     python3 tests/data/onnx/make_golden.py
+#     cargo test test_onnx_runtime_golden -- --ignored
+import json
+import os
+
+import numpy as np
+import onnxruntime
+
+
+DIR = os.path.dirname(os.path.abspath(__file__))
+
+
+def run(name, output, inputs):
+    session = onnxruntime.InferenceSession(os.path.join(DIR, name))
+    return session.run([output], {"X": inputs})[0]
+
+
+def main():
+    with open(os.path.join(DIR, "inputs.json")) as f:
+        inputs = np.array(json.load(f), dtype=np.float32)
+    outputs = {
+        "regressor": run("regressor.onnx", "variable", inputs)[:, 0].tolist(),
+        "classifier": run("classifier.onnx", "probabilities", inputs).tolist(),
+    }
+    with open(os.path.join(DIR, "outputs.json"), "w") as f:
+        json.dump(outputs, f)
+
+
+if __name__ == "__main__":
+    main()